@@ -0,0 +1,265 @@
+// Non-interactive subcommands for scripts: each opens its own connection, prints plain lines
+// and exits with a non-zero status when the hub reports a failure
+use std::time::Duration;
+use chrono::{Local, Utc};
+use clap::Args;
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use pozor_dom_shared::config::Config;
+use pozor_dom_shared::dashboard::{DeviceSummary, HubState};
+use pozor_dom_shared::envelope::{CommandStatus, DeviceCommand, Envelope, Payload};
+use pozor_dom_shared::tls::ClientStream;
+use pozor_dom_shared::{connection, messages, tls};
+
+type CliResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Args)]
+pub struct SendArgs {
+    #[arg(required_unless_present = "scene", help = "Device to command")]
+    pub device_id: Option<String>,
+    #[arg(required_unless_present = "scene", help = "Action, e.g. turn_on or set_brightness 40")]
+    pub action: Option<String>,
+    #[arg(long, default_value = "", help = "Device channel, shown in the hub's logs")]
+    pub channel: String,
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["device_id", "action"], help = "Activate a scene instead of commanding a device")]
+    pub scene: Option<String>,
+    #[arg(long, value_name = "SECS", default_value_t = 15, help = "Give up when the hub has not answered by then")]
+    pub timeout: u64,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    #[arg(long, help = "Print frames as JSON, one per line")]
+    pub json: bool,
+    #[arg(long = "type", value_name = "TYPE", help = "Only frames of this type (telemetry, presence, command_ack, alert, ...); may be repeated")]
+    pub types: Vec<String>,
+    #[arg(long, value_name = "N", help = "Exit after N frames")]
+    pub count: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct DevicesArgs {
+    #[arg(long, value_name = "SECS", default_value_t = 5, help = "How long to listen for telemetry and presence")]
+    pub wait: u64,
+    #[arg(long, help = "Print JSON instead of a table")]
+    pub json: bool,
+}
+
+// What `send` waits for
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Command(String),
+    Scene(String),
+}
+
+// The final answer to `target` carried by `envelope`, if any: Ok for success, Err for failure
+fn outcome(target: &Target, envelope: &Envelope) -> Option<Result<String, String>> {
+    match (&envelope.payload, target) {
+        (Payload::CommandAck(ack), Target::Command(command_id)) if ack.command_id == *command_id => match ack.status {
+            CommandStatus::Pending => None,
+            CommandStatus::Succeeded => Some(Ok(envelope.to_string())),
+            CommandStatus::Failed | CommandStatus::TimedOut => Some(Err(envelope.to_string())),
+        },
+        (Payload::SceneResult(result), Target::Scene(name)) if result.name == *name => {
+            Some(if result.success { Ok(envelope.to_string()) } else { Err(envelope.to_string()) })
+        }
+        // Refusals and errors only go to the connection that sent the request
+        (Payload::PermissionDenied(denied), Target::Command(command_id)) if denied.command_id == *command_id => {
+            Some(Err(envelope.to_string()))
+        }
+        (Payload::PermissionDenied(_), Target::Scene(_)) | (Payload::Error { .. }, _) => Some(Err(envelope.to_string())),
+        _ => None,
+    }
+}
+
+async fn connect(server_url: &str, config: &Config) -> Result<ClientStream, Box<dyn std::error::Error + Send + Sync>> {
+    let request = connection::authorized_request(server_url, config.client.token.as_deref())?;
+    let (ws_stream, _) = tls::connect(request, config.tls.ca.as_deref())
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", server_url, e))?;
+    Ok(ws_stream)
+}
+
+pub async fn send(server_url: &str, config: &Config, args: SendArgs) -> CliResult {
+    let (request, target) = match args.scene {
+        Some(name) => (Envelope::activate_scene(&name), Target::Scene(name)),
+        None => {
+            // Our own correlation id, so the acks for this command can be told apart from everyone else's
+            let command = DeviceCommand {
+                command_id: format!("client-{}-{}", Utc::now().timestamp_millis(), std::process::id()),
+                device_id: args.device_id.unwrap_or_default(),
+                channel: args.channel,
+                action: args.action.unwrap_or_default(),
+                timestamp: Local::now().to_rfc3339(),
+            };
+            let target = Target::Command(command.command_id.clone());
+            (Envelope::command(command), target)
+        }
+    };
+
+    let mut ws_stream = connect(server_url, config).await?;
+    ws_stream.send(messages::to_ws_message(&request)).await?;
+
+    let deadline = tokio::time::sleep(Duration::from_secs(args.timeout));
+    tokio::pin!(deadline);
+    let result = loop {
+        tokio::select! {
+            _ = &mut deadline => break Err(format!("No answer from the hub within {}s", args.timeout)),
+            message = ws_stream.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let Ok(envelope) = messages::parse_envelope(&text) else {
+                        continue;
+                    };
+                    if let Some(result) = outcome(&target, &envelope) {
+                        break result;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break Err("Connection closed by server".to_string()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => break Err(format!("WebSocket error: {}", e)),
+            }
+        }
+    };
+    let _ = ws_stream.close(None).await;
+
+    println!("{}", result?);
+    Ok(())
+}
+
+pub async fn watch(server_url: &str, config: &Config, args: WatchArgs) -> CliResult {
+    let mut ws_stream = connect(server_url, config).await?;
+    let mut printed = 0;
+    while args.count.is_none_or(|count| printed < count) {
+        let text = tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            message = ws_stream.next() => match message {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Close(_))) | None => return Err("Connection closed by server".into()),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(format!("WebSocket error: {}", e).into()),
+            }
+        };
+
+        let frame_type = serde_json::from_str::<serde_json::Value>(&text)
+            .ok()
+            .and_then(|value| value.get("type").and_then(|kind| kind.as_str()).map(str::to_string))
+            .unwrap_or_default();
+        if !args.types.is_empty() && !args.types.contains(&frame_type) {
+            continue;
+        }
+        if args.json {
+            println!("{}", text);
+        } else {
+            let line = messages::parse_envelope(&text).map(|envelope| envelope.to_string()).unwrap_or_else(|_| text.to_string());
+            println!("[{}] {}", Local::now().format("%H:%M:%S"), line);
+        }
+        printed += 1;
+    }
+    let _ = ws_stream.close(None).await;
+    Ok(())
+}
+
+// Devices heard from while listening; the hub has no snapshot request, so quiet devices are missed
+pub async fn devices(server_url: &str, config: &Config, args: DevicesArgs) -> CliResult {
+    let mut ws_stream = connect(server_url, config).await?;
+    let mut state = HubState::new("Client");
+    let deadline = tokio::time::sleep(Duration::from_secs(args.wait));
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            message = ws_stream.next() => match message {
+                Some(Ok(Message::Text(text))) => match messages::parse_envelope(&text).map(|envelope| envelope.payload) {
+                    Ok(Payload::Telemetry(telemetry)) => {
+                        state.record_telemetry(telemetry, Utc::now());
+                    }
+                    Ok(Payload::Presence(presence)) => {
+                        state.set_presence(&presence.device_id, presence.status, presence.last_seen);
+                    }
+                    _ => {}
+                },
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(format!("WebSocket error: {}", e).into()),
+            }
+        }
+    }
+    let _ = ws_stream.close(None).await;
+
+    let mut devices: Vec<DeviceSummary> = state.device_summaries();
+    devices.sort_by(|a, b| a.telemetry.device_id.cmp(&b.telemetry.device_id));
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
+    if devices.is_empty() {
+        println!("No devices reported within {}s", args.wait);
+        return Ok(());
+    }
+
+    let id_width = devices.iter().map(|device| device.telemetry.device_id.len()).max().unwrap_or(0).max("DEVICE".len());
+    println!("{:<id_width$}  {:<10}  {:<8}  {:<7}  STATE", "DEVICE", "TYPE", "CHANNEL", "STATUS");
+    for device in devices {
+        let telemetry = &device.telemetry;
+        let states: Vec<String> = telemetry
+            .capabilities
+            .iter()
+            .map(|(capability, value)| format!("{}={}", capability.as_str(), capability.format_value(value)))
+            .collect();
+        println!(
+            "{:<id_width$}  {:<10}  {:<8}  {:<7}  {}",
+            telemetry.device_id,
+            telemetry.device_type.as_str(),
+            telemetry.channel,
+            device.status.as_str(),
+            states.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pozor_dom_shared::envelope::{CommandAck, PermissionDenied, SceneResult};
+
+    fn ack(command_id: &str, status: CommandStatus) -> Envelope {
+        Envelope::new(Payload::CommandAck(CommandAck {
+            command_id: command_id.to_string(),
+            device_id: "light-001".to_string(),
+            action: "turn_on".to_string(),
+            status,
+            detail: None,
+        }))
+    }
+
+    #[test]
+    fn test_command_outcome() {
+        let target = Target::Command("client-1".to_string());
+        assert_eq!(outcome(&target, &ack("client-1", CommandStatus::Pending)), None);
+        assert_eq!(outcome(&target, &ack("cmd-other", CommandStatus::Succeeded)), None);
+        assert!(matches!(outcome(&target, &ack("client-1", CommandStatus::Succeeded)), Some(Ok(_))));
+        assert!(matches!(outcome(&target, &ack("client-1", CommandStatus::TimedOut)), Some(Err(_))));
+
+        let denied = Envelope::new(Payload::PermissionDenied(PermissionDenied {
+            command_id: "client-1".to_string(),
+            username: "kid".to_string(),
+            device_id: "lock-front-001".to_string(),
+            action: "unlock".to_string(),
+        }));
+        assert!(matches!(outcome(&target, &denied), Some(Err(_))));
+        assert!(matches!(outcome(&target, &Envelope::error("Requires the operator role")), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_scene_outcome() {
+        let target = Target::Scene("night-mode".to_string());
+        let result = |name: &str, success: bool| {
+            Envelope::new(Payload::SceneResult(SceneResult { name: name.to_string(), success, outcomes: Vec::new() }))
+        };
+        assert_eq!(outcome(&target, &result("morning", true)), None);
+        assert_eq!(outcome(&target, &ack("cmd-1", CommandStatus::Succeeded)), None);
+        assert!(matches!(outcome(&target, &result("night-mode", true)), Some(Ok(_))));
+        assert!(matches!(outcome(&target, &result("night-mode", false)), Some(Err(_))));
+    }
+}
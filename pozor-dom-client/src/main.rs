@@ -1,23 +1,108 @@
+mod commands;
 mod tui;
 mod websocket;
 
-use std::env;
+use clap::{Args, Parser, Subcommand};
+use pozor_dom_shared::config::{self, ConfigArgs};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+#[derive(Parser)]
+#[command(
+    name = "pozor-dom-client",
+    version,
+    about = "Pozor-dom terminal client: an interactive TUI, or one-shot commands for scripts",
+    after_help = "Examples:\n  pozor-dom-client ws://localhost:8081/?hub=home\n  pozor-dom-client ws://localhost:8082/?token=<session token>\n  pozor-dom-client send light-001 turn_on\n  pozor-dom-client watch --type telemetry --json\n\nSet POZOR_DOM_USER_TOKEN (client.token) to your user token when connecting to the cloud relay.\nSet POZOR_DOM_TLS_CA (tls.ca) to a CA certificate to trust a self-signed wss:// server."
+)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    // `pozor-dom-client <url>` without a subcommand opens the TUI
+    #[arg(value_name = "URL", help = "Cloud relay or hub WebSocket URL (default: client.server_url)")]
+    url: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Args)]
+struct ServerArgs {
+    #[arg(long, value_name = "URL", help = "Cloud relay or hub WebSocket URL (default: client.server_url)")]
+    url: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Send a device command or activate a scene, and wait for its final status")]
+    Send {
+        #[command(flatten)]
+        server: ServerArgs,
+        #[command(flatten)]
+        args: commands::SendArgs,
+    },
+    #[command(about = "Print frames from the hub as they arrive")]
+    Watch {
+        #[command(flatten)]
+        server: ServerArgs,
+        #[command(flatten)]
+        args: commands::WatchArgs,
+    },
+    #[command(about = "List the devices that report within a few seconds")]
+    Devices {
+        #[command(flatten)]
+        server: ServerArgs,
+        #[command(flatten)]
+        args: commands::DevicesArgs,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
+    let cli = Cli::parse();
+    let config = config::load_or_exit(&cli.config);
+    let server_url = |url: Option<String>| url.unwrap_or_else(|| config.client.server_url.clone());
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <server_url>", args[0]);
-        eprintln!("Example: {} ws://localhost:8081", args[0]);
-        eprintln!("Example: {} ws://localhost:8082", args[0]);
+    let result = match cli.command {
+        None => {
+            let server_url = server_url(cli.url);
+            let app_state = Arc::new(Mutex::new(tui::AppState::new(server_url.clone())));
+            return websocket::connect_and_run(server_url, config, app_state).await;
+        }
+        Some(Command::Send { server, args }) => commands::send(&server_url(server.url), &config, args).await,
+        Some(Command::Watch { server, args }) => commands::watch(&server_url(server.url), &config, args).await,
+        Some(Command::Devices { server, args }) => commands::devices(&server_url(server.url), &config, args).await,
+    };
+    if let Err(e) = result {
+        eprintln!("❌ {}", e);
         std::process::exit(1);
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        let cli = Cli::try_parse_from(["pozor-dom-client", "ws://localhost:8081/?hub=home"]).unwrap();
+        assert_eq!(cli.url.as_deref(), Some("ws://localhost:8081/?hub=home"));
+        assert!(cli.command.is_none());
 
-    let server_url = args[1].clone();
-    let app_state = Arc::new(Mutex::new(tui::AppState::new(server_url.clone())));
+        let cli = Cli::try_parse_from(["pozor-dom-client", "send", "--url", "ws://localhost:8082", "light-001", "turn_on"]).unwrap();
+        let Some(Command::Send { server, args }) = cli.command else {
+            panic!("expected send");
+        };
+        assert_eq!(server.url.as_deref(), Some("ws://localhost:8082"));
+        assert_eq!((args.device_id.as_deref(), args.action.as_deref()), (Some("light-001"), Some("turn_on")));
 
-    websocket::connect_and_run(server_url, app_state).await
+        assert!(Cli::try_parse_from(["pozor-dom-client", "send", "--scene", "night-mode"]).is_ok());
+        assert!(Cli::try_parse_from(["pozor-dom-client", "send", "light-001"]).is_err());
+        assert!(Cli::try_parse_from(["pozor-dom-client", "send", "light-001", "turn_on", "--scene", "night-mode"]).is_err());
+    }
 }
@@ -2,7 +2,8 @@ use futures_util::SinkExt;
 use tokio_tungstenite::tungstenite::protocol::Message;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use serde_json::json;
+use pozor_dom_shared::envelope::{DeviceCommand, Envelope};
+use pozor_dom_shared::messages;
 use chrono::Local;
 use std::io;
 use ratatui::{
@@ -19,6 +20,8 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
+pub const CLIENT_COMPONENT: &str = "Client";
+
 #[derive(Clone)]
 pub struct AppState {
     pub messages: Vec<String>,
@@ -138,26 +141,34 @@ async fn run_app<B: ratatui::backend::Backend>(
                                     let device_id = state.device_id.as_ref().unwrap().clone();
                                     let channel = state.channel.as_ref().unwrap().clone();
 
-                                    let command = json!({
-                                        "type": "command",
-                                        "device_id": device_id,
-                                        "channel": channel,
-                                        "action": action,
-                                        "timestamp": Local::now().to_rfc3339()
+                                    let command = Envelope::command(DeviceCommand {
+                                        command_id: String::new(),
+                                        device_id: device_id.clone(),
+                                        channel: channel.clone(),
+                                        action: action.to_string(),
+                                        timestamp: Local::now().to_rfc3339(),
                                     });
 
                                     state.add_message(format!("Sending command: {} to {} ({})", action, device_id, channel));
 
-                                    if let Err(e) = ws_write.send(Message::Text(command.to_string().into())).await {
+                                    if let Err(e) = ws_write.send(messages::to_ws_message(&command)).await {
                                         state.add_message(format!("Failed to send command: {}", e));
                                     }
                                 } else {
                                     state.add_message("Error: Set device first with /device <id> <channel>. Use: wifi, ble, zigbee".to_string());
                                 }
+                            } else if input.starts_with("/scene ") {
+                                let name = input.strip_prefix("/scene ").unwrap_or("").trim();
+                                state.add_message(format!("Activating scene: {}", name));
+                                let activate = Envelope::activate_scene(name);
+                                if let Err(e) = ws_write.send(messages::to_ws_message(&activate)).await {
+                                    state.add_message(format!("Failed to activate scene: {}", e));
+                                }
                             } else {
                                 // Send as regular message
                                 state.add_message(format!("Sending: {}", input));
-                                if let Err(e) = ws_write.send(Message::Text(input.into())).await {
+                                let chat = Envelope::chat(CLIENT_COMPONENT, &input);
+                                if let Err(e) = ws_write.send(messages::to_ws_message(&chat)).await {
                                     state.add_message(format!("Failed to send message: {}", e));
                                 }
                             }
@@ -234,8 +245,8 @@ fn ui(f: &mut Frame, state: &AppState) {
         .split(size);
 
     // Create owned strings to avoid temporary value issues
-    let device_display = state.device_id.as_ref().map(|s| s.as_str()).unwrap_or("None");
-    let channel_display = state.channel.as_ref().map(|s| s.as_str()).unwrap_or("None");
+    let device_display = state.device_id.as_deref().unwrap_or("None");
+    let channel_display = state.channel.as_deref().unwrap_or("None");
 
     // Header with more information
     let header = Paragraph::new(vec![
@@ -285,7 +296,7 @@ fn ui(f: &mut Frame, state: &AppState) {
     f.render_widget(input, chunks[2]);
 
     // Help
-    let help = Paragraph::new("Commands: /device <id> <channel> | /send <action> | ESC to quit")
+    let help = Paragraph::new("Commands: /device <id> <channel> | /send <action> | /scene <name> | ESC to quit")
         .block(Block::default().borders(Borders::ALL).title("Help"))
         .wrap(Wrap { trim: true });
     f.render_widget(help, chunks[3]);
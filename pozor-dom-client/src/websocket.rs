@@ -1,15 +1,20 @@
 use futures_util::{SinkExt, StreamExt};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tokio_tungstenite::tungstenite::protocol::Message;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use pozor_dom_shared::messages;
+use pozor_dom_shared::config::Config;
+use pozor_dom_shared::{connection, messages, tls};
 use crate::tui::AppState;
 
 pub async fn connect_and_run(
     server_url: String,
+    config: Config,
     app_state: Arc<Mutex<AppState>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    match connect_async(&server_url).await {
+    // The cloud relay only accepts clients with a user token; the hub takes a session token from POST /api/login
+    let request = connection::authorized_request(&server_url, config.client.token.as_deref())
+        .map_err(|e| e.to_string())?;
+    match tls::connect(request, config.tls.ca.as_deref()).await {
         Ok((ws_stream, _)) => {
             {
                 let mut state = app_state.lock().await;
@@ -20,7 +25,7 @@ pub async fn connect_and_run(
             let (mut write, mut read) = ws_stream.split();
 
             // Send hello message to server
-            let hello_msg = messages::create_welcome_message("Client");
+            let hello_msg = messages::create_welcome_message(crate::tui::CLIENT_COMPONENT);
             if let Err(e) = write.send(hello_msg).await {
                 eprintln!("Failed to send hello message: {}", e);
                 return Ok(());
@@ -35,7 +40,10 @@ pub async fn connect_and_run(
                     match message {
                         Ok(Message::Text(text)) => {
                             let mut state = app_state_ws.lock().await;
-                            state.add_message(format!("Received: {}", text));
+                            match messages::parse_envelope(&text) {
+                                Ok(envelope) => state.add_message(format!("Received: {}", envelope)),
+                                Err(_) => state.add_message(format!("Received unrecognized frame: {}", text)),
+                            }
                         }
                         Ok(Message::Close(_)) => {
                             let mut state = app_state_ws.lock().await;
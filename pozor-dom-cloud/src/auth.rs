@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fmt;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request};
+use tokio_tungstenite::tungstenite::http::{header, Response, StatusCode};
+use pozor_dom_shared::config::CloudConfig;
+
+// Who is on the other end of a relay connection, established by the handshake token
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerIdentity {
+    Hub(String),
+    User(String),
+}
+
+impl PeerIdentity {
+    pub fn is_hub(&self) -> bool {
+        matches!(self, PeerIdentity::Hub(_))
+    }
+}
+
+impl fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerIdentity::Hub(id) => write!(f, "hub:{}", id),
+            PeerIdentity::User(name) => write!(f, "user:{}", name),
+        }
+    }
+}
+
+// Hub id that lets a user subscribe to any hub
+const ANY_HUB: &str = "*";
+
+// Pre-shared hub tokens and per-user tokens accepted by the relay, and the hubs each user may subscribe to
+pub struct TokenStore {
+    hubs: Vec<(String, String)>,
+    users: Vec<(String, String)>,
+    user_hubs: HashMap<String, Vec<String>>,
+}
+
+// Compares every byte so that the time taken does not reveal how much of a token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl TokenStore {
+    pub fn new(hubs: Vec<(String, String)>, users: Vec<(String, String)>, user_hubs: Vec<(String, Vec<String>)>) -> Self {
+        Self { hubs, users, user_hubs: user_hubs.into_iter().collect() }
+    }
+
+    pub fn from_config(config: &CloudConfig) -> Self {
+        Self::new(
+            config.hub_tokens.clone().into_iter().collect(),
+            config.user_tokens.clone().into_iter().collect(),
+            config.user_hubs.clone().into_iter().collect(),
+        )
+    }
+
+    pub fn hub_count(&self) -> usize {
+        self.hubs.len()
+    }
+
+    pub fn user_count(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn authenticate(&self, token: &str) -> Option<PeerIdentity> {
+        let hubs = self.hubs.iter().map(|(id, expected)| (PeerIdentity::Hub(id.clone()), expected));
+        let users = self.users.iter().map(|(name, expected)| (PeerIdentity::User(name.clone()), expected));
+        hubs.chain(users)
+            .filter(|(_, expected)| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+            .map(|(identity, _)| identity)
+            .next()
+    }
+
+    // HTTP API callers present the same token as `Authorization: Bearer <token>`
+    pub fn authenticate_bearer(&self, authorization: Option<&str>) -> Option<PeerIdentity> {
+        self.authenticate(authorization?.strip_prefix("Bearer ")?.trim())
+    }
+
+    // The hub a peer's traffic is routed to: a hub is always its own, a user picks one they are allowed to see.
+    // Users with a single hub may leave the choice out.
+    pub fn authorize_hub(&self, identity: &PeerIdentity, requested: Option<&str>) -> Result<String, String> {
+        match identity {
+            PeerIdentity::Hub(id) => match requested {
+                Some(hub) if hub != id => Err(format!("hub {} cannot subscribe to hub {}", id, hub)),
+                _ => Ok(id.clone()),
+            },
+            PeerIdentity::User(name) => {
+                let allowed = self.user_hubs.get(name).map(Vec::as_slice).unwrap_or_default();
+                match (requested, allowed) {
+                    (Some(hub), _) if allowed.iter().any(|allowed| allowed == hub || allowed == ANY_HUB) => Ok(hub.to_string()),
+                    (Some(hub), _) => Err(format!("user {} is not allowed to access hub {}", name, hub)),
+                    (None, [only]) if only != ANY_HUB => Ok(only.clone()),
+                    (None, _) => Err("choose a hub with ?hub=<id>".to_string()),
+                }
+            }
+        }
+    }
+
+    // Handshake check: the peer identity and its hub, or the 401/403 response the connection is rejected with
+    #[allow(clippy::result_large_err)]
+    pub fn authorize_request(&self, request: &Request) -> Result<(PeerIdentity, String), ErrorResponse> {
+        let identity = request_token(request)
+            .and_then(|token| self.authenticate(&token))
+            .ok_or_else(|| error_response(StatusCode::UNAUTHORIZED, "missing or invalid token".to_string()))?;
+        let hub_id = self
+            .authorize_hub(&identity, requested_hub(request).as_deref())
+            .map_err(|reason| error_response(StatusCode::FORBIDDEN, reason))?;
+        Ok((identity, hub_id))
+    }
+}
+
+fn error_response(status: StatusCode, reason: String) -> ErrorResponse {
+    let mut response = Response::new(Some(reason));
+    *response.status_mut() = status;
+    response
+}
+
+fn query_param(request: &Request, name: &str) -> Option<String> {
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+// `Authorization: Bearer <token>`, or `?token=<token>` since browsers cannot set headers on WebSocket requests
+fn request_token(request: &Request) -> Option<String> {
+    if let Some(value) = request.headers().get(header::AUTHORIZATION) {
+        return value.to_str().ok()?.strip_prefix("Bearer ").map(|token| token.trim().to_string());
+    }
+    query_param(request, "token")
+}
+
+// `X-Pozor-Hub: <id>` or `?hub=<id>`
+fn requested_hub(request: &Request) -> Option<String> {
+    let hub = match request.headers().get("x-pozor-hub") {
+        Some(value) => value.to_str().ok()?.trim().to_string(),
+        None => query_param(request, "hub")?,
+    };
+    Some(hub).filter(|hub| !hub.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> TokenStore {
+        TokenStore::new(
+            vec![("home".to_string(), "hub-secret".to_string()), ("cottage".to_string(), "cottage-secret".to_string())],
+            vec![("alice".to_string(), "alice-secret".to_string()), ("admin".to_string(), "admin-secret".to_string())],
+            vec![("alice".to_string(), vec!["home".to_string()]), ("admin".to_string(), vec!["*".to_string()])],
+        )
+    }
+
+    fn request(uri: &str, authorization: Option<&str>) -> Request {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(value) = authorization {
+            builder = builder.header(header::AUTHORIZATION, value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_tokens_map_to_identities() {
+        let store = store();
+        assert_eq!(store.authenticate("hub-secret"), Some(PeerIdentity::Hub("home".to_string())));
+        assert_eq!(store.authenticate("alice-secret"), Some(PeerIdentity::User("alice".to_string())));
+        assert_eq!(store.authenticate("hub-secre"), None);
+        assert_eq!(store.authenticate(""), None);
+        assert_eq!(PeerIdentity::Hub("home".to_string()).to_string(), "hub:home");
+        assert_eq!(store.authenticate_bearer(Some("Bearer alice-secret")), Some(PeerIdentity::User("alice".to_string())));
+        assert_eq!(store.authenticate_bearer(Some("alice-secret")), None);
+        assert_eq!(store.authenticate_bearer(None), None);
+    }
+
+    #[test]
+    fn test_handshake_token_sources() {
+        let store = store();
+        let header = request("ws://cloud:8081/", Some("Bearer hub-secret"));
+        assert_eq!(store.authorize_request(&header).unwrap(), (PeerIdentity::Hub("home".to_string()), "home".to_string()));
+
+        let query = request("ws://cloud:8081/?lang=ru&token=alice-secret", None);
+        assert_eq!(store.authorize_request(&query).unwrap(), (PeerIdentity::User("alice".to_string()), "home".to_string()));
+
+        for rejected in [request("ws://cloud:8081/", None), request("ws://cloud:8081/", Some("Basic aHViLXNlY3JldA=="))] {
+            let response = store.authorize_request(&rejected).unwrap_err();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[test]
+    fn test_hub_subscription() {
+        let store = store();
+        let alice = PeerIdentity::User("alice".to_string());
+        let admin = PeerIdentity::User("admin".to_string());
+        assert_eq!(store.authorize_hub(&alice, Some("home")), Ok("home".to_string()));
+        assert!(store.authorize_hub(&alice, Some("cottage")).is_err());
+        assert_eq!(store.authorize_hub(&admin, Some("cottage")), Ok("cottage".to_string()));
+        assert!(store.authorize_hub(&admin, None).is_err());
+        assert!(store.authorize_hub(&PeerIdentity::User("mallory".to_string()), Some("home")).is_err());
+        assert!(store.authorize_hub(&PeerIdentity::Hub("home".to_string()), Some("cottage")).is_err());
+
+        let forbidden = request("ws://cloud:8081/?token=alice-secret&hub=cottage", None);
+        assert_eq!(store.authorize_request(&forbidden).unwrap_err().status(), StatusCode::FORBIDDEN);
+        let admin_request = request("ws://cloud:8081/?hub=cottage", Some("Bearer admin-secret"));
+        assert_eq!(store.authorize_request(&admin_request).unwrap().1, "cottage");
+    }
+}
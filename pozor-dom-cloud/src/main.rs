@@ -1,36 +1,116 @@
+mod auth;
+
 use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
-use tokio_tungstenite::accept_async;
+use tokio_tungstenite::accept_hdr_async;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
 use tokio_tungstenite::tungstenite::protocol::Message;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::{mpsc, Mutex};
+use clap::{Parser, Subcommand};
 use warp::Filter;
 use pozor_dom_shared::{config, connection, logging, messages, dashboard, PeerMap, Tx};
+use pozor_dom_shared::config::{Config, ConfigArgs};
+use pozor_dom_shared::envelope::Payload;
+use pozor_dom_shared::tls::TlsFiles;
+use auth::{PeerIdentity, TokenStore};
+
+// One authenticated relay connection; the same identity may be connected more than once.
+// Frames are only relayed between peers of the same hub.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Peer {
+    identity: PeerIdentity,
+    hub_id: String,
+    connection: u64,
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} #{}", self.identity, self.connection)
+    }
+}
+
+// Device state as relayed by each hub, keyed by hub id
+type HubStates = Arc<Mutex<HashMap<String, dashboard::HubState>>>;
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+// Frames only a hub may relay; users could otherwise forge device state on other clients and in the hub API
+fn is_hub_only(payload: &Payload) -> bool {
+    matches!(
+        payload,
+        Payload::Telemetry(_) | Payload::Presence(_) | Payload::CommandAck(_) | Payload::SceneResult(_) | Payload::Alert(_)
+    )
+}
+
+#[derive(Parser)]
+#[command(name = "pozor-dom-cloud", version, about = "Pozor-dom cloud relay and hub API")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Run the relay and the HTTP API (the default)")]
+    Serve,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cli = Cli::parse();
+    let config = config::load_or_exit(&cli.config);
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+    }
+}
+
+async fn serve(config: Config) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     println!("🚀 Pozор-дом Cloud starting...");
-    println!("WebSocket relay + Web Dashboard");
+    println!("WebSocket relay + HTTP API");
     println!("Press Ctrl+C to exit.\n");
 
-    let host = config::get_cloud_host();
-    let port = config::get_cloud_port();
-    let addr = format!("{}:{}", host, port);
+    let addr = format!("{}:{}", config.cloud.host, config.cloud.port);
     let listener = TcpListener::bind(&addr).await?;
-    println!("✅ Cloud WebSocket server listening on: {}", addr);
-    println!("🌐 Cloud dashboard: http://localhost:8080\n");
-    println!("Note: This is using plain WebSocket (ws://), not WSS yet.");
-    println!("For production WSS, you'll need to add TLS certificates.");
 
-    let peer_map = connection::create_peer_map();
-    let cloud_state = Arc::new(Mutex::new(dashboard::HubState::new("Cloud")));
+    // The relay and the HTTP API share one certificate
+    let tls_files = TlsFiles::from_config(&config.tls);
+    let tls_acceptor = tls_files.as_ref().map(TlsFiles::acceptor).transpose()?;
+    let dashboard_port = config.cloud.dashboard_port;
+    if tls_files.is_some() {
+        println!("✅ Cloud WebSocket server listening on: wss://{}", addr);
+        println!("🌐 Cloud HTTP API: https://localhost:{}\n", dashboard_port);
+    } else {
+        println!("✅ Cloud WebSocket server listening on: ws://{}", addr);
+        println!("🌐 Cloud HTTP API: http://localhost:{}\n", dashboard_port);
+        println!("⚠️  Plain WebSocket (ws://): set tls.cert and tls.key (POZOR_DOM_TLS_CERT, POZOR_DOM_TLS_KEY) to serve wss:// in production.");
+    }
+
+    let tokens = Arc::new(TokenStore::from_config(&config.cloud));
+    if tokens.hub_count() + tokens.user_count() == 0 {
+        println!("⚠️  No cloud.hub_tokens or cloud.user_tokens configured: every connection will be rejected");
+    } else {
+        println!("🔐 Accepting {} hub tokens and {} user tokens", tokens.hub_count(), tokens.user_count());
+    }
+
+    let peer_map: PeerMap<Peer> = connection::create_peer_map();
+    let hub_states: HubStates = Arc::new(Mutex::new(HashMap::new()));
     let mut rx = connection::setup_stdin_channel().await?;
 
-    // Start web dashboard server (proxying API calls to hub)
-    let cloud_state_web = Arc::clone(&cloud_state);
+    // Start the HTTP API (hub state per hub, proxying API calls to each hub's dashboard)
+    let hub_states_web = Arc::clone(&hub_states);
+    let peer_map_web = peer_map.clone();
+    let tokens_web = Arc::clone(&tokens);
     tokio::spawn(async move {
-        if let Err(e) = start_cloud_web_server(cloud_state_web).await {
+        if let Err(e) = start_cloud_web_server(hub_states_web, peer_map_web, tokens_web, config, tls_files).await {
             eprintln!("Cloud web server error: {}", e);
         }
     });
@@ -43,10 +123,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 match result {
                     Ok((stream, addr)) => {
                         let peer_map = peer_map.clone();
-                        let cloud_state = Arc::clone(&cloud_state);
+                        let hub_states = Arc::clone(&hub_states);
+                        let tokens = Arc::clone(&tokens);
+                        let tls_acceptor = tls_acceptor.clone();
                         tokio::spawn(async move {
                             println!("New connection from: {}", addr);
-                            handle_connection(peer_map, stream, addr, cloud_state).await;
+                            match tls_acceptor {
+                                Some(acceptor) => match acceptor.accept(stream).await {
+                                    Ok(stream) => handle_connection(peer_map, stream, addr, hub_states, tokens).await,
+                                    Err(e) => eprintln!("TLS handshake with {} failed: {}", addr, e),
+                                },
+                                None => handle_connection(peer_map, stream, addr, hub_states, tokens).await,
+                            }
                         });
                     }
                     Err(e) => {
@@ -60,11 +148,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 match input {
                     Some(text) => {
                         logging::log_broadcast(&text, "Cloud");
-                        // Broadcast the message to all connected peers
+                        // Operator announcements go to every connected peer, whatever their hub
                         let peers = peer_map.lock().unwrap().clone();
-                        for (addr, tx) in peers {
+                        for (peer, tx) in peers {
                             if let Err(e) = tx.send(messages::create_cloud_message(&text)) {
-                                eprintln!("Failed to send to {}: {}", addr, e);
+                                eprintln!("Failed to send to {}: {}", peer, e);
                             }
                         }
                     }
@@ -77,24 +165,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     Ok(())
 }
 
-async fn handle_connection(peer_map: PeerMap, raw_stream: tokio::net::TcpStream, addr: SocketAddr, cloud_state: Arc<Mutex<dashboard::HubState>>) {
-    match accept_async(raw_stream).await {
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    peer_map: PeerMap<Peer>,
+    raw_stream: S,
+    addr: SocketAddr,
+    hub_states: HubStates,
+    tokens: Arc<TokenStore>,
+) {
+    // Unauthenticated peers get a 401 and users asking for a hub they may not see a 403 during the handshake
+    let mut authorized = None;
+    // The handshake callback's error type is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    let authorize = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        authorized = Some(tokens.authorize_request(request)?);
+        Ok(response)
+    };
+
+    match accept_hdr_async(raw_stream, authorize).await {
         Ok(ws_stream) => {
-            logging::log_connection(&addr, "Cloud");
+            let Some((identity, hub_id)) = authorized else {
+                return;
+            };
+            let peer = Peer { identity, hub_id, connection: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed) };
+            logging::log_connection(&peer, "Cloud");
+            println!("🔐 {} authenticated as {} on hub {}", addr, peer.identity, peer.hub_id);
 
             // Create channels for this peer
             let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
-            peer_map.lock().unwrap().insert(addr, tx);
+            peer_map.lock().unwrap().insert(peer.clone(), tx);
 
             let (mut write, mut read) = ws_stream.split();
 
             // Send welcome message
-            let component = if addr.ip().is_loopback() { "Hub" } else { "external client" };
-            let welcome_msg = messages::create_welcome_message(component);
+            let welcome_msg = messages::create_welcome_message(&peer.identity.to_string());
 
             if let Err(e) = write.send(welcome_msg).await {
                 eprintln!("Failed to send welcome message: {}", e);
-                peer_map.lock().unwrap().remove(&addr);
+                peer_map.lock().unwrap().remove(&peer);
                 return;
             }
 
@@ -105,70 +212,93 @@ async fn handle_connection(peer_map: PeerMap, raw_stream: tokio::net::TcpStream,
                     message = read.next() => {
                         match message {
                             Some(Ok(Message::Text(text))) => {
-                                logging::log_message_received(&addr, &text);
-
-                                // Check if this is a hub broadcast message
-                                if messages::is_hub_broadcast(&text) {
-                                    // Extract the actual message
-                                    let broadcast_content = messages::extract_hub_broadcast(&text);
-
-                                    // Try to parse as device telemetry and update cloud state
-                                    match serde_json::from_str::<pozor_dom_shared::dashboard::lib::DeviceTelemetry>(broadcast_content) {
-                                        Ok(telemetry) => {
-                                            println!("✅ Cloud parsed telemetry for device: {}", telemetry.device_id);
-                                            let mut state = cloud_state.lock().await;
-                                            state.update_device(telemetry);
-                                        }
-                                        Err(_) => {
-                                            // Not device telemetry, broadcast to all clients
-                                            let all_peers = peer_map.lock().unwrap().clone();
-                                            let broadcast_msg = messages::create_hub_message(broadcast_content);
-                                            for (peer_addr, tx) in all_peers {
-                                                if let Err(e) = tx.send(broadcast_msg.clone()) {
-                                                    eprintln!("Failed to send hub broadcast to {}: {}", peer_addr, e);
-                                                }
-                                            }
+                                logging::log_message_received(&peer, &text);
+
+                                let envelope = match messages::parse_envelope(&text) {
+                                    Ok(envelope) => envelope,
+                                    Err(e) => {
+                                        eprintln!("Unrecognized frame from {}: {}", peer, e);
+                                        let reply = messages::create_error_message(&format!("Unrecognized frame: {}", e));
+                                        if let Err(e) = write.send(reply).await {
+                                            eprintln!("Failed to send response: {}", e);
+                                            break;
                                         }
+                                        continue;
                                     }
-                                } else {
-                                    // Normal message handling
-                                    // Collect broadcast recipients (release lock immediately)
-                                    let broadcast_recipients: Vec<Tx> = {
-                                        let peers = peer_map.lock().unwrap();
-                                        peers.iter()
-                                            .filter(|(peer_addr, _)| *peer_addr != &addr)
-                                            .map(|(_, tx)| tx.clone())
-                                            .collect()
-                                    };
-
-                                    // Broadcast to all other peers
-                                    let broadcast_msg = format!("[{}] {}", addr, text);
-                                    for recipient in broadcast_recipients {
-                                        if let Err(e) = recipient.send(Message::Text(broadcast_msg.clone().into())) {
-                                            eprintln!("Failed to send to peer: {}", e);
-                                        }
+                                };
+
+                                if let Some(reply) = messages::version_error(&envelope) {
+                                    eprintln!("Unsupported protocol version {} from {}", envelope.version, peer);
+                                    if let Err(e) = write.send(reply).await {
+                                        eprintln!("Failed to send response: {}", e);
+                                        break;
                                     }
+                                    continue;
+                                }
 
-                                    // Only echo back if this is not a response message (to prevent infinite loop)
-                                    if !messages::is_response_message(&text) {
-                                        // Echo back to sender
-                                        let echo_msg = messages::create_echo_message("Cloud", &text);
-                                        if let Err(e) = write.send(echo_msg).await {
-                                            eprintln!("Failed to send response: {}", e);
-                                            break;
+                                if is_hub_only(&envelope.payload) && !peer.identity.is_hub() {
+                                    eprintln!("Rejected hub-only frame from {}", peer);
+                                    let reply = messages::create_error_message("Only hubs may send this frame");
+                                    if let Err(e) = write.send(reply).await {
+                                        eprintln!("Failed to send response: {}", e);
+                                        break;
+                                    }
+                                    continue;
+                                }
+
+                                // Keep the hub's cloud state in sync with the telemetry and presence it relays
+                                match &envelope.payload {
+                                    Payload::Telemetry(telemetry) => {
+                                        println!("✅ Cloud parsed telemetry for device: {} (hub {})", telemetry.device_id, peer.hub_id);
+                                        let mut states = hub_states.lock().await;
+                                        states
+                                            .entry(peer.hub_id.clone())
+                                            .or_insert_with(|| dashboard::HubState::new(&peer.hub_id))
+                                            .update_device(telemetry.clone());
+                                    }
+                                    Payload::Presence(presence) => {
+                                        if let Some(state) = hub_states.lock().await.get_mut(&peer.hub_id) {
+                                            state.set_presence(&presence.device_id, presence.status, presence.last_seen);
                                         }
                                     }
+                                    _ => {}
+                                }
+
+                                // Collect broadcast recipients (release lock immediately)
+                                let broadcast_recipients: Vec<Tx> = {
+                                    let peers = peer_map.lock().unwrap();
+                                    peers.iter()
+                                        .filter(|(other, _)| *other != &peer && other.hub_id == peer.hub_id)
+                                        .map(|(_, tx)| tx.clone())
+                                        .collect()
+                                };
+
+                                // Relay to the hub and the other users subscribed to it
+                                let broadcast_msg = messages::to_ws_message(&envelope);
+                                for recipient in broadcast_recipients {
+                                    if let Err(e) = recipient.send(broadcast_msg.clone()) {
+                                        eprintln!("Failed to send to peer: {}", e);
+                                    }
+                                }
+
+                                // Only echo chat back to the sender; echoing replies or telemetry would loop forever
+                                if let Payload::Chat { text, .. } = &envelope.payload {
+                                    let echo_msg = messages::create_echo_message(messages::CLOUD_COMPONENT, text);
+                                    if let Err(e) = write.send(echo_msg).await {
+                                        eprintln!("Failed to send response: {}", e);
+                                        break;
+                                    }
                                 }
                             }
                             Some(Ok(Message::Close(_))) => {
-                                println!("Connection closed by: {}", addr);
+                                println!("Connection closed by: {}", peer);
                                 break;
                             }
                             Some(Ok(other)) => {
-                                println!("Received non-text message from {}: {:?}", addr, other);
+                                println!("Received non-text message from {}: {:?}", peer, other);
                             }
                             Some(Err(e)) => {
-                                eprintln!("Error from {}: {}", addr, e);
+                                eprintln!("Error from {}: {}", peer, e);
                                 break;
                             }
                             None => break,
@@ -180,7 +310,7 @@ async fn handle_connection(peer_map: PeerMap, raw_stream: tokio::net::TcpStream,
                         match message {
                             Some(msg) => {
                                 if let Err(e) = write.send(msg).await {
-                                    eprintln!("Failed to send message to {}: {}", addr, e);
+                                    eprintln!("Failed to send message to {}: {}", peer, e);
                                     break;
                                 }
                             }
@@ -191,8 +321,8 @@ async fn handle_connection(peer_map: PeerMap, raw_stream: tokio::net::TcpStream,
             }
 
             // Clean up
-            peer_map.lock().unwrap().remove(&addr);
-            println!("Connection with {} closed", addr);
+            peer_map.lock().unwrap().remove(&peer);
+            logging::log_disconnection(&peer, "Cloud");
         }
         Err(e) => {
             eprintln!("Failed to accept WebSocket connection from {}: {}", addr, e);
@@ -200,71 +330,119 @@ async fn handle_connection(peer_map: PeerMap, raw_stream: tokio::net::TcpStream,
     }
 }
 
-async fn start_cloud_web_server(cloud_state: Arc<Mutex<dashboard::HubState>>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Serve Yew-based dashboard
-    let dashboard = warp::path::end()
-        .map(move || {
-            let html_content = include_str!("../../pozor-dom-shared/src/dashboard/yew_index.html");
-            warp::reply::html(html_content)
-        });
-
-    // Cloud state filter for API endpoints
-    let cloud_state_filter = warp::any().map(move || Arc::clone(&cloud_state));
-
-    // Cloud-specific API endpoints
-    let api_toggle_cloud = warp::path!("api" / "toggle-cloud")
-        .and(warp::post())
-        .and(cloud_state_filter.clone())
-        .and_then(toggle_cloud);
-
-    // Proxy other API requests to hub
-    let api_proxy = warp::path("api")
-        .and(warp::path::full())
+// The cloud serves no dashboard of its own: each hub's dashboard is reached through the proxy below
+async fn start_cloud_web_server(
+    hub_states: HubStates,
+    peer_map: PeerMap<Peer>,
+    tokens: Arc<TokenStore>,
+    config: Config,
+    tls: Option<TlsFiles>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // /api/hubs/{id}/proxy/... goes to that hub's /api/...
+    let hub_proxy = Arc::new(HubProxy {
+        client: hub_api_client(config.tls.ca.as_deref())?,
+        hub_api_urls: config.cloud.hub_api_urls.clone(),
+        tokens: Arc::clone(&tokens),
+    });
+
+    let hub_states_filter = warp::any().map(move || Arc::clone(&hub_states));
+    let peer_map_filter = warp::any().map(move || peer_map.clone());
+    let tokens_filter = warp::any().map(move || Arc::clone(&tokens));
+    let authorization = warp::header::optional::<String>("authorization");
+
+    let api_hubs = warp::path!("api" / "hubs")
+        .and(warp::get())
+        .and(authorization)
+        .and(tokens_filter.clone())
+        .and(hub_states_filter.clone())
+        .and(peer_map_filter)
+        .and_then(list_hubs);
+
+    let api_hub_devices = warp::path!("api" / "hubs" / String / "devices")
+        .and(warp::get())
+        .and(authorization)
+        .and(tokens_filter.clone())
+        .and(hub_states_filter)
+        .and_then(hub_devices);
+
+    let api_proxy = warp::path!("api" / "hubs" / String / "proxy" / ..)
+        .and(warp::path::tail())
         .and(warp::method())
         .and(warp::body::bytes())
+        .and(warp::header::headers_cloned())
+        .and(warp::any().map(move || Arc::clone(&hub_proxy)))
         .and_then(proxy_to_hub);
 
-    // Serve WebAssembly files (embedded)
-    let wasm_js_content = include_bytes!("../../pozor-dom-shared/pkg/pozor_dom_shared.js");
-    let wasm_js = warp::path("pozor_dom_shared.js")
-        .map(move || {
-            warp::http::Response::builder()
-                .header("content-type", "application/javascript")
-                .body(wasm_js_content.to_vec())
-                .unwrap()
-        });
-
-    let wasm_binary_content = include_bytes!("../../pozor-dom-shared/pkg/pozor_dom_shared_bg.wasm");
-    let wasm_binary = warp::path("pozor_dom_shared_bg.wasm")
-        .map(move || {
-            warp::http::Response::builder()
-                .header("content-type", "application/wasm")
-                .body(wasm_binary_content.to_vec())
-                .unwrap()
-        });
-
-    let routes = dashboard
-        .or(api_toggle_cloud)
+    let routes = api_hubs
+        .or(api_hub_devices)
         .or(api_proxy)
-        .or(wasm_js)
-        .or(wasm_binary)
         .with(warp::cors().allow_any_origin());
 
-    println!("🌐 Cloud web dashboard available at: http://localhost:8080");
-    warp::serve(routes).run(([127, 0, 0, 1], 8080)).await;
+    let port = config.cloud.dashboard_port;
+    match tls {
+        Some(files) => {
+            println!("🌐 Cloud HTTP API available at: https://localhost:{}", port);
+            warp::serve(routes).tls().cert_path(&files.cert_path).key_path(&files.key_path).run(([127, 0, 0, 1], port)).await;
+        }
+        None => {
+            println!("🌐 Cloud HTTP API available at: http://localhost:{}", port);
+            warp::serve(routes).run(([127, 0, 0, 1], port)).await;
+        }
+    }
 
     Ok(())
 }
 
+// Client for the hub API; also trusts tls.ca when the hub dashboard uses a self-signed certificate
+fn hub_api_client(ca_path: Option<&str>) -> Result<reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(path) = ca_path {
+        let pem = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+    Ok(builder.build()?)
+}
+
+fn json_error(message: &str, status: warp::http::StatusCode) -> Box<dyn warp::Reply> {
+    Box::new(warp::reply::with_status(warp::reply::json(&serde_json::json!({ "error": message })), status))
+}
+
+// The caller's relay token (a user's, or a hub's for itself) and its access to the hub: 401 or 403 otherwise
+fn authorize_hub(tokens: &TokenStore, authorization: Option<&str>, hub_id: &str) -> Result<(), Box<dyn warp::Reply>> {
+    let identity = tokens
+        .authenticate_bearer(authorization)
+        .ok_or_else(|| json_error("missing or invalid token", warp::http::StatusCode::UNAUTHORIZED))?;
+    tokens
+        .authorize_hub(&identity, Some(hub_id))
+        .map(|_| ())
+        .map_err(|reason| json_error(&reason, warp::http::StatusCode::FORBIDDEN))
+}
+
+struct HubProxy {
+    client: reqwest::Client,
+    // Hub dashboards by hub id
+    hub_api_urls: BTreeMap<String, String>,
+    tokens: Arc<TokenStore>,
+}
+
 async fn proxy_to_hub(
-    path: warp::path::FullPath,
+    hub_id: String,
+    path: warp::path::Tail,
     method: warp::http::Method,
     body: bytes::Bytes,
+    headers: warp::http::HeaderMap,
+    proxy: Arc<HubProxy>,
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    // Proxy to hub at localhost:3000
-    let hub_url = format!("http://localhost:3000{}", path.as_str());
+    let authorization = headers.get("authorization").and_then(|v| v.to_str().ok());
+    if let Err(reply) = authorize_hub(&proxy.tokens, authorization, &hub_id) {
+        return Ok(reply);
+    }
+    let Some(hub_api_url) = proxy.hub_api_urls.get(&hub_id) else {
+        return Ok(json_error(&format!("no dashboard configured for hub {}", hub_id), warp::http::StatusCode::NOT_FOUND));
+    };
+    let hub_url = format!("{}/api/{}", hub_api_url.trim_end_matches('/'), path.as_str());
 
-    let client = reqwest::Client::new();
+    let client = &proxy.client;
     let mut request = match method {
         warp::http::Method::GET => client.get(&hub_url),
         warp::http::Method::POST => client.post(&hub_url),
@@ -273,6 +451,13 @@ async fn proxy_to_hub(
         _ => return Err(warp::reject::not_found()),
     };
 
+    // Authorization carries the relay token; the hub checks its own session from the cookie
+    for name in ["cookie", "content-type"] {
+        if let Some(value) = headers.get(name).and_then(|v| v.to_str().ok()) {
+            request = request.header(name, value);
+        }
+    }
+
     if !body.is_empty() {
         request = request.body(body);
     }
@@ -280,6 +465,7 @@ async fn proxy_to_hub(
     match request.send().await {
         Ok(response) => {
             let status_code = response.status().as_u16();
+            let set_cookie = response.headers().get("set-cookie").and_then(|v| v.to_str().ok()).map(str::to_string);
             let is_json = response.headers().get("content-type")
                 .and_then(|v| v.to_str().ok())
                 .map(|v| v.contains("application/json"))
@@ -289,27 +475,70 @@ async fn proxy_to_hub(
             // Return the response body with appropriate status
             let reply = warp::reply::with_status(body_bytes.to_vec(), warp::http::StatusCode::from_u16(status_code).unwrap_or(warp::http::StatusCode::OK));
 
-            // Add content-type header if it's JSON
-            if is_json {
-                Ok(Box::new(warp::reply::with_header(reply, "content-type", "application/json")))
+            // Add content-type header if it's JSON, and the session cookie from a login
+            let reply: Box<dyn warp::Reply> = if is_json {
+                Box::new(warp::reply::with_header(reply, "content-type", "application/json"))
             } else {
-                Ok(Box::new(reply))
+                Box::new(reply)
+            };
+            match set_cookie {
+                Some(cookie) => Ok(Box::new(warp::reply::with_header(reply, "set-cookie", cookie))),
+                None => Ok(reply),
             }
         }
         Err(e) => {
-            eprintln!("Failed to proxy request to hub: {}", e);
+            eprintln!("Failed to proxy request to hub {}: {}", hub_id, e);
             Err(warp::reject::not_found())
         }
     }
 }
 
-async fn toggle_cloud(
-    cloud_state: Arc<Mutex<dashboard::HubState>>,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut state = cloud_state.lock().await;
-    state.toggle_cloud();
-    Ok(warp::reply::json(&serde_json::json!({
-        "cloud_enabled": state.cloud_enabled,
-        "message": format!("Cloud {} for {}", if state.cloud_enabled { "enabled" } else { "disabled" }, state.service_name)
-    })))
+// Hubs the cloud has seen and the caller may access, with whether they are connected right now
+// and how many users are subscribed
+async fn list_hubs(
+    authorization: Option<String>,
+    tokens: Arc<TokenStore>,
+    hub_states: HubStates,
+    peer_map: PeerMap<Peer>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let Some(identity) = tokens.authenticate_bearer(authorization.as_deref()) else {
+        return Ok(json_error("missing or invalid token", warp::http::StatusCode::UNAUTHORIZED));
+    };
+
+    let mut hubs: HashMap<String, (bool, usize)> = hub_states.lock().await.keys().map(|hub_id| (hub_id.clone(), (false, 0))).collect();
+    for peer in peer_map.lock().unwrap().keys() {
+        let (connected, users) = hubs.entry(peer.hub_id.clone()).or_default();
+        if peer.identity.is_hub() {
+            *connected = true;
+        } else {
+            *users += 1;
+        }
+    }
+
+    let states = hub_states.lock().await;
+    let mut hubs: Vec<serde_json::Value> = hubs
+        .into_iter()
+        .filter(|(hub_id, _)| tokens.authorize_hub(&identity, Some(hub_id)).is_ok())
+        .map(|(hub_id, (connected, users))| {
+            let devices = states.get(&hub_id).map(|state| state.devices.len()).unwrap_or(0);
+            serde_json::json!({"hub_id": hub_id, "connected": connected, "users": users, "devices": devices})
+        })
+        .collect();
+    hubs.sort_by(|a, b| a["hub_id"].as_str().cmp(&b["hub_id"].as_str()));
+    Ok(Box::new(warp::reply::json(&hubs)))
+}
+
+async fn hub_devices(
+    hub_id: String,
+    authorization: Option<String>,
+    tokens: Arc<TokenStore>,
+    hub_states: HubStates,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if let Err(reply) = authorize_hub(&tokens, authorization.as_deref(), &hub_id) {
+        return Ok(reply);
+    }
+    match hub_states.lock().await.get(&hub_id) {
+        Some(state) => Ok(Box::new(warp::reply::json(&state.device_summaries()))),
+        None => Ok(json_error(&format!("unknown hub {}", hub_id), warp::http::StatusCode::NOT_FOUND)),
+    }
 }
@@ -0,0 +1,264 @@
+// Fleet description for the emulator: broker address, default publish interval and the
+// devices to run. Loaded from `--fleet <file>` (TOML, or JSON by extension) or built in.
+
+use pozor_dom_shared::device::{Capability, DeviceDescriptor, DeviceType};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+type FleetError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FleetConfig {
+    pub broker: BrokerConfig,
+    pub publish_interval_secs: f64,
+    pub devices: Vec<DeviceSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrokerConfig {
+    pub host: String,
+    pub port: u16,
+    pub keep_alive_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceSpec {
+    pub id: String,
+    pub channel: String,
+    #[serde(default, rename = "type")]
+    pub device_type: DeviceType,
+    // Defaults to the usual capabilities of the device type
+    #[serde(default)]
+    pub capabilities: Option<Vec<Capability>>,
+    // Overrides the fleet-wide publish interval
+    #[serde(default)]
+    pub interval_secs: Option<f64>,
+    #[serde(default)]
+    pub ranges: BTreeMap<Capability, ValueRange>,
+    // Expands into `count` devices named `{id}-001`, `{id}-002`, ... for load tests
+    #[serde(default = "default_count")]
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+// A single device ready to run, after defaults are applied and `count` is expanded
+#[derive(Debug, Clone)]
+pub struct EmulatedDevice {
+    pub descriptor: DeviceDescriptor,
+    pub publish_interval: Duration,
+    pub ranges: BTreeMap<Capability, ValueRange>,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 1883,
+            keep_alive_secs: 5,
+        }
+    }
+}
+
+impl Default for FleetConfig {
+    fn default() -> Self {
+        let spec = |id: &str, channel: &str, device_type: DeviceType| DeviceSpec {
+            id: id.to_string(),
+            channel: channel.to_string(),
+            device_type,
+            capabilities: None,
+            interval_secs: None,
+            ranges: BTreeMap::new(),
+            count: 1,
+        };
+
+        Self {
+            broker: BrokerConfig::default(),
+            publish_interval_secs: 5.0,
+            devices: vec![
+                // WiFi devices
+                spec("device-wifi-001", "WiFi", DeviceType::Sensor),
+                spec("device-wifi-002", "WiFi", DeviceType::Sensor),
+                spec("device-wifi-003", "WiFi", DeviceType::Sensor),
+                // BLE devices
+                spec("device-ble-001", "BLE", DeviceType::Sensor),
+                spec("device-ble-002", "BLE", DeviceType::Sensor),
+                spec("device-ble-003", "BLE", DeviceType::Sensor),
+                // ZigBee devices
+                spec("device-zigbee-001", "ZigBee", DeviceType::Sensor),
+                spec("device-zigbee-002", "ZigBee", DeviceType::Sensor),
+                spec("device-zigbee-003", "ZigBee", DeviceType::Sensor),
+                // Additional device types
+                spec("sensor-temp-001", "WiFi", DeviceType::Sensor),
+                spec("sensor-motion-001", "BLE", DeviceType::MotionSensor),
+                spec("light-bulb-001", "ZigBee", DeviceType::Light),
+                spec("thermostat-001", "WiFi", DeviceType::Thermostat),
+                spec("door-sensor-001", "BLE", DeviceType::ContactSensor),
+                spec("smart-plug-001", "ZigBee", DeviceType::SmartPlug),
+                spec("lock-front-001", "ZigBee", DeviceType::Lock),
+            ],
+        }
+    }
+}
+
+impl ValueRange {
+    pub const fn new(min: f64, max: f64) -> Self {
+        Self { min, max }
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+pub fn default_capabilities(device_type: DeviceType) -> Vec<Capability> {
+    match device_type {
+        DeviceType::Sensor => vec![Capability::Temperature, Capability::Humidity],
+        DeviceType::Light => vec![Capability::Switch, Capability::Dimmer],
+        DeviceType::SmartPlug => vec![Capability::Switch, Capability::PowerMeter],
+        DeviceType::Thermostat => vec![Capability::Temperature, Capability::ThermostatSetpoint],
+        DeviceType::ContactSensor => vec![Capability::Contact],
+        DeviceType::MotionSensor => vec![Capability::Motion],
+        DeviceType::Lock => vec![Capability::Lock],
+    }
+}
+
+impl FleetConfig {
+    pub fn load(path: &Path) -> Result<Self, FleetError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read fleet file {}: {}", path.display(), e))?;
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        Self::parse(&contents, is_json).map_err(|e| format!("invalid fleet file {}: {}", path.display(), e).into())
+    }
+
+    pub fn parse(contents: &str, is_json: bool) -> Result<Self, FleetError> {
+        let config: FleetConfig = if is_json {
+            serde_json::from_str(contents)?
+        } else {
+            toml::from_str(contents)?
+        };
+        Ok(config)
+    }
+
+    // Applies defaults, expands `count` and validates the result
+    pub fn devices(&self) -> Result<Vec<EmulatedDevice>, FleetError> {
+        if self.devices.is_empty() {
+            return Err("fleet has no devices".into());
+        }
+        let default_interval = interval(self.publish_interval_secs)?;
+
+        let mut seen = HashSet::new();
+        let mut devices = Vec::new();
+        for spec in &self.devices {
+            if spec.id.is_empty() {
+                return Err("device id must not be empty".into());
+            }
+            if spec.count == 0 {
+                return Err(format!("device {}: count must be at least 1", spec.id).into());
+            }
+            for (capability, range) in &spec.ranges {
+                if !(range.min.is_finite() && range.max.is_finite() && range.min <= range.max) {
+                    return Err(format!("device {}: invalid {} range {}..{}", spec.id, capability.as_str(), range.min, range.max).into());
+                }
+            }
+            let publish_interval = match spec.interval_secs {
+                Some(secs) => interval(secs).map_err(|e| format!("device {}: {}", spec.id, e))?,
+                None => default_interval,
+            };
+            let capabilities = spec
+                .capabilities
+                .clone()
+                .unwrap_or_else(|| default_capabilities(spec.device_type));
+
+            for index in 1..=spec.count {
+                let device_id = if spec.count == 1 {
+                    spec.id.clone()
+                } else {
+                    format!("{}-{:03}", spec.id, index)
+                };
+                if !seen.insert(device_id.clone()) {
+                    return Err(format!("duplicate device id {}", device_id).into());
+                }
+                devices.push(EmulatedDevice {
+                    descriptor: DeviceDescriptor {
+                        device_id,
+                        channel: spec.channel.clone(),
+                        device_type: spec.device_type,
+                        capabilities: capabilities.clone(),
+                    },
+                    publish_interval,
+                    ranges: spec.ranges.clone(),
+                });
+            }
+        }
+        Ok(devices)
+    }
+}
+
+fn interval(secs: f64) -> Result<Duration, FleetError> {
+    if secs.is_finite() && secs > 0.0 {
+        Ok(Duration::from_secs_f64(secs))
+    } else {
+        Err(format!("publish interval must be a positive number of seconds, got {}", secs).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_fleet_parses() {
+        let config = FleetConfig::parse(include_str!("../fleet.example.toml"), false).unwrap();
+        let devices = config.devices().unwrap();
+        assert_eq!(config.broker.port, 1883);
+        assert!(devices.iter().any(|d| d.descriptor.device_type == DeviceType::Thermostat));
+        assert_eq!(devices.iter().filter(|d| d.descriptor.device_id.starts_with("load-sensor-")).count(), 50);
+    }
+
+    #[test]
+    fn test_json_fleet_applies_defaults() {
+        let config = FleetConfig::parse(
+            r#"{"publish_interval_secs": 2, "devices": [{"id": "plug", "channel": "WiFi", "type": "smart_plug", "interval_secs": 0.5}]}"#,
+            true,
+        )
+        .unwrap();
+        let devices = config.devices().unwrap();
+        assert_eq!(config.broker.host, "127.0.0.1");
+        assert_eq!(devices[0].descriptor.capabilities, vec![Capability::Switch, Capability::PowerMeter]);
+        assert_eq!(devices[0].publish_interval, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_invalid_fleets_are_rejected() {
+        let duplicate = FleetConfig::parse(
+            "[[devices]]\nid = \"a\"\nchannel = \"WiFi\"\n\n[[devices]]\nid = \"a\"\nchannel = \"BLE\"\n",
+            false,
+        )
+        .unwrap();
+        assert!(duplicate.devices().is_err());
+
+        let inverted = FleetConfig::parse(
+            "[[devices]]\nid = \"a\"\nchannel = \"WiFi\"\nranges.temperature = { min = 30, max = 10 }\n",
+            false,
+        )
+        .unwrap();
+        assert!(inverted.devices().is_err());
+
+        assert!(FleetConfig::parse("publish_interval = 5\n", false).is_err());
+    }
+}
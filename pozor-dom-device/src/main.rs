@@ -1,96 +1,69 @@
-
-use rand::Rng;
-use rumqttc::{AsyncClient, MqttOptions, QoS};
-use serde_json::json;
-use std::time::Duration;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rumqttc::{AsyncClient, LastWill, MqttOptions, QoS};
+use pozor_dom_shared::dashboard::DeviceTelemetry;
+use pozor_dom_shared::device::PresenceStatus;
+use pozor_dom_shared::envelope::{CommandResult, Envelope, Payload};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
 use tokio::time::sleep;
 
-const MQTT_BROKER: &str = "127.0.0.1";
-const MQTT_PORT: u16 = 1883;
+mod fleet;
+mod scenario;
+mod state;
+
+use fleet::{BrokerConfig, EmulatedDevice, FleetConfig};
+use scenario::{FaultFlags, FaultKind, Scenario};
+use state::DeviceState;
 
-#[derive(Debug, Clone)]
-struct Device {
-    id: String,
-    channel: String,
+// Settings shared by every emulated device
+struct Emulation {
+    broker: BrokerConfig,
+    scenario: Scenario,
+    // Scenario fault times are measured from here
+    started: Instant,
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     println!("🔌 Позор-дом Device - MQTT Emulator");
-    println!("Подключение к MQTT брокеру: {}:{}\n", MQTT_BROKER, MQTT_PORT);
-
-    // Create devices for each channel - expanded device set
-    let devices = vec![
-        // WiFi devices
-        Device {
-            id: "device-wifi-001".to_string(),
-            channel: "WiFi".to_string(),
-        },
-        Device {
-            id: "device-wifi-002".to_string(),
-            channel: "WiFi".to_string(),
-        },
-        Device {
-            id: "device-wifi-003".to_string(),
-            channel: "WiFi".to_string(),
-        },
-        // BLE devices
-        Device {
-            id: "device-ble-001".to_string(),
-            channel: "BLE".to_string(),
-        },
-        Device {
-            id: "device-ble-002".to_string(),
-            channel: "BLE".to_string(),
-        },
-        Device {
-            id: "device-ble-003".to_string(),
-            channel: "BLE".to_string(),
-        },
-        // ZigBee devices
-        Device {
-            id: "device-zigbee-001".to_string(),
-            channel: "ZigBee".to_string(),
-        },
-        Device {
-            id: "device-zigbee-002".to_string(),
-            channel: "ZigBee".to_string(),
-        },
-        Device {
-            id: "device-zigbee-003".to_string(),
-            channel: "ZigBee".to_string(),
-        },
-        // Additional device types
-        Device {
-            id: "sensor-temp-001".to_string(),
-            channel: "WiFi".to_string(),
-        },
-        Device {
-            id: "sensor-motion-001".to_string(),
-            channel: "BLE".to_string(),
-        },
-        Device {
-            id: "light-bulb-001".to_string(),
-            channel: "ZigBee".to_string(),
-        },
-        Device {
-            id: "thermostat-001".to_string(),
-            channel: "WiFi".to_string(),
-        },
-        Device {
-            id: "door-sensor-001".to_string(),
-            channel: "BLE".to_string(),
-        },
-        Device {
-            id: "smart-plug-001".to_string(),
-            channel: "ZigBee".to_string(),
-        },
-    ];
-
-    // Spawn a device instance for each channel
+
+    let args: Vec<String> = std::env::args().collect();
+    let fleet = match arg_path(&args, "--fleet") {
+        Some(path) => {
+            println!("📄 Loading fleet from {}", path.display());
+            FleetConfig::load(&path)?
+        }
+        None => FleetConfig::default(),
+    };
+    let scenario = match arg_path(&args, "--scenario") {
+        Some(path) => {
+            let scenario = Scenario::load(&path)?;
+            println!("⚡ Loaded scenario from {} ({} faults)", path.display(), scenario.faults.len());
+            scenario
+        }
+        None => Scenario::default(),
+    };
+    let devices = fleet.devices()?;
+
+    println!("Подключение к MQTT брокеру: {}:{}", fleet.broker.host, fleet.broker.port);
+    println!("Эмулируется устройств: {}\n", devices.len());
+
+    let emulation = Arc::new(Emulation {
+        broker: fleet.broker,
+        scenario,
+        started: Instant::now(),
+    });
+
+    // Spawn a device instance for each device in the fleet
     for device in devices {
+        let emulation = Arc::clone(&emulation);
         tokio::spawn(async move {
-            run_device(device).await;
+            run_device(device, emulation).await;
         });
     }
 
@@ -100,36 +73,118 @@ async fn main() {
     }
 }
 
-async fn run_device(device: Device) {
-    let device_id = device.id.clone();
+// `--name <file>` or `--name=<file>`
+fn arg_path(args: &[String], name: &str) -> Option<PathBuf> {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == name {
+            return iter.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+async fn run_device(emulated: EmulatedDevice, emulation: Arc<Emulation>) {
+    let device = emulated.descriptor;
+    let device_id = device.device_id.clone();
     let channel = device.channel.clone();
 
-    println!("📱 Starting device: {} on {} channel", device_id, channel);
+    println!("📱 Starting device: {} ({}) on {} channel", device_id, device.device_type.as_str(), channel);
+
+    let broker = &emulation.broker;
+    let mut mqtt_options = MqttOptions::new(&device_id, broker.host.as_str(), broker.port);
+    mqtt_options.set_keep_alive(Duration::from_secs(broker.keep_alive_secs));
 
-    let mut mqtt_options = MqttOptions::new(&device_id, MQTT_BROKER, MQTT_PORT);
-    mqtt_options.set_keep_alive(Duration::from_secs(5));
+    // The broker announces "offline" for us if the connection drops without a clean disconnect
+    let status_topic = format!("pozor-dom/device/{}/status", device_id);
+    mqtt_options.set_last_will(LastWill::new(
+        &status_topic,
+        PresenceStatus::Offline.as_str(),
+        QoS::AtLeastOnce,
+        true,
+    ));
 
     let (client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
 
+    let state = Arc::new(Mutex::new(DeviceState::new(&device, &emulated.ranges)));
+    // Woken after a command changes the state so telemetry reflects it right away
+    let state_changed = Arc::new(Notify::new());
+    let flags = Arc::new(FaultFlags::default());
+
     // Spawn event loop handler
     let device_for_event = device_id.clone();
+    let client_for_event = client.clone();
+    let state_for_event = Arc::clone(&state);
+    let changed_for_event = Arc::clone(&state_changed);
+    let flags_for_event = Arc::clone(&flags);
     tokio::spawn(async move {
+        let command_topic = format!("pozor-dom/hub/command/{}", device_for_event);
+        let mut disconnect_sent = false;
         loop {
+            // While a broker_disconnect fault is active, stop polling so no reconnect happens
+            if flags_for_event.is_disconnected() {
+                if disconnect_sent {
+                    sleep(Duration::from_millis(200)).await;
+                    continue;
+                }
+            } else {
+                disconnect_sent = false;
+            }
+
             match eventloop.poll().await {
                 Ok(notification) => {
                     // Handle MQTT notifications (subscribed messages)
                     match notification {
+                        rumqttc::Event::Incoming(rumqttc::Incoming::ConnAck(_)) => {
+                            // Subscribe and announce presence on every (re)connect, the session is not persisted
+                            let client = client_for_event.clone();
+                            let device_id = device_for_event.clone();
+                            let topic = command_topic.clone();
+                            let status_topic = status_topic.clone();
+                            tokio::spawn(async move {
+                                match client.subscribe(&topic, QoS::AtMostOnce).await {
+                                    Ok(_) => println!("✅ [{}] Subscribed to: {}", device_id, topic),
+                                    Err(e) => eprintln!("❌ [{}] Subscribe error: {}", device_id, e),
+                                }
+                                let online = PresenceStatus::Online.as_str();
+                                if let Err(e) = client.publish(&status_topic, QoS::AtLeastOnce, true, online).await {
+                                    eprintln!("❌ [{}] Status publish error: {}", device_id, e);
+                                }
+                            });
+                        }
+                        rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect) => {
+                            disconnect_sent = true;
+                        }
                         rumqttc::Event::Incoming(rumqttc::Incoming::Publish(publish)) => {
+                            if flags_for_event.is_offline() {
+                                println!("🔇 [{}] Offline, ignoring message on {}", device_for_event, publish.topic);
+                                continue;
+                            }
                             if let Ok(payload) = std::str::from_utf8(&publish.payload) {
                                 println!(
                                     "📥 [{}] Received command on {}: {}",
                                     device_for_event, publish.topic, payload
                                 );
+
+                                if let Some(result) = handle_command(&device_for_event, &state_for_event, payload) {
+                                    if result.success {
+                                        changed_for_event.notify_one();
+                                    }
+                                    // Publish from a separate task so the event loop keeps polling
+                                    let client = client_for_event.clone();
+                                    tokio::spawn(publish_command_result(client, result));
+                                }
                             }
                         }
                         _ => {}
                     }
                 }
+                Err(_) if flags_for_event.is_disconnected() => {
+                    disconnect_sent = true;
+                }
                 Err(e) => {
                     eprintln!("❌ [{}] MQTT Error: {}", device_for_event, e);
                     sleep(Duration::from_secs(5)).await;
@@ -138,61 +193,130 @@ async fn run_device(device: Device) {
         }
     });
 
-    sleep(Duration::from_millis(500)).await;
-
-    // Subscribe to command topic for this device
-    let command_topic = format!("pozor-dom/hub/command/{}", device_id);
-    match client.subscribe(&command_topic, QoS::AtMostOnce).await {
-        Ok(_) => println!("✅ [{}] Subscribed to: {}", device_id, command_topic),
-        Err(e) => eprintln!("❌ [{}] Subscribe error: {}", device_id, e),
-    }
+    let mut rng = match emulation.scenario.device_seed(&device_id) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut fired_faults = HashSet::new();
 
     // Publish telemetry data periodically
     loop {
-        // Generate random values inside the loop to avoid Send issues
-        let temperature = {
-            let mut rng = rand::thread_rng();
-            20.0 + rng.gen_range(-5.0..5.0)
-        };
-        
-        let humidity = {
-            let mut rng = rand::thread_rng();
-            50.0 + rng.gen_range(-20.0..20.0)
-        };
-        
-        let signal_strength = {
-            let mut rng = rand::thread_rng();
-            rng.gen_range(-100..-30)
+        let faults = emulation.scenario.active(&device_id, emulation.started.elapsed(), &mut fired_faults);
+        let offline = faults.contains(&FaultKind::Offline);
+        let disconnect = faults.contains(&FaultKind::BrokerDisconnect);
+
+        if flags.offline.swap(offline, Ordering::Relaxed) != offline {
+            println!("⚡ [{}] Fault: {}", device_id, if offline { "going offline" } else { "back online" });
+        }
+        if flags.disconnected.swap(disconnect, Ordering::Relaxed) != disconnect {
+            if disconnect {
+                println!("⚡ [{}] Fault: dropping broker connection", device_id);
+                if let Err(e) = client.try_disconnect() {
+                    eprintln!("❌ [{}] Disconnect error: {}", device_id, e);
+                }
+            } else {
+                println!("⚡ [{}] Fault cleared: reconnecting to broker", device_id);
+            }
+        }
+
+        let (capabilities, signal_strength) = {
+            let mut state = state.lock().unwrap();
+            state.tick(&mut rng);
+            (state.values().clone(), rng.gen_range(-100..-30))
         };
 
-        let timestamp = chrono::Local::now().to_rfc3339();
+        if !offline && !disconnect {
+            let mut telemetry = DeviceTelemetry {
+                device_id: device_id.clone(),
+                channel: channel.clone(),
+                device_type: device.device_type,
+                capabilities,
+                signal_strength,
+                timestamp: chrono::Utc::now(),
+            };
+            let mut copies = 1;
+            let mut malformed = false;
+            for fault in &faults {
+                match fault {
+                    FaultKind::Burst { count } => copies += count,
+                    FaultKind::MalformedJson => malformed = true,
+                    FaultKind::OutOfRange { capability, value } => {
+                        telemetry.capabilities.insert(*capability, value.clone());
+                    }
+                    FaultKind::ClockSkew { skew_secs } => {
+                        telemetry.timestamp += chrono::Duration::seconds(*skew_secs);
+                    }
+                    FaultKind::DuplicateId { as_device } => telemetry.device_id = as_device.clone(),
+                    FaultKind::Offline | FaultKind::BrokerDisconnect => {}
+                }
+            }
 
-        let telemetry = json!({
-            "device_id": device_id,
-            "channel": channel,
-            "temperature": format!("{:.2}", temperature),
-            "humidity": format!("{:.2}", humidity),
-            "timestamp": timestamp,
-            "signal_strength": signal_strength
-        });
+            let topic = format!("pozor-dom/device/{}/telemetry", telemetry.device_id);
+            let (payload, summary) = if malformed {
+                // Cut off mid-object, the way a crashing firmware would leave it
+                let payload = format!("{{\"device_id\":\"{}\",\"capabilities\":{{\"temperature\":", telemetry.device_id);
+                let summary = format!("⚡ malformed payload: {}", payload);
+                (payload, summary)
+            } else {
+                let summary = Envelope::telemetry(telemetry.clone()).to_string();
+                (serde_json::to_string(&telemetry).unwrap_or_default(), summary)
+            };
+            if copies > 1 {
+                println!("⚡ [{}] Fault: burst of {} messages", device_id, copies);
+            }
 
-        let topic = format!("pozor-dom/device/{}/telemetry", device_id);
-        match client
-            .publish(
-                &topic,
-                QoS::AtLeastOnce,
-                false,
-                telemetry.to_string().into_bytes(),
-            )
-            .await
-        {
-            Ok(_) => println!(
-                "📤 [{}] Telemetry: temp={:.2}°C, humidity={:.2}%",
-                device_id, temperature, humidity
-            ),
-            Err(e) => eprintln!("❌ [{}] Publish error: {}", device_id, e),
+            for _ in 0..copies {
+                match client
+                    .publish(
+                        &topic,
+                        QoS::AtLeastOnce,
+                        false,
+                        payload.clone().into_bytes(),
+                    )
+                    .await
+                {
+                    Ok(_) => println!("📤 [{}] {}", device_id, summary),
+                    Err(e) => eprintln!("❌ [{}] Publish error: {}", device_id, e),
+                }
+            }
         }
 
-        sleep(Duration::from_secs(5)).await;
+        tokio::select! {
+            _ = sleep(emulated.publish_interval) => {}
+            _ = state_changed.notified() => {}
+        }
+    }
+}
+
+fn handle_command(device_id: &str, state: &Mutex<DeviceState>, payload: &str) -> Option<CommandResult> {
+    let command = match Envelope::from_json(payload) {
+        Ok(Envelope { payload: Payload::Command(command), .. }) => command,
+        _ => return None,
+    };
+
+    let outcome = state.lock().unwrap().apply(&command.action);
+    let (success, detail) = match outcome {
+        Ok(detail) => (true, detail),
+        Err(reason) => {
+            eprintln!("⚠️ [{}] Rejected command '{}': {}", device_id, command.action, reason);
+            (false, reason)
+        }
+    };
+
+    Some(CommandResult {
+        command_id: command.command_id,
+        device_id: device_id.to_string(),
+        success,
+        detail: Some(detail),
+    })
+}
+
+async fn publish_command_result(client: AsyncClient, result: CommandResult) {
+    let topic = format!("pozor-dom/device/{}/command_result", result.device_id);
+    let payload = serde_json::to_string(&result).unwrap_or_default();
+
+    match client.publish(&topic, QoS::AtLeastOnce, false, payload.into_bytes()).await {
+        Ok(_) => println!("📤 [{}] Command result for {}: {}", result.device_id, result.command_id, if result.success { "ok" } else { "failed" }),
+        Err(e) => eprintln!("❌ [{}] Command result publish error: {}", result.device_id, e),
     }
 }
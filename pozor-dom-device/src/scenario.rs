@@ -0,0 +1,199 @@
+// Fault injection timeline for the emulator, loaded from `--scenario <file>` (TOML, or JSON by
+// extension). Times are seconds since the emulator started, so a run is reproducible.
+
+use pozor_dom_shared::device::{Capability, CapabilityValue};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+type ScenarioError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    // Seeds every device's random generator so readings repeat between runs
+    pub seed: Option<u64>,
+    pub faults: Vec<Fault>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fault {
+    pub at_secs: f64,
+    // Without a duration the fault fires once, on the first publish at or after `at_secs`
+    #[serde(default)]
+    pub duration_secs: Option<f64>,
+    // Exact ids or `prefix*`; empty means every device
+    #[serde(default)]
+    pub devices: Vec<String>,
+    #[serde(flatten)]
+    pub kind: FaultKind,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FaultKind {
+    // Stops publishing and ignores commands while keeping the MQTT session
+    Offline,
+    // Drops the MQTT connection; the device reconnects and resubscribes afterwards
+    BrokerDisconnect,
+    // Publishes `count` extra telemetry messages back to back
+    Burst { count: usize },
+    // Publishes a payload that is not valid telemetry JSON
+    MalformedJson,
+    // Reports `value` for `capability` regardless of the device state
+    OutOfRange { capability: Capability, value: CapabilityValue },
+    // Shifts the telemetry timestamp by `skew_secs` (negative for the past)
+    ClockSkew { skew_secs: i64 },
+    // Publishes telemetry under another device's id and topic
+    DuplicateId { as_device: String },
+}
+
+// Fault state the telemetry loop shares with the device's MQTT event loop
+#[derive(Debug, Default)]
+pub struct FaultFlags {
+    pub offline: AtomicBool,
+    pub disconnected: AtomicBool,
+}
+
+impl FaultFlags {
+    pub fn is_offline(&self) -> bool {
+        self.offline.load(Ordering::Relaxed)
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+}
+
+impl Fault {
+    fn applies_to(&self, device_id: &str) -> bool {
+        self.devices.is_empty()
+            || self.devices.iter().any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => device_id.starts_with(prefix),
+                None => pattern == device_id,
+            })
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read scenario file {}: {}", path.display(), e))?;
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        Self::parse(&contents, is_json).map_err(|e| format!("invalid scenario file {}: {}", path.display(), e).into())
+    }
+
+    pub fn parse(contents: &str, is_json: bool) -> Result<Self, ScenarioError> {
+        let scenario: Scenario = if is_json {
+            serde_json::from_str(contents)?
+        } else {
+            toml::from_str(contents)?
+        };
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    fn validate(&self) -> Result<(), ScenarioError> {
+        for (index, fault) in self.faults.iter().enumerate() {
+            if !(fault.at_secs.is_finite() && fault.at_secs >= 0.0) {
+                return Err(format!("fault #{}: at_secs must be a non-negative number", index + 1).into());
+            }
+            if let Some(duration) = fault.duration_secs {
+                if !(duration.is_finite() && duration > 0.0) {
+                    return Err(format!("fault #{}: duration_secs must be positive", index + 1).into());
+                }
+            }
+            match &fault.kind {
+                FaultKind::Burst { count: 0 } => {
+                    return Err(format!("fault #{}: burst count must be at least 1", index + 1).into());
+                }
+                FaultKind::DuplicateId { as_device } if as_device.is_empty() => {
+                    return Err(format!("fault #{}: as_device must not be empty", index + 1).into());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Faults in effect for a device at `elapsed`; one-shot faults are recorded in `fired`
+    // (indices into `faults`) so they trigger only once per device
+    pub fn active(&self, device_id: &str, elapsed: Duration, fired: &mut HashSet<usize>) -> Vec<FaultKind> {
+        let now = elapsed.as_secs_f64();
+        self.faults
+            .iter()
+            .enumerate()
+            .filter(|(_, fault)| fault.applies_to(device_id) && now >= fault.at_secs)
+            .filter(|(index, fault)| match fault.duration_secs {
+                Some(duration) => now < fault.at_secs + duration,
+                None => fired.insert(*index),
+            })
+            .map(|(_, fault)| fault.kind.clone())
+            .collect()
+    }
+
+    // Per-device seed derived from the scenario seed, so devices do not all report the same values
+    pub fn device_seed(&self, device_id: &str) -> Option<u64> {
+        let seed = self.seed?;
+        // FNV-1a keeps the mapping stable across runs and platforms
+        let hash = device_id
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+        Some(seed ^ hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_scenario_parses() {
+        let scenario = Scenario::parse(include_str!("../scenario.example.toml"), false).unwrap();
+        assert_eq!(scenario.seed, Some(42));
+        assert!(scenario.faults.iter().any(|f| f.kind == FaultKind::BrokerDisconnect));
+        assert!(scenario
+            .faults
+            .iter()
+            .any(|f| matches!(f.kind, FaultKind::OutOfRange { capability: Capability::Temperature, .. })));
+    }
+
+    #[test]
+    fn test_fault_windows_and_one_shots() {
+        let scenario = Scenario::parse(
+            r#"{"faults": [
+                {"at_secs": 10, "duration_secs": 5, "devices": ["light-*"], "kind": "offline"},
+                {"at_secs": 12, "devices": ["thermostat-001"], "kind": "malformed_json"}
+            ]}"#,
+            true,
+        )
+        .unwrap();
+
+        let mut fired = HashSet::new();
+        let at = |secs| Duration::from_secs(secs);
+        assert!(scenario.active("light-bulb-001", at(9), &mut fired).is_empty());
+        assert_eq!(scenario.active("light-bulb-001", at(12), &mut fired), vec![FaultKind::Offline]);
+        assert!(scenario.active("light-bulb-001", at(15), &mut fired).is_empty());
+        assert!(scenario.active("smart-plug-001", at(12), &mut fired).is_empty());
+
+        assert_eq!(scenario.active("thermostat-001", at(13), &mut fired), vec![FaultKind::MalformedJson]);
+        assert!(scenario.active("thermostat-001", at(14), &mut fired).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_scenarios_are_rejected() {
+        assert!(Scenario::parse("[[faults]]\nat_secs = 1\nkind = \"burst\"\ncount = 0\n", false).is_err());
+        assert!(Scenario::parse("[[faults]]\nat_secs = -1\nkind = \"offline\"\n", false).is_err());
+        assert!(Scenario::parse("[[faults]]\nat_secs = 1\nkind = \"meteor_strike\"\n", false).is_err());
+    }
+
+    #[test]
+    fn test_device_seeds_are_stable_and_distinct() {
+        let scenario = Scenario { seed: Some(7), faults: Vec::new() };
+        assert_eq!(scenario.device_seed("a"), scenario.device_seed("a"));
+        assert_ne!(scenario.device_seed("a"), scenario.device_seed("b"));
+        assert_eq!(Scenario::default().device_seed("a"), None);
+    }
+}
@@ -0,0 +1,286 @@
+// Internal state of an emulated device: actuator values persist between ticks and are
+// changed by commands, sensor values are resampled on every telemetry tick
+
+use rand::Rng;
+use pozor_dom_shared::device::{Capability, CapabilityValue, DeviceDescriptor};
+use std::collections::BTreeMap;
+
+use crate::fleet::ValueRange;
+
+#[derive(Debug, Clone)]
+pub struct DeviceState {
+    values: BTreeMap<Capability, CapabilityValue>,
+    ranges: BTreeMap<Capability, ValueRange>,
+}
+
+// Used for numeric capabilities the fleet file does not give a range for
+fn default_range(capability: Capability) -> Option<ValueRange> {
+    match capability {
+        Capability::Temperature => Some(ValueRange::new(15.0, 25.0)),
+        Capability::Humidity => Some(ValueRange::new(30.0, 70.0)),
+        Capability::PowerMeter => Some(ValueRange::new(5.0, 1500.0)),
+        Capability::ThermostatSetpoint => Some(ValueRange::new(5.0, 35.0)),
+        Capability::Dimmer => Some(ValueRange::new(0.0, 100.0)),
+        _ => None,
+    }
+}
+
+impl DeviceState {
+    pub fn new(device: &DeviceDescriptor, overrides: &BTreeMap<Capability, ValueRange>) -> Self {
+        let ranges: BTreeMap<Capability, ValueRange> = device
+            .capabilities
+            .iter()
+            .filter_map(|capability| {
+                let range = overrides.get(capability).copied().or_else(|| default_range(*capability))?;
+                Some((*capability, range))
+            })
+            .collect();
+        let values = device
+            .capabilities
+            .iter()
+            .map(|capability| (*capability, initial_value(*capability, ranges.get(capability))))
+            .collect();
+        Self { values, ranges }
+    }
+
+    pub fn values(&self) -> &BTreeMap<Capability, CapabilityValue> {
+        &self.values
+    }
+
+    fn has(&self, capability: Capability) -> bool {
+        self.values.contains_key(&capability)
+    }
+
+    fn is_on(&self) -> bool {
+        self.values
+            .get(&Capability::Switch)
+            .and_then(CapabilityValue::as_bool)
+            .unwrap_or(true)
+    }
+
+    fn number(&self, capability: Capability) -> Option<f64> {
+        self.values.get(&capability).and_then(CapabilityValue::as_f64)
+    }
+
+    fn range(&self, capability: Capability) -> ValueRange {
+        self.ranges
+            .get(&capability)
+            .copied()
+            .or_else(|| default_range(capability))
+            .unwrap_or(ValueRange::new(0.0, 0.0))
+    }
+
+    fn sample(&self, capability: Capability, rng: &mut impl Rng) -> f64 {
+        let range = self.range(capability);
+        round2(rng.gen_range(range.min..=range.max))
+    }
+
+    fn set(&mut self, capability: Capability, value: impl Into<CapabilityValue>) {
+        self.values.insert(capability, value.into());
+    }
+
+    // Resamples sensor readings; actuator values only change through `apply`
+    pub fn tick(&mut self, rng: &mut impl Rng) {
+        let setpoint = self.number(Capability::ThermostatSetpoint);
+        let on = self.is_on();
+
+        if let Some(current) = self.number(Capability::Temperature) {
+            let next = match setpoint {
+                // Thermostats drift towards their target instead of jumping around it
+                Some(target) => current + (target - current) * 0.3 + rng.gen_range(-0.2..0.2),
+                None => self.sample(Capability::Temperature, rng),
+            };
+            self.set(Capability::Temperature, round2(next));
+        }
+        if self.has(Capability::Humidity) {
+            let humidity = self.sample(Capability::Humidity, rng);
+            self.set(Capability::Humidity, humidity);
+        }
+        if self.has(Capability::Contact) {
+            self.set(Capability::Contact, rng.gen_bool(0.1));
+        }
+        if self.has(Capability::Motion) {
+            self.set(Capability::Motion, rng.gen_bool(0.2));
+        }
+        if self.has(Capability::PowerMeter) {
+            let watts = if on { self.sample(Capability::PowerMeter, rng) } else { 0.0 };
+            self.set(Capability::PowerMeter, watts);
+        }
+    }
+
+    // Executes an action such as `turn_on`, `set_brightness 40` or `set_target 22`;
+    // Ok carries a short description for the command result, Err the reason it was rejected
+    pub fn apply(&mut self, action: &str) -> Result<String, String> {
+        let mut parts = action.split_whitespace();
+        let name = parts.next().unwrap_or_default();
+        let argument = parts.next();
+
+        match name {
+            "turn_on" | "turn_off" | "toggle" => {
+                self.require(Capability::Switch)?;
+                let on = match name {
+                    "turn_on" => true,
+                    "turn_off" => false,
+                    _ => !self.is_on(),
+                };
+                self.set(Capability::Switch, on);
+                if !on && self.has(Capability::PowerMeter) {
+                    self.set(Capability::PowerMeter, 0.0);
+                }
+                Ok(format!("switched {}", if on { "on" } else { "off" }))
+            }
+            "set_brightness" => {
+                self.require(Capability::Dimmer)?;
+                let level = parse_number(argument)?;
+                let range = self.range(Capability::Dimmer);
+                if !range.contains(level) {
+                    return Err(format!("brightness {} is outside {}..{}", level, range.min, range.max));
+                }
+                self.set(Capability::Dimmer, level.round());
+                // Dimming a light that is off turns it on, dimming to zero turns it off
+                if self.has(Capability::Switch) {
+                    self.set(Capability::Switch, level > 0.0);
+                }
+                Ok(format!("brightness set to {:.0}%", level))
+            }
+            "set_target" => {
+                self.require(Capability::ThermostatSetpoint)?;
+                let target = parse_number(argument)?;
+                let range = self.range(Capability::ThermostatSetpoint);
+                if !range.contains(target) {
+                    return Err(format!("target {} is outside {}..{}", target, range.min, range.max));
+                }
+                self.set(Capability::ThermostatSetpoint, round2(target));
+                Ok(format!("target set to {:.1}°C", target))
+            }
+            "lock" | "unlock" => {
+                self.require(Capability::Lock)?;
+                let locked = name == "lock";
+                self.set(Capability::Lock, locked);
+                Ok(if locked { "locked" } else { "unlocked" }.to_string())
+            }
+            // Read-only requests are answered by the next telemetry message
+            _ if name.starts_with("get_") || name == "status" => Ok("state reported".to_string()),
+            "" => Err("empty action".to_string()),
+            _ => Err(format!("unknown action '{}'", name)),
+        }
+    }
+
+    fn require(&self, capability: Capability) -> Result<(), String> {
+        if self.has(capability) {
+            Ok(())
+        } else {
+            Err(format!("device has no {} capability", capability.as_str()))
+        }
+    }
+}
+
+fn initial_value(capability: Capability, range: Option<&ValueRange>) -> CapabilityValue {
+    let midpoint = range.map(|range| round2((range.min + range.max) / 2.0));
+    match capability {
+        Capability::Temperature | Capability::Humidity => CapabilityValue::Number(midpoint.unwrap_or(20.0)),
+        Capability::Switch => CapabilityValue::Bool(false),
+        Capability::Dimmer => CapabilityValue::Number(range.map_or(100.0, |range| range.max)),
+        Capability::Contact => CapabilityValue::Bool(false),
+        Capability::Motion => CapabilityValue::Bool(false),
+        Capability::PowerMeter => CapabilityValue::Number(0.0),
+        Capability::ThermostatSetpoint => {
+            let setpoint = range.map_or(21.0, |range| 21.0_f64.clamp(range.min, range.max));
+            CapabilityValue::Number(setpoint)
+        }
+        Capability::Lock => CapabilityValue::Bool(true),
+    }
+}
+
+fn parse_number(argument: Option<&str>) -> Result<f64, String> {
+    let argument = argument.ok_or_else(|| "missing numeric argument".to_string())?;
+    argument
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| format!("'{}' is not a number", argument))
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pozor_dom_shared::device::DeviceType;
+
+    fn state(device_type: DeviceType, capabilities: &[Capability]) -> DeviceState {
+        DeviceState::new(
+            &DeviceDescriptor {
+                device_id: "test-device".to_string(),
+                channel: "WiFi".to_string(),
+                device_type,
+                capabilities: capabilities.to_vec(),
+            },
+            &BTreeMap::new(),
+        )
+    }
+
+    #[test]
+    fn test_light_commands_mutate_state() {
+        let mut light = state(DeviceType::Light, &[Capability::Switch, Capability::Dimmer]);
+        light.apply("turn_on").unwrap();
+        assert_eq!(light.values()[&Capability::Switch], CapabilityValue::Bool(true));
+
+        light.apply("set_brightness 40").unwrap();
+        assert_eq!(light.values()[&Capability::Dimmer], CapabilityValue::Number(40.0));
+
+        light.apply("set_brightness 0").unwrap();
+        assert_eq!(light.values()[&Capability::Switch], CapabilityValue::Bool(false));
+
+        assert!(light.apply("set_brightness 140").is_err());
+        assert!(light.apply("set_brightness bright").is_err());
+        assert!(light.apply("set_target 22").is_err());
+    }
+
+    #[test]
+    fn test_thermostat_drifts_towards_target() {
+        let mut thermostat = state(DeviceType::Thermostat, &[Capability::Temperature, Capability::ThermostatSetpoint]);
+        thermostat.apply("set_target 28").unwrap();
+        assert_eq!(thermostat.values()[&Capability::ThermostatSetpoint], CapabilityValue::Number(28.0));
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..30 {
+            thermostat.tick(&mut rng);
+        }
+        let temperature = thermostat.values()[&Capability::Temperature].as_f64().unwrap();
+        assert!((temperature - 28.0).abs() < 1.0, "temperature {} did not approach target", temperature);
+    }
+
+    #[test]
+    fn test_plug_draws_no_power_when_off() {
+        let mut plug = state(DeviceType::SmartPlug, &[Capability::Switch, Capability::PowerMeter]);
+        plug.apply("turn_off").unwrap();
+        plug.tick(&mut rand::thread_rng());
+        assert_eq!(plug.values()[&Capability::PowerMeter], CapabilityValue::Number(0.0));
+
+        assert_eq!(plug.apply("toggle").unwrap(), "switched on");
+        assert!(plug.apply("self_destruct").is_err());
+        assert!(plug.apply("get_temperature").is_ok());
+    }
+
+    #[test]
+    fn test_samples_stay_within_configured_ranges() {
+        let descriptor = DeviceDescriptor {
+            device_id: "test-device".to_string(),
+            channel: "WiFi".to_string(),
+            device_type: DeviceType::Sensor,
+            capabilities: vec![Capability::Temperature, Capability::Humidity],
+        };
+        let ranges = BTreeMap::from([(Capability::Humidity, ValueRange::new(40.0, 41.0))]);
+        let mut sensor = DeviceState::new(&descriptor, &ranges);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            sensor.tick(&mut rng);
+            let humidity = sensor.values()[&Capability::Humidity].as_f64().unwrap();
+            assert!((40.0..=41.0).contains(&humidity), "humidity {} out of range", humidity);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+use chrono::{TimeDelta, Utc};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, pbkdf2};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::handshake::server::Request;
+use warp::Filter;
+use warp::http::StatusCode;
+use crate::database::{self, Database};
+
+// Cookie the login page stores the session token in, for the browser dashboard
+pub const SESSION_COOKIE: &str = "pozor_session";
+
+pub const SESSION_TTL_HOURS: i64 = 24;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+// OWASP's recommendation for PBKDF2-HMAC-SHA256
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const SALT_LENGTH: usize = 16;
+const TOKEN_LENGTH: usize = 32;
+
+// Ordered by privilege: each role can do everything the ones before it can
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // Reads devices, history, messages, automations and alerts
+    Viewer,
+    // Also sends device commands, activates scenes and edits rules, scenes and schedules
+    Operator,
+    // Also toggles the cloud relay, edits alert thresholds and manages users
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "viewer" => Some(Role::Viewer),
+            "operator" => Some(Role::Operator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct User {
+    pub username: String,
+    pub role: Role,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub token: String,
+    pub user: User,
+    pub expires_at: String,
+}
+
+// Stored as "pbkdf2-sha256$<iterations>$<salt>$<hash>" so the cost can be raised later
+pub fn hash_password(password: &str) -> String {
+    hash_password_with(password, PBKDF2_ITERATIONS)
+}
+
+fn hash_password_with(password: &str, iterations: u32) -> String {
+    let mut salt = [0u8; SALT_LENGTH];
+    SystemRandom::new().fill(&mut salt).expect("system random number generator failed");
+    let mut hash = [0u8; digest::SHA256_OUTPUT_LEN];
+    let rounds = NonZeroU32::new(iterations).expect("iterations must not be zero");
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, rounds, &salt, password.as_bytes(), &mut hash);
+    format!("pbkdf2-sha256${}${}${}", iterations, STANDARD_NO_PAD.encode(salt), STANDARD_NO_PAD.encode(hash))
+}
+
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let parts: Vec<&str> = stored.split('$').collect();
+    let ["pbkdf2-sha256", iterations, salt, hash] = parts.as_slice() else {
+        return false;
+    };
+    let (Some(rounds), Ok(salt), Ok(hash)) = (
+        iterations.parse().ok().and_then(NonZeroU32::new),
+        STANDARD_NO_PAD.decode(salt),
+        STANDARD_NO_PAD.decode(hash),
+    ) else {
+        return false;
+    };
+    pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, rounds, &salt, password.as_bytes(), &hash).is_ok()
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; TOKEN_LENGTH];
+    SystemRandom::new().fill(&mut bytes).expect("system random number generator failed");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// Sessions are looked up by digest so that a copy of the database does not hand out live tokens
+fn token_digest(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+pub fn validate_username(username: &str) -> Result<(), String> {
+    let valid = !username.is_empty()
+        && username.len() <= 64
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err("username must be 1-64 characters of letters, digits, '-', '_' or '.'".to_string())
+    }
+}
+
+pub fn validate_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!("password must be at least {} characters", MIN_PASSWORD_LENGTH));
+    }
+    Ok(())
+}
+
+pub struct Accounts {
+    db: Arc<Database>,
+    // Verified against for unknown usernames, so that a login takes as long whether or not the user exists
+    dummy_hash: String,
+}
+
+impl Accounts {
+    // `admin_password` (hub.admin_password) is only used for the "admin" account of a fresh hub
+    pub fn new(db: Arc<Database>, admin_password: Option<String>) -> Self {
+        let accounts = Self { db, dummy_hash: hash_password(&random_token()) };
+        if let Err(e) = accounts.bootstrap(admin_password) {
+            eprintln!("❌ Failed to create the initial admin account: {}", e);
+        }
+        accounts
+    }
+
+    // A fresh hub gets an "admin" account so that someone can log in and create the others
+    fn bootstrap(&self, admin_password: Option<String>) -> rusqlite::Result<()> {
+        if self.db.count_users()? > 0 {
+            return Ok(());
+        }
+        let (password, generated) = match admin_password {
+            Some(password) => (password, false),
+            None => (random_token(), true),
+        };
+        let admin = User { username: "admin".to_string(), role: Role::Admin, created_at: Utc::now().to_rfc3339() };
+        self.db.create_user(&admin, &hash_password(&password))?;
+        if generated {
+            println!("🔑 Created user 'admin' with password: {}", password);
+            println!("   Change it with PUT /api/users/admin, or set hub.admin_password before the first start");
+        } else {
+            println!("🔑 Created user 'admin' with the password from hub.admin_password");
+        }
+        Ok(())
+    }
+
+    pub fn login(&self, username: &str, password: &str) -> rusqlite::Result<Option<Session>> {
+        let stored = self.db.get_password_hash(username)?;
+        let verified = verify_password(password, stored.as_deref().unwrap_or(&self.dummy_hash));
+        let Some(user) = self.db.get_user(username)?.filter(|_| verified && stored.is_some()) else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        self.db.delete_expired_sessions(&database::format_sample_time(now))?;
+        let token = random_token();
+        let expires_at = database::format_sample_time(now + TimeDelta::hours(SESSION_TTL_HOURS));
+        self.db.insert_session(&token_digest(&token), &user.username, &database::format_sample_time(now), &expires_at)?;
+        Ok(Some(Session { token, user, expires_at }))
+    }
+
+    pub fn authenticate(&self, token: &str) -> rusqlite::Result<Option<User>> {
+        self.db.get_session_user(&token_digest(token), &database::format_sample_time(Utc::now()))
+    }
+
+    pub fn logout(&self, token: &str) -> rusqlite::Result<bool> {
+        self.db.delete_session(&token_digest(token))
+    }
+
+    // Changing a password signs the user out everywhere
+    pub fn set_password(&self, username: &str, password: &str) -> rusqlite::Result<bool> {
+        let updated = self.db.set_password_hash(username, &hash_password(password))?;
+        if updated {
+            self.db.delete_user_sessions(username)?;
+        }
+        Ok(updated)
+    }
+}
+
+// `Authorization: Bearer <token>` first, then the dashboard's session cookie
+fn select_token(authorization: Option<&str>, cookie: Option<&str>) -> Option<String> {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(cookie)
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+fn cookie_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// WebSocket handshakes may also pass the token as `?token=`, since browsers cannot set headers there
+pub fn handshake_token(request: &Request) -> Option<String> {
+    let header = |name: &str| request.headers().get(name).and_then(|value| value.to_str().ok());
+    let cookie = header(warp::http::header::COOKIE.as_str()).and_then(|cookie| cookie_value(cookie, SESSION_COOKIE));
+    select_token(header(warp::http::header::AUTHORIZATION.as_str()), cookie).or_else(|| {
+        request
+            .uri()
+            .query()?
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == "token")
+            .map(|(_, token)| token.to_string())
+    })
+}
+
+#[derive(Debug)]
+pub enum AuthRejection {
+    Unauthenticated,
+    Forbidden(Role),
+    Unavailable,
+}
+
+impl warp::reject::Reject for AuthRejection {}
+
+// Session token of the request, if any; used by logout
+pub fn token() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .map(|authorization: Option<String>, cookie: Option<String>| select_token(authorization.as_deref(), cookie.as_deref()))
+}
+
+// The logged-in user, or a 401
+pub fn authenticated(accounts: Arc<Accounts>) -> impl Filter<Extract = (User,), Error = warp::Rejection> + Clone {
+    token().and_then(move |token: Option<String>| {
+        let accounts = Arc::clone(&accounts);
+        async move {
+            let token = token.ok_or_else(|| warp::reject::custom(AuthRejection::Unauthenticated))?;
+            match tokio::task::block_in_place(|| accounts.authenticate(&token)) {
+                Ok(Some(user)) => Ok(user),
+                Ok(None) => Err(warp::reject::custom(AuthRejection::Unauthenticated)),
+                Err(e) => {
+                    eprintln!("Database error checking a session: {}", e);
+                    Err(warp::reject::custom(AuthRejection::Unavailable))
+                }
+            }
+        }
+    })
+}
+
+// The logged-in user if they have at least `role`; goes after the path and method so other routes are unaffected
+pub fn authorized(accounts: Arc<Accounts>, role: Role) -> impl Filter<Extract = (User,), Error = warp::Rejection> + Clone {
+    authenticated(accounts).and_then(move |user: User| async move {
+        if user.role >= role {
+            Ok(user)
+        } else {
+            Err(warp::reject::custom(AuthRejection::Forbidden(role)))
+        }
+    })
+}
+
+// Same as `authorized` for handlers that do not need the user
+pub fn require(accounts: Arc<Accounts>, role: Role) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    authorized(accounts, role).map(|_: User| ()).untuple_one()
+}
+
+pub async fn handle_rejection(rejection: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    let (status, message) = match rejection.find::<AuthRejection>() {
+        Some(AuthRejection::Unauthenticated) => (StatusCode::UNAUTHORIZED, "authentication required".to_string()),
+        Some(AuthRejection::Forbidden(role)) => (StatusCode::FORBIDDEN, format!("requires the {} role", role.as_str())),
+        Some(AuthRejection::Unavailable) => (StatusCode::SERVICE_UNAVAILABLE, "accounts are unavailable".to_string()),
+        None => return Err(rejection),
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({ "error": message })), status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hashes() {
+        let stored = hash_password_with("correct horse", 1_000);
+        assert!(stored.starts_with("pbkdf2-sha256$1000$"));
+        assert!(verify_password("correct horse", &stored));
+        assert!(!verify_password("correct horsf", &stored));
+        assert_ne!(stored, hash_password_with("correct horse", 1_000), "salts must differ");
+        assert!(!verify_password("correct horse", "plain-text"));
+        assert!(!verify_password("correct horse", "pbkdf2-sha256$0$AAAA$AAAA"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_login_sessions_and_roles() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let viewer = User { username: "guest".to_string(), role: Role::Viewer, created_at: Utc::now().to_rfc3339() };
+        db.create_user(&viewer, &hash_password_with("guest-password", 1_000)).unwrap();
+        let accounts = Arc::new(Accounts::new(Arc::clone(&db), None));
+        assert_eq!(db.count_admins().unwrap(), 0, "no bootstrap admin when users exist");
+
+        assert!(accounts.login("guest", "wrong-password").unwrap().is_none());
+        assert!(accounts.login("nobody", "guest-password").unwrap().is_none());
+        let session = accounts.login("guest", "guest-password").unwrap().unwrap();
+        assert_eq!(accounts.authenticate(&session.token).unwrap(), Some(viewer));
+
+        let read = require(Arc::clone(&accounts), Role::Viewer);
+        let write = require(Arc::clone(&accounts), Role::Operator);
+        let bearer = format!("Bearer {}", session.token);
+        assert!(warp::test::request().header("authorization", &bearer).filter(&read).await.is_ok());
+        assert!(warp::test::request().header("cookie", format!("{}={}", SESSION_COOKIE, session.token)).filter(&read).await.is_ok());
+        assert!(warp::test::request().filter(&read).await.is_err());
+
+        let forbidden = warp::test::request().header("authorization", &bearer).filter(&write).await.unwrap_err();
+        assert!(matches!(forbidden.find::<AuthRejection>(), Some(AuthRejection::Forbidden(Role::Operator))));
+
+        assert!(accounts.set_password("guest", "new-guest-password").unwrap());
+        assert_eq!(accounts.authenticate(&session.token).unwrap(), None, "password change ends sessions");
+    }
+
+    #[test]
+    fn test_handshake_token_sources() {
+        let request = |uri: &str, header: Option<(&str, &str)>| {
+            let mut builder = Request::builder().uri(uri);
+            if let Some((name, value)) = header {
+                builder = builder.header(name, value);
+            }
+            builder.body(()).unwrap()
+        };
+        assert_eq!(handshake_token(&request("/", Some(("authorization", "Bearer abc")))), Some("abc".to_string()));
+        assert_eq!(handshake_token(&request("/", Some(("cookie", "theme=dark; pozor_session=def")))), Some("def".to_string()));
+        assert_eq!(handshake_token(&request("/?token=ghi", None)), Some("ghi".to_string()));
+        assert_eq!(handshake_token(&request("/", None)), None);
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use pozor_dom_shared::envelope::DeviceCommand;
+use crate::accounts::{Role, User};
+use crate::database::Database;
+
+// Lets a user command the devices matching `device_id` (an id, `prefix*` or `*`);
+// an empty `actions` list allows every action
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceAcl {
+    // Taken from the URL when the entry is saved
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub device_id: String,
+    #[serde(default)]
+    pub actions: Vec<String>,
+}
+
+impl DeviceAcl {
+    pub fn validate(&self) -> Result<(), String> {
+        let pattern = self.device_id.strip_suffix('*').unwrap_or(&self.device_id);
+        if self.device_id.is_empty() || pattern.contains('*') {
+            return Err("device_id must be a device id, a prefix ending in '*' or '*'".to_string());
+        }
+        if self.actions.iter().any(|action| action.trim().is_empty()) {
+            return Err("actions must not be empty strings".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, device_id: &str, action: &str) -> bool {
+        let device_matches = match self.device_id.strip_suffix('*') {
+            Some(prefix) => device_id.starts_with(prefix),
+            None => self.device_id == device_id,
+        };
+        device_matches && (self.actions.is_empty() || self.actions.iter().any(|allowed| allowed == action))
+    }
+}
+
+// Only matching commands pass, so a user without entries may command no device; `*` gives full access
+pub fn permits(entries: &[DeviceAcl], device_id: &str, action: &str) -> bool {
+    entries.iter().any(|entry| entry.matches(device_id, action))
+}
+
+// Checked before a user's command reaches MQTT; rules, scenes started by schedules and other
+// hub-initiated commands are not subject to it
+pub struct DeviceAccess {
+    db: Arc<Database>,
+}
+
+impl DeviceAccess {
+    pub fn new(db: Arc<Database>) -> Self {
+        Self { db }
+    }
+
+    // The commands the user may not send; admins may send anything
+    pub fn denied<'a>(&self, user: &User, commands: &'a [DeviceCommand]) -> rusqlite::Result<Vec<&'a DeviceCommand>> {
+        if user.role == Role::Admin {
+            return Ok(Vec::new());
+        }
+        let entries = self.db.load_device_acls(&user.username)?;
+        Ok(commands.iter().filter(|command| !permits(&entries, &command.device_id, &command.action)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn entry(device_id: &str, actions: &[&str]) -> DeviceAcl {
+        DeviceAcl {
+            username: "kid".to_string(),
+            device_id: device_id.to_string(),
+            actions: actions.iter().map(|action| action.to_string()).collect(),
+        }
+    }
+
+    fn command(device_id: &str, action: &str) -> DeviceCommand {
+        DeviceCommand {
+            command_id: String::new(),
+            device_id: device_id.to_string(),
+            channel: "ZigBee".to_string(),
+            action: action.to_string(),
+            timestamp: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_entries_match_devices_and_actions() {
+        let entries = vec![entry("light-kids-*", &[]), entry("thermostat-kids", &["set_target"])];
+        assert!(permits(&entries, "light-kids-001", "turn_on"));
+        assert!(permits(&entries, "thermostat-kids", "set_target"));
+        assert!(!permits(&entries, "thermostat-kids", "turn_off"));
+        assert!(!permits(&entries, "lock-front-001", "unlock"));
+        assert!(permits(&[entry("*", &["turn_on"])], "lock-front-001", "turn_on"));
+        assert!(!permits(&[], "lock-front-001", "unlock"), "no entries means no devices");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(entry("light-*", &["turn_on"]).validate().is_ok());
+        assert!(entry("*", &[]).validate().is_ok());
+        assert!(entry("", &[]).validate().is_err());
+        assert!(entry("light-*-001", &[]).validate().is_err());
+        assert!(entry("light-001", &[" "]).validate().is_err());
+    }
+
+    #[test]
+    fn test_denied_commands() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        db.save_device_acl(&entry("light-kids-*", &[])).unwrap();
+        let access = DeviceAccess::new(Arc::clone(&db));
+
+        let commands = vec![command("light-kids-001", "turn_on"), command("lock-front-001", "unlock")];
+        let kid = User { username: "kid".to_string(), role: Role::Operator, created_at: Utc::now().to_rfc3339() };
+        let denied = access.denied(&kid, &commands).unwrap();
+        assert_eq!(denied.iter().map(|command| command.device_id.as_str()).collect::<Vec<_>>(), vec!["lock-front-001"]);
+
+        let admin = User { username: "kid".to_string(), role: Role::Admin, ..kid.clone() };
+        assert!(access.denied(&admin, &commands).unwrap().is_empty());
+
+        // Another operator without entries gets nothing, whatever the kid's list holds
+        let operator = User { username: "operator".to_string(), ..kid.clone() };
+        assert_eq!(access.denied(&operator, &commands).unwrap().len(), 2);
+        db.save_device_acl(&DeviceAcl { username: "operator".to_string(), ..entry("*", &[]) }).unwrap();
+        assert!(access.denied(&operator, &commands).unwrap().is_empty());
+
+        // Removing a user's last entry takes their devices away instead of lifting the list
+        db.delete_device_acl("kid", "light-kids-*").unwrap();
+        assert_eq!(access.denied(&kid, &commands).unwrap().len(), 2);
+        assert!(access.denied(&operator, &commands).unwrap().is_empty());
+    }
+}
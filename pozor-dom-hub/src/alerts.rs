@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use pozor_dom_shared::dashboard::DeviceTelemetry;
+use pozor_dom_shared::envelope::{AlertCondition, AlertEvent, AlertMetric, AlertState};
+use crate::database::Database;
+use crate::notify::{NotificationDispatcher, SinkConfig};
+
+// Thresholds stored under this id apply to every device that has none of its own
+pub const DEFAULT_THRESHOLDS_ID: &str = "*";
+
+const DEFAULT_DEBOUNCE_READINGS: u32 = 2;
+
+const METRICS: [AlertMetric; 3] = [AlertMetric::Temperature, AlertMetric::Humidity, AlertMetric::SignalStrength];
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct Band {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AlertThresholds {
+    // Taken from the URL when saved; "*" for the defaults
+    #[serde(default)]
+    pub device_id: String,
+    #[serde(default)]
+    pub temperature: Band,
+    #[serde(default)]
+    pub humidity: Band,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal_strength_min: Option<f64>,
+    // Consecutive out-of-band readings needed before an alert is raised
+    #[serde(default = "default_debounce_readings")]
+    pub debounce_readings: u32,
+    // Where raised and cleared alerts are delivered
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notify: Vec<SinkConfig>,
+}
+
+fn default_debounce_readings() -> u32 {
+    DEFAULT_DEBOUNCE_READINGS
+}
+
+// How far back inside the band a value has to come before its alert clears
+fn hysteresis(metric: AlertMetric) -> f64 {
+    match metric {
+        AlertMetric::Temperature => 0.5,
+        AlertMetric::Humidity => 2.0,
+        AlertMetric::SignalStrength => 3.0,
+    }
+}
+
+fn reading(telemetry: &DeviceTelemetry, metric: AlertMetric) -> Option<f64> {
+    match metric {
+        AlertMetric::Temperature => telemetry.temperature(),
+        AlertMetric::Humidity => telemetry.humidity(),
+        AlertMetric::SignalStrength => Some(telemetry.signal_strength as f64),
+    }
+}
+
+impl AlertThresholds {
+    pub fn validate(&self, exec_allowlist: &[String]) -> Result<(), String> {
+        for (name, band) in [("temperature", self.temperature), ("humidity", self.humidity)] {
+            if band.min.into_iter().chain(band.max).any(|value| !value.is_finite()) {
+                return Err(format!("{} thresholds must be numbers", name));
+            }
+            if let (Some(min), Some(max)) = (band.min, band.max)
+                && min >= max
+            {
+                return Err(format!("{} min must be below max", name));
+            }
+        }
+        if self.signal_strength_min.is_some_and(|min| !min.is_finite()) {
+            return Err("signal_strength_min must be a number".to_string());
+        }
+        if self.debounce_readings == 0 {
+            return Err("debounce_readings must be at least 1".to_string());
+        }
+        for sink in &self.notify {
+            sink.validate(exec_allowlist)?;
+        }
+        Ok(())
+    }
+
+    fn band(&self, metric: AlertMetric) -> Band {
+        match metric {
+            AlertMetric::Temperature => self.temperature,
+            AlertMetric::Humidity => self.humidity,
+            AlertMetric::SignalStrength => Band { min: self.signal_strength_min, max: None },
+        }
+    }
+}
+
+fn thresholds_for<'a>(thresholds: &'a HashMap<String, AlertThresholds>, device_id: &str) -> Option<&'a AlertThresholds> {
+    thresholds.get(device_id).or_else(|| thresholds.get(DEFAULT_THRESHOLDS_ID))
+}
+
+#[derive(Default)]
+struct MetricState {
+    raised: Option<AlertEvent>,
+    // Consecutive out-of-band readings that have not raised an alert yet
+    pending: Option<(AlertCondition, u32)>,
+}
+
+pub struct AlertMonitor {
+    db: Arc<Database>,
+    notifications: Arc<NotificationDispatcher>,
+    thresholds: RwLock<HashMap<String, AlertThresholds>>,
+    states: Mutex<HashMap<(String, AlertMetric), MetricState>>,
+}
+
+impl AlertMonitor {
+    pub fn new(db: Arc<Database>, notifications: Arc<NotificationDispatcher>) -> Self {
+        let monitor = Self {
+            db,
+            notifications,
+            thresholds: RwLock::new(HashMap::new()),
+            states: Mutex::new(HashMap::new()),
+        };
+        match monitor.reload() {
+            Ok(count) => println!("🚨 Loaded alert thresholds for {} devices", count),
+            Err(e) => eprintln!("❌ Failed to load alert thresholds: {}", e),
+        }
+
+        // Alerts still raised when the hub stopped are cleared by the next in-band reading, not raised again
+        match monitor.db.load_open_alerts() {
+            Ok(open) => {
+                let mut states = monitor.states.lock().unwrap();
+                for alert in open {
+                    let state = MetricState { raised: Some(alert.clone()), pending: None };
+                    states.insert((alert.device_id, alert.metric), state);
+                }
+            }
+            Err(e) => eprintln!("❌ Failed to load open alerts: {}", e),
+        }
+        monitor
+    }
+
+    // Re-reads the thresholds after they were changed through the API
+    pub fn reload(&self) -> rusqlite::Result<usize> {
+        let thresholds = self.db.load_alert_thresholds()?;
+        let count = thresholds.len();
+        *self.thresholds.write().unwrap() =
+            thresholds.into_iter().map(|thresholds| (thresholds.device_id.clone(), thresholds)).collect();
+        Ok(count)
+    }
+
+    pub fn exec_allowlist(&self) -> Vec<String> {
+        self.notifications.exec_allowlist()
+    }
+
+    // Sinks of the thresholds that apply to the device
+    fn sinks_for(&self, device_id: &str) -> Vec<SinkConfig> {
+        let thresholds = self.thresholds.read().unwrap();
+        thresholds_for(&thresholds, device_id).map(|config| config.notify.clone()).unwrap_or_default()
+    }
+
+    pub fn active(&self) -> Vec<AlertEvent> {
+        let mut active: Vec<AlertEvent> =
+            self.states.lock().unwrap().values().filter_map(|state| state.raised.clone()).collect();
+        active.sort_by_key(|event| std::cmp::Reverse(event.timestamp));
+        active
+    }
+
+    // Evaluates a reading, stores the alerts it raised or cleared (blocking database access)
+    // and hands them to the notification sinks
+    pub fn process(&self, telemetry: &DeviceTelemetry) -> Vec<AlertEvent> {
+        let mut events = self.evaluate(telemetry, Utc::now());
+        let sinks = if events.is_empty() { Vec::new() } else { self.sinks_for(&telemetry.device_id) };
+        for event in events.iter_mut() {
+            match self.db.insert_alert(event) {
+                Ok(id) => event.id = id,
+                Err(e) => eprintln!("❌ Failed to store alert for {}: {}", event.device_id, e),
+            }
+            if event.state == AlertState::Raised {
+                let mut states = self.states.lock().unwrap();
+                if let Some(raised) = states.get_mut(&(event.device_id.clone(), event.metric)).and_then(|state| state.raised.as_mut()) {
+                    raised.id = event.id;
+                }
+            }
+            self.notifications.dispatch(event.clone(), sinks.clone());
+        }
+        events
+    }
+
+    fn evaluate(&self, telemetry: &DeviceTelemetry, now: DateTime<Utc>) -> Vec<AlertEvent> {
+        let thresholds = self.thresholds.read().unwrap();
+        let config = thresholds_for(&thresholds, &telemetry.device_id);
+        let debounce_readings = config.map_or(DEFAULT_DEBOUNCE_READINGS, |config| config.debounce_readings);
+        let mut states = self.states.lock().unwrap();
+        let mut events = Vec::new();
+
+        for metric in METRICS {
+            let Some(value) = reading(telemetry, metric) else {
+                continue;
+            };
+            let band = config.map(|config| config.band(metric)).unwrap_or_default();
+            let state = states.entry((telemetry.device_id.clone(), metric)).or_default();
+
+            if let Some(raised) = &state.raised {
+                // Also clears alerts whose threshold was removed or moved past the value
+                let back_in_band = match raised.condition {
+                    AlertCondition::Above => band.max.is_none_or(|max| value <= max - hysteresis(metric)),
+                    AlertCondition::Below => band.min.is_none_or(|min| value >= min + hysteresis(metric)),
+                };
+                if !back_in_band {
+                    continue;
+                }
+                events.push(AlertEvent { id: 0, value, state: AlertState::Cleared, timestamp: now, ..raised.clone() });
+                state.raised = None;
+            }
+
+            let outside = match (band.min, band.max) {
+                (_, Some(max)) if value > max => Some((AlertCondition::Above, max)),
+                (Some(min), _) if value < min => Some((AlertCondition::Below, min)),
+                _ => None,
+            };
+            let Some((condition, threshold)) = outside else {
+                state.pending = None;
+                continue;
+            };
+            let count = match state.pending {
+                Some((pending, count)) if pending == condition => count + 1,
+                _ => 1,
+            };
+            if count < debounce_readings {
+                state.pending = Some((condition, count));
+                continue;
+            }
+
+            let alert = AlertEvent {
+                id: 0,
+                device_id: telemetry.device_id.clone(),
+                metric,
+                condition,
+                threshold,
+                value,
+                state: AlertState::Raised,
+                timestamp: now,
+            };
+            state.raised = Some(alert.clone());
+            state.pending = None;
+            events.push(alert);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pozor_dom_shared::device::{Capability, DeviceType};
+    use std::collections::BTreeMap;
+    use crate::shutdown::Shutdown;
+
+    fn telemetry(temperature: f64, signal_strength: i32) -> DeviceTelemetry {
+        DeviceTelemetry {
+            device_id: "device-wifi-001".to_string(),
+            channel: "WiFi".to_string(),
+            device_type: DeviceType::Sensor,
+            capabilities: BTreeMap::from([(Capability::Temperature, temperature.into())]),
+            signal_strength,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn monitor(thresholds: serde_json::Value) -> AlertMonitor {
+        let thresholds: AlertThresholds = serde_json::from_value(thresholds).unwrap();
+        thresholds.validate(&[]).unwrap();
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        AlertMonitor {
+            notifications: Arc::new(NotificationDispatcher::new(Arc::clone(&db), Vec::new(), Shutdown::new())),
+            db,
+            thresholds: RwLock::new(HashMap::from([(DEFAULT_THRESHOLDS_ID.to_string(), thresholds)])),
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn states(events: &[AlertEvent]) -> Vec<(AlertMetric, AlertState)> {
+        events.iter().map(|event| (event.metric, event.state)).collect()
+    }
+
+    #[test]
+    fn test_debounce_and_hysteresis() {
+        let monitor = monitor(serde_json::json!({"device_id": "*", "temperature": {"min": 16, "max": 28}}));
+        let now = Utc::now();
+
+        // One spike is not enough, two readings in a row are
+        assert!(monitor.evaluate(&telemetry(29.0, -50), now).is_empty());
+        assert!(monitor.evaluate(&telemetry(27.0, -50), now).is_empty());
+        assert!(monitor.evaluate(&telemetry(29.0, -50), now).is_empty());
+        let raised = monitor.evaluate(&telemetry(29.5, -50), now);
+        assert_eq!(states(&raised), vec![(AlertMetric::Temperature, AlertState::Raised)]);
+        assert_eq!(raised[0].condition, AlertCondition::Above);
+        assert_eq!(raised[0].threshold, 28.0);
+        assert_eq!(monitor.active().len(), 1);
+
+        // Just under the threshold is within the hysteresis margin
+        assert!(monitor.evaluate(&telemetry(27.8, -50), now).is_empty());
+        let cleared = monitor.evaluate(&telemetry(27.4, -50), now);
+        assert_eq!(states(&cleared), vec![(AlertMetric::Temperature, AlertState::Cleared)]);
+        assert!(monitor.active().is_empty());
+    }
+
+    #[test]
+    fn test_signal_strength_and_removed_thresholds() {
+        let monitor = monitor(serde_json::json!({"signal_strength_min": -80, "debounce_readings": 1}));
+        let now = Utc::now();
+
+        let raised = monitor.evaluate(&telemetry(20.0, -85), now);
+        assert_eq!(states(&raised), vec![(AlertMetric::SignalStrength, AlertState::Raised)]);
+        assert!(monitor.evaluate(&telemetry(20.0, -85), now).is_empty(), "An alert is raised only once");
+
+        monitor.thresholds.write().unwrap().clear();
+        let cleared = monitor.evaluate(&telemetry(20.0, -85), now);
+        assert_eq!(states(&cleared), vec![(AlertMetric::SignalStrength, AlertState::Cleared)]);
+    }
+
+    #[test]
+    fn test_invalid_thresholds() {
+        let inverted: AlertThresholds = serde_json::from_value(serde_json::json!({"humidity": {"min": 70, "max": 30}})).unwrap();
+        assert!(inverted.validate(&[]).is_err());
+        let no_debounce: AlertThresholds = serde_json::from_value(serde_json::json!({"debounce_readings": 0})).unwrap();
+        assert!(no_debounce.validate(&[]).is_err());
+        let exec: AlertThresholds =
+            serde_json::from_value(serde_json::json!({"notify": [{"type": "exec", "program": "/usr/bin/notify-send"}]})).unwrap();
+        assert!(exec.validate(&[]).is_err());
+        assert!(exec.validate(&["/usr/bin/notify-send".to_string()]).is_ok());
+    }
+}
@@ -0,0 +1,326 @@
+// Command line of pozor-dom-hub: `serve` runs the hub, the other subcommands work on its
+// database and MQTT broker directly and exit
+use std::path::PathBuf;
+use std::sync::Arc;
+use chrono::Utc;
+use clap::{Args, Parser, Subcommand};
+use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
+use serde::Serialize;
+use pozor_dom_shared::config::{Config, ConfigArgs};
+use pozor_dom_shared::dashboard::DeviceSummary;
+use pozor_dom_shared::envelope::{AlertEvent, CommandAck, CommandResult, CommandStatus, DeviceCommand, Envelope, Payload};
+use crate::accounts::User;
+use crate::acl::DeviceAcl;
+use crate::alerts::AlertThresholds;
+use crate::commands::COMMAND_TIMEOUT;
+use crate::database::Database;
+use crate::migrations;
+use crate::mqtt;
+use crate::rules::Rule;
+use crate::scenes::Scene;
+use crate::scheduler::Schedule;
+
+type CliResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Parser)]
+#[command(name = "pozor-dom-hub", version, about = "Pozor-dom hub: MQTT bridge, automations, web dashboard and cloud relay link")]
+pub struct Cli {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    // `pozor-dom-hub <cloud>` without a subcommand still runs the hub
+    #[command(flatten)]
+    pub serve: ServeArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Args, Clone, Default)]
+pub struct ServeArgs {
+    #[arg(value_name = "CLOUD", help = "Cloud relay host or ws:// / wss:// URL (overrides hub.cloud_url)")]
+    pub cloud: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(about = "Run the hub (the default)")]
+    Serve(ServeArgs),
+    #[command(subcommand, about = "Maintain the hub database (hub.db_path)")]
+    Db(DbCommand),
+    #[command(subcommand, about = "Inspect the devices the hub has heard from")]
+    Devices(DevicesCommand),
+    #[command(name = "send-command", about = "Publish a command to a device over MQTT and wait for its result")]
+    Send(SendCommandArgs),
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    #[command(about = "Create the database or bring its schema up to date, backing up an existing file first")]
+    Migrate {
+        #[arg(long, help = "Only list the steps that would run")]
+        dry_run: bool,
+    },
+    #[command(about = "Export devices, automations, alerts, users and access lists as JSON")]
+    Export {
+        #[arg(short, long, value_name = "FILE", help = "Write to FILE instead of stdout")]
+        output: Option<PathBuf>,
+        #[arg(long, value_name = "N", default_value_t = 1000, help = "Newest alert history entries to include")]
+        alerts: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DevicesCommand {
+    #[command(about = "List the devices stored in the database")]
+    List {
+        #[arg(long, help = "Print JSON instead of a table")]
+        json: bool,
+    },
+}
+
+#[derive(Args)]
+pub struct SendCommandArgs {
+    pub device_id: String,
+    pub action: String,
+    #[arg(long, help = "Device channel (default: the one stored in the database)")]
+    pub channel: Option<String>,
+    #[arg(long, help = "Return once the broker has the command instead of waiting for the device")]
+    pub no_wait: bool,
+}
+
+// Everything `db export` writes; sessions and password hashes stay in the database
+#[derive(Serialize)]
+pub struct Export {
+    pub exported_at: String,
+    pub cloud_enabled: bool,
+    pub devices: Vec<DeviceSummary>,
+    pub rules: Vec<Rule>,
+    pub scenes: Vec<Scene>,
+    pub schedules: Vec<Schedule>,
+    pub alert_thresholds: Vec<AlertThresholds>,
+    pub alerts: Vec<AlertEvent>,
+    pub users: Vec<User>,
+    pub device_acls: Vec<DeviceAcl>,
+}
+
+impl Export {
+    pub fn collect(db: &Database, alert_limit: usize) -> rusqlite::Result<Self> {
+        let users = db.load_users()?;
+        let mut device_acls = Vec::new();
+        for user in &users {
+            device_acls.extend(db.load_device_acls(&user.username)?);
+        }
+        Ok(Self {
+            exported_at: Utc::now().to_rfc3339(),
+            cloud_enabled: db.get_cloud_enabled()?,
+            devices: db.load_device_summaries()?,
+            rules: db.load_rules()?,
+            scenes: db.load_scenes()?,
+            schedules: db.load_schedules()?,
+            alert_thresholds: db.load_alert_thresholds()?,
+            alerts: db.load_alerts(None, alert_limit)?,
+            users,
+            device_acls,
+        })
+    }
+}
+
+pub fn migrate(config: &Config, dry_run: bool) -> CliResult {
+    let db_path = &config.hub.db_path;
+    if dry_run {
+        let plan = migrations::plan(db_path)?;
+        if plan.pending.is_empty() {
+            println!("✅ Database schema is up to date (version {}): {}", plan.current_version, db_path);
+            return Ok(());
+        }
+        if plan.exists {
+            println!("{} is at schema version {}; would apply:", db_path, plan.current_version);
+        } else {
+            println!("{} does not exist; would create it with:", db_path);
+        }
+        for migration in &plan.pending {
+            println!("  {:>3}  {}", migration.version, migration.description);
+        }
+        if plan.backup {
+            println!("A backup would be written next to it first");
+        }
+        return Ok(());
+    }
+
+    let mut conn = rusqlite::Connection::open(db_path)?;
+    let report = migrations::migrate(&mut conn, db_path)?;
+    if report.applied.is_empty() {
+        println!("✅ Database schema is up to date (version {}): {}", report.to_version, db_path);
+    } else {
+        println!("✅ Migrated {} from schema version {} to {}", db_path, report.from_version, report.to_version);
+        if let Some(backup) = report.backup {
+            println!("   Restore {} over it to go back", backup.display());
+        }
+    }
+    Ok(())
+}
+
+// Scripting commands never change the database, even when its schema is out of date
+fn open_read_only(config: &Config) -> Result<Database, String> {
+    Database::open_read_only(&config.hub.db_path).map_err(|e| format!("{}: {}", config.hub.db_path, e))
+}
+
+pub fn export(config: &Config, output: Option<PathBuf>, alert_limit: usize) -> CliResult {
+    let db = open_read_only(config)?;
+    let export = Export::collect(&db, alert_limit)?;
+    let json = serde_json::to_string_pretty(&export)?;
+    match output {
+        Some(path) => {
+            std::fs::write(&path, json + "\n").map_err(|e| format!("{}: {}", path.display(), e))?;
+            eprintln!(
+                "💾 Exported {} devices, {} rules, {} scenes, {} schedules and {} users to {}",
+                export.devices.len(),
+                export.rules.len(),
+                export.scenes.len(),
+                export.schedules.len(),
+                export.users.len(),
+                path.display()
+            );
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+pub fn list_devices(config: &Config, json: bool) -> CliResult {
+    let db = open_read_only(config)?;
+    let devices = db.load_device_summaries()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
+    if devices.is_empty() {
+        println!("No devices in {}", config.hub.db_path);
+        return Ok(());
+    }
+
+    let id_width = devices.iter().map(|device| device.telemetry.device_id.len()).max().unwrap_or(0).max("DEVICE".len());
+    println!("{:<id_width$}  {:<10}  {:<8}  {:>6}  LAST SEEN", "DEVICE", "TYPE", "CHANNEL", "SIGNAL");
+    for device in devices {
+        let telemetry = device.telemetry;
+        println!(
+            "{:<id_width$}  {:<10}  {:<8}  {:>6}  {}",
+            telemetry.device_id,
+            telemetry.device_type.as_str(),
+            telemetry.channel,
+            telemetry.signal_strength,
+            device.last_seen.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+    }
+    Ok(())
+}
+
+// Goes straight to the broker under its own client id, so the running hub neither tracks the
+// command nor applies access lists to it
+pub async fn send_command(config: &Config, args: SendCommandArgs) -> CliResult {
+    let channel = match args.channel {
+        Some(channel) => channel,
+        None => {
+            let db = open_read_only(config)?;
+            db.load_device_summaries()?
+                .into_iter()
+                .find(|device| device.telemetry.device_id == args.device_id)
+                .map(|device| device.telemetry.channel)
+                .ok_or_else(|| format!("Device {} is not in {}; pass --channel", args.device_id, config.hub.db_path))?
+        }
+    };
+    let command = DeviceCommand {
+        command_id: format!("cli-{}-{}", Utc::now().timestamp_millis(), std::process::id()),
+        device_id: args.device_id,
+        channel,
+        action: args.action,
+        timestamp: Utc::now().to_rfc3339(),
+    };
+
+    let mut opts = MqttOptions::new(format!("pozor-dom-hub-cli-{}", std::process::id()), config.hub.mqtt.host.as_str(), config.hub.mqtt.port);
+    opts.set_keep_alive(std::time::Duration::from_secs(5));
+    let (client, mut eventloop) = AsyncClient::new(opts, 10);
+    let client = Arc::new(client);
+    if !args.no_wait {
+        let topic = format!("pozor-dom/device/{}/command_result", command.device_id);
+        client.subscribe(topic, QoS::AtLeastOnce).await?;
+    }
+    mqtt::send_device_command(&command, &client).await?;
+
+    let deadline = tokio::time::sleep(COMMAND_TIMEOUT);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => {
+                let ack = CommandAck::for_command(&command, CommandStatus::TimedOut, None);
+                return Err(Envelope::new(Payload::CommandAck(ack)).to_string().into());
+            }
+            event = eventloop.poll() => match event? {
+                Event::Incoming(Incoming::PubAck(_)) if args.no_wait => {
+                    println!("✅ Command {} accepted by the broker", command.command_id);
+                    return Ok(());
+                }
+                Event::Incoming(Incoming::Publish(publish)) => {
+                    let Ok(result) = serde_json::from_slice::<CommandResult>(&publish.payload) else {
+                        continue;
+                    };
+                    if result.command_id != command.command_id {
+                        continue;
+                    }
+                    let status = if result.success { CommandStatus::Succeeded } else { CommandStatus::Failed };
+                    let ack = Envelope::new(Payload::CommandAck(CommandAck::for_command(&command, status, result.detail)));
+                    if !result.success {
+                        return Err(ack.to_string().into());
+                    }
+                    println!("✅ {}", ack);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use crate::accounts::Role;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        let cli = Cli::try_parse_from(["pozor-dom-hub", "cloud.example.com"]).unwrap();
+        assert_eq!(cli.serve.cloud.as_deref(), Some("cloud.example.com"));
+        assert!(cli.command.is_none());
+
+        let cli = Cli::try_parse_from(["pozor-dom-hub", "--set", "hub.db_path=test.db", "db", "export", "-o", "hub.json"]).unwrap();
+        assert_eq!(cli.config.overrides, vec!["hub.db_path=test.db"]);
+        assert!(matches!(cli.command, Some(Command::Db(DbCommand::Export { output: Some(_), alerts: 1000 }))));
+        let cli = Cli::try_parse_from(["pozor-dom-hub", "db", "migrate", "--dry-run"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Db(DbCommand::Migrate { dry_run: true }))));
+
+        let cli = Cli::try_parse_from(["pozor-dom-hub", "send-command", "light-001", "turn_on", "--channel", "ZigBee"]).unwrap();
+        let Some(Command::Send(args)) = cli.command else {
+            panic!("expected send-command");
+        };
+        assert_eq!((args.device_id.as_str(), args.action.as_str(), args.channel.as_deref()), ("light-001", "turn_on", Some("ZigBee")));
+        assert!(Cli::try_parse_from(["pozor-dom-hub", "send-command", "light-001"]).is_err());
+    }
+
+    #[test]
+    fn test_export_leaves_out_secrets() {
+        let db = Database::new(":memory:").unwrap();
+        let user = User { username: "kid".to_string(), role: Role::Operator, created_at: Utc::now().to_rfc3339() };
+        db.create_user(&user, "pbkdf2-hash").unwrap();
+        db.save_device_acl(&DeviceAcl { username: "kid".to_string(), device_id: "light-*".to_string(), actions: Vec::new() }).unwrap();
+
+        let export = Export::collect(&db, 10).unwrap();
+        assert_eq!(export.users, vec![user]);
+        assert_eq!(export.device_acls.len(), 1);
+        assert!(!serde_json::to_string(&export).unwrap().contains("pbkdf2-hash"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rumqttc::AsyncClient;
+use tokio::sync::{broadcast, oneshot};
+use pozor_dom_shared::envelope::{CommandAck, CommandResult, CommandStatus, DeviceCommand, Envelope, Payload};
+
+// How long a device has to publish a command_result before the command is reported as timed out
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct PendingCommand {
+    command: DeviceCommand,
+    // Set when the dispatcher waits for the final status (scenes)
+    waiter: Option<oneshot::Sender<CommandAck>>,
+}
+
+// Commands relayed to MQTT that are still waiting for a command_result
+#[derive(Default)]
+pub struct CommandTracker {
+    pending: Mutex<HashMap<String, PendingCommand>>,
+    next_id: AtomicU64,
+}
+
+impl CommandTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn assign_id(&self, command: &mut DeviceCommand) {
+        if command.command_id.is_empty() {
+            let millis = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis();
+            let seq = self.next_id.fetch_add(1, Ordering::Relaxed);
+            command.command_id = format!("cmd-{}-{}", millis, seq);
+        }
+    }
+
+    fn register(&self, command: &DeviceCommand, waiter: Option<oneshot::Sender<CommandAck>>) {
+        let pending = PendingCommand { command: command.clone(), waiter };
+        self.pending.lock().unwrap().insert(command.command_id.clone(), pending);
+    }
+
+    // Final ack for a pending command, also handed to whoever waits for it; None if it was already finished.
+    // A `device_id` must be the one the command went to, so one device cannot finish another's command
+    fn finish(&self, command_id: &str, device_id: Option<&str>, status: CommandStatus, detail: Option<String>) -> Option<Envelope> {
+        let pending = {
+            let mut pending = self.pending.lock().unwrap();
+            let target = &pending.get(command_id)?.command.device_id;
+            if device_id.is_some_and(|device_id| device_id != target) {
+                return None;
+            }
+            pending.remove(command_id)?
+        };
+        let ack = CommandAck::for_command(&pending.command, status, detail);
+        if let Some(waiter) = pending.waiter {
+            let _ = waiter.send(ack.clone());
+        }
+        Some(Envelope::new(Payload::CommandAck(ack)))
+    }
+
+    // Final ack for a device's result, or None if the command already timed out, is unknown or went to another device
+    pub fn resolve(&self, result: &CommandResult) -> Option<Envelope> {
+        let status = if result.success { CommandStatus::Succeeded } else { CommandStatus::Failed };
+        self.finish(&result.command_id, Some(&result.device_id), status, result.detail.clone())
+    }
+}
+
+// Relays a command to its device and reports its progress on the broadcast channel:
+// the command itself (with its correlation id), then pending, then the final status
+pub async fn dispatch_command(
+    command: DeviceCommand,
+    tracker: Arc<CommandTracker>,
+    mqtt_client: &Arc<AsyncClient>,
+    broadcast_tx: &Arc<broadcast::Sender<String>>,
+) {
+    dispatch(command, tracker, mqtt_client, broadcast_tx, None).await;
+}
+
+// Same as dispatch_command, but waits for the final status (at most COMMAND_TIMEOUT)
+pub async fn dispatch_command_and_wait(
+    command: DeviceCommand,
+    tracker: Arc<CommandTracker>,
+    mqtt_client: &Arc<AsyncClient>,
+    broadcast_tx: &Arc<broadcast::Sender<String>>,
+) -> CommandAck {
+    let (waiter, done) = oneshot::channel();
+    let command = dispatch(command, tracker, mqtt_client, broadcast_tx, Some(waiter)).await;
+    done.await.unwrap_or_else(|_| CommandAck::for_command(&command, CommandStatus::Failed, Some("Command was dropped".to_string())))
+}
+
+async fn dispatch(
+    mut command: DeviceCommand,
+    tracker: Arc<CommandTracker>,
+    mqtt_client: &Arc<AsyncClient>,
+    broadcast_tx: &Arc<broadcast::Sender<String>>,
+    waiter: Option<oneshot::Sender<CommandAck>>,
+) -> DeviceCommand {
+    tracker.assign_id(&mut command);
+    tracker.register(&command, waiter);
+    let _ = broadcast_tx.send(Envelope::command(command.clone()).to_json());
+    let _ = broadcast_tx.send(Envelope::command_ack(&command, CommandStatus::Pending, None).to_json());
+
+    if let Err(e) = crate::mqtt::send_device_command(&command, mqtt_client).await {
+        eprintln!("Failed to send device command: {}", e);
+        let detail = Some(format!("Failed to send device command: {}", e));
+        if let Some(ack) = tracker.finish(&command.command_id, None, CommandStatus::Failed, detail) {
+            let _ = broadcast_tx.send(ack.to_json());
+        }
+        return command;
+    }
+
+    let command_id = command.command_id.clone();
+    let device_id = command.device_id.clone();
+    let broadcast_tx = Arc::clone(broadcast_tx);
+    tokio::spawn(async move {
+        tokio::time::sleep(COMMAND_TIMEOUT).await;
+        if let Some(ack) = tracker.finish(&command_id, None, CommandStatus::TimedOut, None) {
+            println!("⏱️ Command {} to {} timed out", command_id, device_id);
+            let _ = broadcast_tx.send(ack.to_json());
+        }
+    });
+
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(device_id: &str) -> DeviceCommand {
+        DeviceCommand {
+            command_id: String::new(),
+            device_id: device_id.to_string(),
+            channel: "ZigBee".to_string(),
+            action: "unlock".to_string(),
+            timestamp: String::new(),
+        }
+    }
+
+    fn result(command_id: &str, device_id: &str) -> CommandResult {
+        CommandResult { command_id: command_id.to_string(), device_id: device_id.to_string(), success: true, detail: None }
+    }
+
+    #[test]
+    fn test_results_only_from_the_target_device() {
+        let tracker = CommandTracker::new();
+        let mut lock = command("lock-front-001");
+        tracker.assign_id(&mut lock);
+        tracker.register(&lock, None);
+
+        assert!(tracker.resolve(&result(&lock.command_id, "light-001")).is_none(), "another device's result is ignored");
+        let ack = tracker.resolve(&result(&lock.command_id, "lock-front-001")).expect("the target device finishes the command");
+        match ack.payload {
+            Payload::CommandAck(ack) => assert_eq!((ack.device_id.as_str(), ack.status), ("lock-front-001", CommandStatus::Succeeded)),
+            other => panic!("expected a command_ack, got {:?}", other),
+        }
+        assert!(tracker.resolve(&result(&lock.command_id, "lock-front-001")).is_none(), "a command finishes once");
+    }
+}
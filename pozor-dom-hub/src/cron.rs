@@ -0,0 +1,168 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Timelike};
+
+// Standard five-field cron expression ("minute hour day-of-month month day-of-week") in local hub time.
+// Fields accept `*`, numbers, ranges `a-b`, steps `*/n` / `a-b/n`, lists `a,b` and
+// English month/day names (`jan`, `mon`); day-of-week 0 and 7 are both Sunday.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronExpression {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+    // As in cron, when both day fields are restricted a day matching either one is enough
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// How far ahead to look for a match; covers Feb 29 schedules
+const MAX_SEARCH_DAYS: u32 = 366 * 5;
+
+fn parse_value(raw: &str, min: u32, max: u32, names: &[&str], name_base: u32) -> Result<u32, String> {
+    let lower = raw.to_ascii_lowercase();
+    let value = match names.iter().position(|name| *name == lower) {
+        Some(index) => index as u32 + name_base,
+        None => raw.parse().map_err(|_| format!("invalid value '{}'", raw))?,
+    };
+    if value < min || value > max {
+        return Err(format!("value {} out of range {}-{}", value, min, max));
+    }
+    Ok(value)
+}
+
+fn field_error(name: &'static str) -> impl Fn(String) -> String {
+    move |e| format!("{} field: {}", name, e)
+}
+
+// Returns the allowed values (indexed from 0 up to `max`) and whether the field was `*`
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], name_base: u32) -> Result<(Vec<bool>, bool), String> {
+    let mut allowed = vec![false; max as usize + 1];
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| format!("invalid step '{}'", step))?;
+                if step == 0 {
+                    return Err("step must be at least 1".to_string());
+                }
+                (range, step)
+            }
+            None => (item, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, min, max, names, name_base)?, parse_value(end, min, max, names, name_base)?)
+        } else {
+            let start = parse_value(range, min, max, names, name_base)?;
+            // `5/15` means every 15 starting at 5
+            (start, if item.contains('/') { max } else { start })
+        };
+        if start > end {
+            return Err(format!("invalid range '{}'", range));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+    Ok((allowed, field == "*"))
+}
+
+impl CronExpression {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let &[minute, hour, day_of_month, month, day_of_week] = fields.as_slice() else {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        };
+
+        let (minutes, _) = parse_field(minute, 0, 59, &[], 0).map_err(field_error("minute"))?;
+        let (hours, _) = parse_field(hour, 0, 23, &[], 0).map_err(field_error("hour"))?;
+        let (days_of_month, any_day_of_month) = parse_field(day_of_month, 1, 31, &[], 0).map_err(field_error("day-of-month"))?;
+        let (months, _) = parse_field(month, 1, 12, &MONTH_NAMES, 1).map_err(field_error("month"))?;
+        let (mut days_of_week, any_day_of_week) = parse_field(day_of_week, 0, 7, &DAY_NAMES, 0).map_err(field_error("day-of-week"))?;
+        if days_of_week[7] {
+            days_of_week[0] = true;
+        }
+        days_of_week.truncate(7);
+
+        Ok(Self { minutes, hours, days_of_month, months, days_of_week, any_day_of_month, any_day_of_week })
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !self.months[date.month() as usize] {
+            return false;
+        }
+        let day_of_month = self.days_of_month[date.day() as usize];
+        let day_of_week = self.days_of_week[date.weekday().num_days_from_sunday() as usize];
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+
+    // First matching minute strictly after `after`; local times skipped by a DST change never match
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date();
+        for _ in 0..MAX_SEARCH_DAYS {
+            if self.matches_date(date) {
+                for hour in (0..24).filter(|hour| self.hours[*hour as usize]) {
+                    for minute in (0..60).filter(|minute| self.minutes[*minute as usize]) {
+                        let candidate = date.and_hms_opt(hour, minute, 0)?;
+                        if candidate < start {
+                            continue;
+                        }
+                        if let Some(time) = Local.from_local_datetime(&candidate).earliest() {
+                            return Some(time);
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_next_run_for_weekday_mornings() {
+        let cron = CronExpression::parse("30 7 * * mon-fri").unwrap();
+        // 2024-01-05 is a Friday
+        assert_eq!(cron.next_after(local(2024, 1, 5, 7, 0)), Some(local(2024, 1, 5, 7, 30)));
+        assert_eq!(cron.next_after(local(2024, 1, 5, 7, 30)), Some(local(2024, 1, 8, 7, 30)));
+    }
+
+    #[test]
+    fn test_steps_lists_and_day_fields() {
+        let every_quarter = CronExpression::parse("*/15 * * * *").unwrap();
+        assert_eq!(every_quarter.next_after(local(2024, 3, 1, 10, 16)), Some(local(2024, 3, 1, 10, 30)));
+
+        // Day-of-month OR day-of-week when both are set: the 13th or any Friday
+        let either = CronExpression::parse("0 12 13 * 5").unwrap();
+        assert_eq!(either.next_after(local(2024, 9, 1, 0, 0)), Some(local(2024, 9, 6, 12, 0)));
+        assert_eq!(either.next_after(local(2024, 9, 10, 0, 0)), Some(local(2024, 9, 13, 12, 0)));
+
+        let leap = CronExpression::parse("0 0 29 feb *").unwrap();
+        assert_eq!(leap.next_after(local(2024, 3, 1, 0, 0)), Some(local(2028, 2, 29, 0, 0)));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for expression in ["* * * *", "60 * * * *", "* * * * 8", "*/0 * * * *", "5-1 * * * *", "a * * * *"] {
+            assert!(CronExpression::parse(expression).is_err(), "{} should be rejected", expression);
+        }
+        assert!(CronExpression::parse("0 0 31 2 *").unwrap().next_after(local(2024, 1, 1, 0, 0)).is_none());
+    }
+}
@@ -1,64 +1,236 @@
-use rusqlite::{Connection, Result};
-use std::collections::HashMap;
+use rusqlite::{params, Connection, OpenFlags, Result, Row};
+use rusqlite::types::Type;
 use std::sync::{Arc, Mutex};
-use pozor_dom_shared::dashboard::lib::DeviceTelemetry;
-use chrono::Utc;
+use pozor_dom_shared::dashboard::lib::{compat, DeviceSummary, DeviceTelemetry};
+use pozor_dom_shared::device::{Capability, DeviceType, PresenceStatus};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use pozor_dom_shared::envelope::{AlertCondition, AlertEvent, AlertMetric, AlertState};
+use crate::accounts::{Role, User};
+use crate::acl::DeviceAcl;
+use crate::alerts::AlertThresholds;
+use crate::migrations;
+use crate::rules::Rule;
+use crate::scenes::Scene;
+use crate::scheduler::Schedule;
+
+// One stored telemetry reading; `received_at` is the hub clock in UTC
+#[derive(Debug, Clone, Serialize)]
+pub struct TelemetrySample {
+    #[serde(flatten)]
+    pub telemetry: DeviceTelemetry,
+    pub received_at: String,
+}
+
+// One attempt to deliver an alert through a notification sink
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationDelivery {
+    pub id: i64,
+    pub alert_id: i64,
+    pub sink: String,
+    pub target: String,
+    pub attempt: u32,
+    pub success: bool,
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+// Fixed-width UTC timestamps so that SQLite string comparison matches time order
+pub fn format_sample_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+// Min/max/avg of the readings that fell into one bucket
+#[derive(Debug, Clone, Serialize)]
+pub struct TelemetryAggregate {
+    pub device_id: String,
+    pub resolution: &'static str,
+    pub bucket_start: String,
+    pub sample_count: i64,
+    pub temperature_min: Option<f64>,
+    pub temperature_max: Option<f64>,
+    pub temperature_avg: Option<f64>,
+    pub humidity_min: Option<f64>,
+    pub humidity_max: Option<f64>,
+    pub humidity_avg: Option<f64>,
+    pub signal_strength_min: i32,
+    pub signal_strength_max: i32,
+    pub signal_strength_avg: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Raw,
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "raw" => Some(Resolution::Raw),
+            "1m" => Some(Resolution::Minute),
+            "1h" => Some(Resolution::Hour),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Raw => "raw",
+            Resolution::Minute => "1m",
+            Resolution::Hour => "1h",
+        }
+    }
+
+    // Length of the `YYYY-MM-DDTHH:MM` prefix shared by every timestamp in a bucket,
+    // and the suffix that turns that prefix back into a full timestamp
+    fn bucket_format(&self) -> (usize, &'static str) {
+        match self {
+            Resolution::Raw => (24, ""),
+            Resolution::Minute => (16, ":00.000Z"),
+            Resolution::Hour => (13, ":00:00.000Z"),
+        }
+    }
+
+    pub fn bucket_start(&self, time: DateTime<Utc>) -> String {
+        let (prefix_len, suffix) = self.bucket_format();
+        let formatted = format_sample_time(time);
+        format!("{}{}", &formatted[..prefix_len], suffix)
+    }
+
+    fn watermark_key(&self) -> String {
+        format!("rollup_watermark_{}", self.as_str())
+    }
+}
+
+// Reads device_id, channel, device_type, capabilities, signal_strength, timestamp from consecutive columns
+fn telemetry_from_row(row: &Row, first: usize) -> Result<DeviceTelemetry> {
+    let device_type: String = row.get(first + 2)?;
+    let capabilities: String = row.get(first + 3)?;
+
+    Ok(DeviceTelemetry {
+        device_id: row.get(first)?,
+        channel: row.get(first + 1)?,
+        device_type: DeviceType::parse(&device_type).unwrap_or_default(),
+        capabilities: serde_json::from_str(&capabilities)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(first + 3, Type::Text, Box::new(e)))?,
+        signal_strength: row.get(first + 4)?,
+        timestamp: get_time(row, first + 5)?,
+    })
+}
+
+fn get_time(row: &Row, idx: usize) -> Result<DateTime<Utc>> {
+    let raw: String = row.get(idx)?;
+    compat::parse_rfc3339_utc(&raw)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, e.into()))
+}
+
+fn capabilities_json(telemetry: &DeviceTelemetry) -> String {
+    serde_json::to_string(&telemetry.capabilities).unwrap_or_else(|_| "{}".to_string())
+}
+
+fn rule_json(rule: &Rule) -> String {
+    serde_json::to_string(rule).unwrap_or_else(|_| "{}".to_string())
+}
+
+// The id, name and enabled columns win over whatever the stored definition says
+fn rule_from_row(row: &Row) -> Result<Rule> {
+    let definition: String = row.get(3)?;
+    let mut rule: Rule = serde_json::from_str(&definition)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, Type::Text, e.into()))?;
+    rule.id = row.get(0)?;
+    rule.name = row.get(1)?;
+    rule.enabled = row.get(2)?;
+    Ok(rule)
+}
+
+fn scene_from_row(row: &Row) -> Result<Scene> {
+    let definition: String = row.get(1)?;
+    let mut scene: Scene = serde_json::from_str(&definition)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(1, Type::Text, e.into()))?;
+    scene.name = row.get(0)?;
+    Ok(scene)
+}
+
+fn schedule_json(schedule: &Schedule) -> String {
+    serde_json::to_string(schedule).unwrap_or_else(|_| "{}".to_string())
+}
+
+fn schedule_from_row(row: &Row) -> Result<Schedule> {
+    let definition: String = row.get(3)?;
+    let mut schedule: Schedule = serde_json::from_str(&definition)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, Type::Text, e.into()))?;
+    schedule.id = row.get(0)?;
+    schedule.name = row.get(1)?;
+    schedule.enabled = row.get(2)?;
+    schedule.next_run = None;
+    Ok(schedule)
+}
+
+fn alert_from_row(row: &Row) -> Result<AlertEvent> {
+    let metric: String = row.get(2)?;
+    let condition: String = row.get(3)?;
+    let state: String = row.get(6)?;
+    let unknown = |idx: usize, value: String| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, format!("unknown value '{}'", value).into());
+
+    Ok(AlertEvent {
+        id: row.get(0)?,
+        device_id: row.get(1)?,
+        metric: AlertMetric::parse(&metric).ok_or_else(|| unknown(2, metric))?,
+        condition: AlertCondition::parse(&condition).ok_or_else(|| unknown(3, condition))?,
+        threshold: row.get(4)?,
+        value: row.get(5)?,
+        state: AlertState::parse(&state).ok_or_else(|| unknown(6, state))?,
+        timestamp: get_time(row, 7)?,
+    })
+}
+
+fn alert_thresholds_from_row(row: &Row) -> Result<AlertThresholds> {
+    let definition: String = row.get(1)?;
+    let mut thresholds: AlertThresholds = serde_json::from_str(&definition)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(1, Type::Text, e.into()))?;
+    thresholds.device_id = row.get(0)?;
+    Ok(thresholds)
+}
+
+fn user_from_row(row: &Row) -> Result<User> {
+    let role: String = row.get(1)?;
+    Ok(User {
+        username: row.get(0)?,
+        role: Role::parse(&role)
+            .ok_or_else(|| rusqlite::Error::FromSqlConversionFailure(1, Type::Text, format!("unknown role '{}'", role).into()))?,
+        created_at: row.get(2)?,
+    })
+}
+
+fn device_acl_from_row(row: &Row) -> Result<DeviceAcl> {
+    let actions: String = row.get(2)?;
+    Ok(DeviceAcl {
+        username: row.get(0)?,
+        device_id: row.get(1)?,
+        actions: serde_json::from_str(&actions)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, Type::Text, e.into()))?,
+    })
+}
 
 pub struct Database {
     conn: Arc<Mutex<Connection>>,
 }
 
 impl Database {
+    // Opens the database and brings its schema up to date, backing up an existing file first
     pub fn new(db_path: &str) -> Result<Self> {
-        let conn = Connection::open(db_path)?;
-
-        // Create devices table if it doesn't exist
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS devices (
-                device_id TEXT PRIMARY KEY,
-                channel TEXT NOT NULL,
-                temperature TEXT NOT NULL,
-                humidity TEXT NOT NULL,
-                signal_strength INTEGER NOT NULL,
-                timestamp TEXT NOT NULL,
-                last_seen TEXT NOT NULL
-            )",
-            [],
-        )?;
-
-        // Create messages table for logging
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                content TEXT NOT NULL,
-                timestamp TEXT NOT NULL
-            )",
-            [],
-        )?;
+        let mut conn = Connection::open(db_path)?;
+        migrations::migrate(&mut conn, db_path)?;
 
-        // Create config table for storing configuration
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS config (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL,
-                updated_at TEXT NOT NULL
-            )",
-            [],
-        )?;
+        Ok(Database { conn: Arc::new(Mutex::new(conn)) })
+    }
 
-        // Create indexes for better performance
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_devices_channel ON devices(channel)",
-            [],
-        )?;
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_devices_timestamp ON devices(timestamp)",
-            [],
-        )?;
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_messages_timestamp ON messages(timestamp)",
-            [],
-        )?;
+    // For commands that only read: the file is neither created nor migrated nor backed up
+    pub fn open_read_only(db_path: &str) -> Result<Self> {
+        let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        migrations::ensure_current(&conn)?;
 
         Ok(Database { conn: Arc::new(Mutex::new(conn)) })
     }
@@ -69,47 +241,199 @@ impl Database {
         let conn = self.conn.lock().unwrap();
         conn.execute(
             "INSERT OR REPLACE INTO devices
-             (device_id, channel, temperature, humidity, signal_strength, timestamp, last_seen)
+             (device_id, channel, device_type, capabilities, signal_strength, timestamp, last_seen)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            [
-                &telemetry.device_id,
-                &telemetry.channel,
-                &telemetry.temperature,
-                &telemetry.humidity,
-                &telemetry.signal_strength.to_string(),
-                &telemetry.timestamp,
-                &now,
+            params![
+                telemetry.device_id,
+                telemetry.channel,
+                telemetry.device_type.as_str(),
+                capabilities_json(telemetry),
+                telemetry.signal_strength,
+                telemetry.timestamp.to_rfc3339(),
+                now,
             ],
         )?;
 
         Ok(())
     }
 
-    pub fn load_devices(&self) -> Result<HashMap<String, DeviceTelemetry>> {
+    pub fn append_sample(&self, telemetry: &DeviceTelemetry) -> Result<()> {
+        let received_at = format_sample_time(Utc::now());
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO telemetry_samples
+             (device_id, channel, device_type, capabilities, temperature, humidity, signal_strength, timestamp, received_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                telemetry.device_id,
+                telemetry.channel,
+                telemetry.device_type.as_str(),
+                capabilities_json(telemetry),
+                telemetry.get(Capability::Temperature).and_then(|v| v.as_f64()),
+                telemetry.get(Capability::Humidity).and_then(|v| v.as_f64()),
+                telemetry.signal_strength,
+                telemetry.timestamp.to_rfc3339(),
+                received_at,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    // The newest `limit` samples for one device within optional [from, to], in ascending time order
+    pub fn load_history(
+        &self,
+        device_id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Result<Vec<TelemetrySample>> {
+        let from = from.map(format_sample_time);
+        let to = to.map(format_sample_time);
+
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT device_id, channel, temperature, humidity, signal_strength, timestamp
-             FROM devices ORDER BY last_seen DESC"
+            "SELECT device_id, channel, device_type, capabilities, signal_strength, timestamp, received_at
+             FROM telemetry_samples
+             WHERE device_id = ?1
+               AND (?2 IS NULL OR received_at >= ?2)
+               AND (?3 IS NULL OR received_at <= ?3)
+             ORDER BY received_at DESC
+             LIMIT ?4"
         )?;
 
-        let device_iter = stmt.query_map([], |row| {
-            Ok(DeviceTelemetry {
+        let sample_iter = stmt.query_map(params![device_id, from, to, limit as i64], |row| {
+            Ok(TelemetrySample {
+                telemetry: telemetry_from_row(row, 0)?,
+                received_at: row.get(6)?,
+            })
+        })?;
+
+        let mut samples = sample_iter.collect::<Result<Vec<_>>>()?;
+        samples.reverse();
+        Ok(samples)
+    }
+
+    // Aggregates every completed bucket since the last rollup and advances the watermark.
+    // Returns the watermark, i.e. the start of the oldest bucket that is not rolled up yet.
+    pub fn rollup_samples(&self, resolution: Resolution, now: DateTime<Utc>) -> Result<String> {
+        let (prefix_len, suffix) = resolution.bucket_format();
+        let until = resolution.bucket_start(now);
+        let key = resolution.watermark_key();
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let since: Option<String> = match tx.query_row("SELECT value FROM config WHERE key = ?1", [&key], |row| row.get(0)) {
+            Ok(value) => Some(value),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e),
+        };
+
+        tx.execute(
+            "INSERT OR REPLACE INTO telemetry_aggregates
+             (device_id, resolution, bucket_start, sample_count,
+              temperature_min, temperature_max, temperature_avg,
+              humidity_min, humidity_max, humidity_avg,
+              signal_strength_min, signal_strength_max, signal_strength_avg)
+             SELECT device_id, ?1, substr(received_at, 1, ?2) || ?3 AS bucket, COUNT(*),
+                    MIN(temperature), MAX(temperature), AVG(temperature),
+                    MIN(humidity), MAX(humidity), AVG(humidity),
+                    MIN(signal_strength), MAX(signal_strength), AVG(signal_strength)
+             FROM telemetry_samples
+             WHERE (?4 IS NULL OR received_at >= ?4) AND received_at < ?5
+             GROUP BY device_id, bucket",
+            params![resolution.as_str(), prefix_len as i64, suffix, since, until],
+        )?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO config (key, value, updated_at) VALUES (?1, ?2, ?3)",
+            params![key, until, Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+
+        Ok(until)
+    }
+
+    pub fn delete_samples_before(&self, cutoff: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM telemetry_samples WHERE received_at < ?1", [cutoff])
+    }
+
+    // The newest `limit` buckets, in ascending time order like load_history
+    pub fn load_aggregates(
+        &self,
+        device_id: &str,
+        resolution: Resolution,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Result<Vec<TelemetryAggregate>> {
+        // Include the bucket that contains `from`, not just buckets starting after it
+        let from = from.map(|time| resolution.bucket_start(time));
+        let to = to.map(format_sample_time);
+        let resolution_name = resolution.as_str();
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT device_id, bucket_start, sample_count,
+                    temperature_min, temperature_max, temperature_avg,
+                    humidity_min, humidity_max, humidity_avg,
+                    signal_strength_min, signal_strength_max, signal_strength_avg
+             FROM telemetry_aggregates
+             WHERE device_id = ?1 AND resolution = ?2
+               AND (?3 IS NULL OR bucket_start >= ?3)
+               AND (?4 IS NULL OR bucket_start <= ?4)
+             ORDER BY bucket_start DESC
+             LIMIT ?5"
+        )?;
+
+        let aggregate_iter = stmt.query_map(params![device_id, resolution_name, from, to, limit as i64], |row| {
+            Ok(TelemetryAggregate {
                 device_id: row.get(0)?,
-                channel: row.get(1)?,
-                temperature: row.get(2)?,
-                humidity: row.get(3)?,
-                signal_strength: row.get(4)?,
-                timestamp: row.get(5)?,
+                resolution: resolution_name,
+                bucket_start: row.get(1)?,
+                sample_count: row.get(2)?,
+                temperature_min: row.get(3)?,
+                temperature_max: row.get(4)?,
+                temperature_avg: row.get(5)?,
+                humidity_min: row.get(6)?,
+                humidity_max: row.get(7)?,
+                humidity_avg: row.get(8)?,
+                signal_strength_min: row.get(9)?,
+                signal_strength_max: row.get(10)?,
+                signal_strength_avg: row.get(11)?,
             })
         })?;
 
-        let mut devices = HashMap::new();
-        for device in device_iter {
-            let device = device?;
-            devices.insert(device.device_id.clone(), device);
-        }
+        let mut aggregates = aggregate_iter.collect::<Result<Vec<_>>>()?;
+        aggregates.reverse();
+        Ok(aggregates)
+    }
+
+    // Devices with the hub time they were last heard from; status is filled in by the caller
+    pub fn load_device_summaries(&self) -> Result<Vec<DeviceSummary>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT device_id, channel, device_type, capabilities, signal_strength, timestamp, last_seen
+             FROM devices ORDER BY device_id"
+        )?;
+
+        let summary_iter = stmt.query_map([], |row| {
+            Ok(DeviceSummary {
+                telemetry: telemetry_from_row(row, 0)?,
+                status: PresenceStatus::Offline,
+                last_seen: get_time(row, 6)?,
+            })
+        })?;
 
-        Ok(devices)
+        summary_iter.collect()
+    }
+
+    // Called last on shutdown: waits for a statement in progress and writes out pages SQLite still caches
+    pub fn flush(&self) -> Result<()> {
+        self.conn.lock().unwrap().cache_flush()
     }
 
     pub fn get_cloud_enabled(&self) -> Result<bool> {
@@ -139,4 +463,414 @@ impl Database {
 
         Ok(())
     }
+
+    pub fn load_rules(&self) -> Result<Vec<Rule>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, name, enabled, definition FROM rules ORDER BY id")?;
+        let rule_iter = stmt.query_map([], rule_from_row)?;
+        rule_iter.collect()
+    }
+
+    pub fn get_rule(&self, id: i64) -> Result<Option<Rule>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, name, enabled, definition FROM rules WHERE id = ?1")?;
+        match stmt.query_row([id], rule_from_row) {
+            Ok(rule) => Ok(Some(rule)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn create_rule(&self, rule: &Rule) -> Result<i64> {
+        let now = Utc::now().to_rfc3339();
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO rules (name, enabled, definition, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            params![rule.name, rule.enabled, rule_json(rule), now],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    // False when there is no rule with this id
+    pub fn update_rule(&self, id: i64, rule: &Rule) -> Result<bool> {
+        let now = Utc::now().to_rfc3339();
+
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE rules SET name = ?1, enabled = ?2, definition = ?3, updated_at = ?4 WHERE id = ?5",
+            params![rule.name, rule.enabled, rule_json(rule), now, id],
+        )?;
+
+        Ok(updated > 0)
+    }
+
+    pub fn delete_rule(&self, id: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM rules WHERE id = ?1", [id])? > 0)
+    }
+
+    pub fn load_scenes(&self) -> Result<Vec<Scene>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT name, definition FROM scenes ORDER BY name")?;
+        let scene_iter = stmt.query_map([], scene_from_row)?;
+        scene_iter.collect()
+    }
+
+    pub fn get_scene(&self, name: &str) -> Result<Option<Scene>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT name, definition FROM scenes WHERE name = ?1")?;
+        match stmt.query_row([name], scene_from_row) {
+            Ok(scene) => Ok(Some(scene)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Creates the scene or replaces the one with the same name
+    pub fn save_scene(&self, scene: &Scene) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let definition = serde_json::to_string(scene).unwrap_or_else(|_| "{}".to_string());
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO scenes (name, definition, updated_at) VALUES (?1, ?2, ?3)",
+            [&scene.name, &definition, &now],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delete_scene(&self, name: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM scenes WHERE name = ?1", [name])? > 0)
+    }
+
+    pub fn load_schedules(&self) -> Result<Vec<Schedule>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, name, enabled, definition FROM schedules ORDER BY id")?;
+        let schedule_iter = stmt.query_map([], schedule_from_row)?;
+        schedule_iter.collect()
+    }
+
+    pub fn get_schedule(&self, id: i64) -> Result<Option<Schedule>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, name, enabled, definition FROM schedules WHERE id = ?1")?;
+        match stmt.query_row([id], schedule_from_row) {
+            Ok(schedule) => Ok(Some(schedule)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn create_schedule(&self, schedule: &Schedule) -> Result<i64> {
+        let now = Utc::now().to_rfc3339();
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO schedules (name, enabled, definition, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            params![schedule.name, schedule.enabled, schedule_json(schedule), now],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    // False when there is no schedule with this id
+    pub fn update_schedule(&self, id: i64, schedule: &Schedule) -> Result<bool> {
+        let now = Utc::now().to_rfc3339();
+
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE schedules SET name = ?1, enabled = ?2, definition = ?3, updated_at = ?4 WHERE id = ?5",
+            params![schedule.name, schedule.enabled, schedule_json(schedule), now, id],
+        )?;
+
+        Ok(updated > 0)
+    }
+
+    pub fn delete_schedule(&self, id: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM schedules WHERE id = ?1", [id])? > 0)
+    }
+
+    pub fn insert_alert(&self, alert: &AlertEvent) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO alerts (device_id, metric, condition, threshold, value, state, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                alert.device_id,
+                alert.metric.as_str(),
+                alert.condition.as_str(),
+                alert.threshold,
+                alert.value,
+                alert.state.as_str(),
+                alert.timestamp.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    // Alert history, newest first, optionally for a single device
+    pub fn load_alerts(&self, device_id: Option<&str>, limit: usize) -> Result<Vec<AlertEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, device_id, metric, condition, threshold, value, state, created_at
+             FROM alerts
+             WHERE ?1 IS NULL OR device_id = ?1
+             ORDER BY id DESC
+             LIMIT ?2"
+        )?;
+        let alert_iter = stmt.query_map(params![device_id, limit as i64], alert_from_row)?;
+        alert_iter.collect()
+    }
+
+    // Alerts whose latest row for the device and metric is still a raise
+    pub fn load_open_alerts(&self) -> Result<Vec<AlertEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, device_id, metric, condition, threshold, value, state, created_at
+             FROM alerts a
+             WHERE state = 'raised'
+               AND id = (SELECT MAX(id) FROM alerts b WHERE b.device_id = a.device_id AND b.metric = a.metric)"
+        )?;
+        let alert_iter = stmt.query_map([], alert_from_row)?;
+        alert_iter.collect()
+    }
+
+    pub fn load_alert_thresholds(&self) -> Result<Vec<AlertThresholds>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT device_id, definition FROM alert_thresholds ORDER BY device_id")?;
+        let thresholds_iter = stmt.query_map([], alert_thresholds_from_row)?;
+        thresholds_iter.collect()
+    }
+
+    // Creates or replaces the thresholds for `thresholds.device_id`
+    pub fn save_alert_thresholds(&self, thresholds: &AlertThresholds) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let definition = serde_json::to_string(thresholds).unwrap_or_else(|_| "{}".to_string());
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO alert_thresholds (device_id, definition, updated_at) VALUES (?1, ?2, ?3)",
+            [&thresholds.device_id, &definition, &now],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delete_alert_thresholds(&self, device_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM alert_thresholds WHERE device_id = ?1", [device_id])? > 0)
+    }
+
+    pub fn insert_delivery(&self, delivery: &NotificationDelivery) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO notification_deliveries (alert_id, sink, target, attempt, success, detail, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                delivery.alert_id,
+                delivery.sink,
+                delivery.target,
+                delivery.attempt,
+                delivery.success,
+                delivery.detail,
+                delivery.created_at,
+            ],
+        )?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    // Delivery log, newest first, optionally for a single alert
+    pub fn load_deliveries(&self, alert_id: Option<i64>, limit: usize) -> Result<Vec<NotificationDelivery>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, alert_id, sink, target, attempt, success, detail, created_at
+             FROM notification_deliveries
+             WHERE ?1 IS NULL OR alert_id = ?1
+             ORDER BY id DESC
+             LIMIT ?2"
+        )?;
+        let delivery_iter = stmt.query_map(params![alert_id, limit as i64], |row| {
+            Ok(NotificationDelivery {
+                id: row.get(0)?,
+                alert_id: row.get(1)?,
+                sink: row.get(2)?,
+                target: row.get(3)?,
+                attempt: row.get(4)?,
+                success: row.get(5)?,
+                detail: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })?;
+        delivery_iter.collect()
+    }
+
+    pub fn count_users(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    pub fn count_admins(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM users WHERE role = ?1", [Role::Admin.as_str()], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    pub fn load_users(&self) -> Result<Vec<User>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT username, role, created_at FROM users ORDER BY username")?;
+        let user_iter = stmt.query_map([], user_from_row)?;
+        user_iter.collect()
+    }
+
+    pub fn get_user(&self, username: &str) -> Result<Option<User>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT username, role, created_at FROM users WHERE username = ?1")?;
+        match stmt.query_row([username], user_from_row) {
+            Ok(user) => Ok(Some(user)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_password_hash(&self, username: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        match conn.query_row("SELECT password_hash FROM users WHERE username = ?1", [username], |row| row.get(0)) {
+            Ok(hash) => Ok(Some(hash)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // False if the username is taken
+    pub fn create_user(&self, user: &User, password_hash: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO users (username, password_hash, role, created_at) VALUES (?1, ?2, ?3, ?4)",
+            [&user.username, password_hash, user.role.as_str(), &user.created_at],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    pub fn set_user_role(&self, username: &str, role: Role) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("UPDATE users SET role = ?1 WHERE username = ?2", [role.as_str(), username])? > 0)
+    }
+
+    pub fn set_password_hash(&self, username: &str, password_hash: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("UPDATE users SET password_hash = ?1 WHERE username = ?2", [password_hash, username])? > 0)
+    }
+
+    // Also ends the user's sessions and drops their device access list
+    pub fn delete_user(&self, username: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM sessions WHERE username = ?1", [username])?;
+        conn.execute("DELETE FROM device_acls WHERE username = ?1", [username])?;
+        Ok(conn.execute("DELETE FROM users WHERE username = ?1", [username])? > 0)
+    }
+
+    pub fn insert_session(&self, token_digest: &str, username: &str, created_at: &str, expires_at: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO sessions (token_digest, username, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+            [token_digest, username, created_at, expires_at],
+        )?;
+        Ok(())
+    }
+
+    // The user behind an unexpired session
+    pub fn get_session_user(&self, token_digest: &str, now: &str) -> Result<Option<User>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT u.username, u.role, u.created_at
+             FROM sessions s JOIN users u ON u.username = s.username
+             WHERE s.token_digest = ?1 AND s.expires_at > ?2"
+        )?;
+        match stmt.query_row([token_digest, now], user_from_row) {
+            Ok(user) => Ok(Some(user)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn delete_session(&self, token_digest: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM sessions WHERE token_digest = ?1", [token_digest])? > 0)
+    }
+
+    pub fn delete_user_sessions(&self, username: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM sessions WHERE username = ?1", [username])
+    }
+
+    pub fn delete_expired_sessions(&self, now: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM sessions WHERE expires_at <= ?1", [now])
+    }
+
+    pub fn load_device_acls(&self, username: &str) -> Result<Vec<DeviceAcl>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT username, device_id, actions FROM device_acls WHERE username = ?1 ORDER BY device_id")?;
+        let acl_iter = stmt.query_map([username], device_acl_from_row)?;
+        acl_iter.collect()
+    }
+
+    // Creates or replaces the entry for `acl.username` and `acl.device_id`
+    pub fn save_device_acl(&self, acl: &DeviceAcl) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let actions = serde_json::to_string(&acl.actions).unwrap_or_else(|_| "[]".to_string());
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO device_acls (username, device_id, actions, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            [&acl.username, &acl.device_id, &actions, &now],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delete_device_acl(&self, username: &str, device_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM device_acls WHERE username = ?1 AND device_id = ?2", [username, device_id])? > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn telemetry(temperature: f64) -> DeviceTelemetry {
+        DeviceTelemetry {
+            device_id: "device-wifi-001".to_string(),
+            channel: "WiFi".to_string(),
+            device_type: DeviceType::Sensor,
+            capabilities: BTreeMap::from([(Capability::Temperature, temperature.into())]),
+            signal_strength: -50,
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_history_limit_keeps_the_newest_samples() {
+        let db = Database::new(":memory:").unwrap();
+        for temperature in [20.0, 21.0, 22.0, 23.0] {
+            db.append_sample(&telemetry(temperature)).unwrap();
+            // received_at has millisecond resolution
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let temperatures: Vec<_> = db.load_history("device-wifi-001", None, None, 2).unwrap()
+            .iter()
+            .map(|sample| sample.telemetry.get(Capability::Temperature).and_then(|v| v.as_f64()))
+            .collect();
+        assert_eq!(temperatures, vec![Some(22.0), Some(23.0)]);
+    }
 }
@@ -1,35 +1,112 @@
+#![recursion_limit = "256"]
+
+mod accounts;
+mod acl;
+mod cli;
+mod commands;
 mod mqtt;
 mod websocket;
 mod database;
+mod migrations;
+mod presence;
+mod retention;
+mod rules;
+mod scenes;
+mod scheduler;
+mod cron;
+mod sun;
+mod alerts;
+mod notify;
+mod reload;
+mod shutdown;
 
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
 use tokio::sync::{broadcast, Mutex, mpsc};
 use tokio::task::JoinHandle;
-use pozor_dom_shared::dashboard;
+use tokio_util::sync::CancellationToken;
+use clap::Parser;
+use pozor_dom_shared::{config, dashboard};
+use pozor_dom_shared::config::{Config, ConfigArgs, MqttConfig};
+use pozor_dom_shared::tls::TlsFiles;
+use accounts::Role;
+use pozor_dom_shared::device::PresenceStatus;
+use pozor_dom_shared::envelope::{CommandResult, Envelope};
 use warp::Filter;
+use serde::Deserialize;
+use chrono::{DateTime, Utc};
 
 #[derive(Debug)]
 enum CloudCommand {
     Connect,
     Disconnect,
+    // New relay settings from a configuration reload
+    Reconfigure(CloudLink),
+}
+
+// How the hub reaches the cloud relay
+#[derive(Debug, Clone, PartialEq)]
+struct CloudLink {
+    url: String,
+    token: Option<String>,
+    ca_path: Option<String>,
+}
+
+impl CloudLink {
+    // A bare host means plain ws:// on the default port; pass a full wss:// URL for a TLS relay
+    fn from_config(config: &Config) -> Self {
+        Self {
+            url: config.hub.cloud_relay_url(),
+            token: config.hub.cloud_token.clone(),
+            ca_path: config.tls.ca.clone(),
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cli = cli::Cli::parse();
+    let config = config::load_or_exit(&cli.config);
+
+    let result = match cli.command {
+        None => return serve(config, cli.config, cli.serve).await,
+        Some(cli::Command::Serve(args)) => return serve(config, cli.config, args).await,
+        Some(cli::Command::Db(cli::DbCommand::Migrate { dry_run })) => cli::migrate(&config, dry_run),
+        Some(cli::Command::Db(cli::DbCommand::Export { output, alerts })) => cli::export(&config, output, alerts),
+        Some(cli::Command::Devices(cli::DevicesCommand::List { json })) => cli::list_devices(&config, json),
+        Some(cli::Command::Send(args)) => cli::send_command(&config, args).await,
+    };
+    if let Err(e) = result {
+        eprintln!("❌ {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn serve(mut config: Config, config_args: ConfigArgs, args: cli::ServeArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // The cloud relay may still be given as an argument: a host or a full URL
+    if let Some(cloud) = &args.cloud {
+        config.hub.cloud_url = cloud.clone();
+    }
+
     println!("🚀 Позор-дом Hub starting...");
     println!("Local network server + Cloud relay + MQTT bridge + Web Dashboard");
     println!("Press Ctrl+C to exit.\n");
 
-    let args: Vec<String> = std::env::args().collect();
-    let cloud_host = if args.len() > 1 {
-        args[1].as_str()
-    } else {
-        "127.0.0.1"
-    };
+    // Tasks that must wind down cleanly are spawned through this and watch its token
+    let shutdown = shutdown::Shutdown::new();
+
+    // The WebSocket server and the dashboard share one certificate
+    let tls_files = TlsFiles::from_config(&config.tls);
+    let tls_acceptor = tls_files.as_ref().map(TlsFiles::acceptor).transpose()?;
 
     // Initialize SQLite database
-    let db = Arc::new(database::Database::new("pozor_dom_hub.db")?);
-    println!("💾 Database initialized: pozor_dom_hub.db");
+    let db = Arc::new(database::Database::new(&config.hub.db_path)?);
+    println!("💾 Database initialized: {}", config.hub.db_path);
+
+    // Web API and WebSocket users
+    let accounts = Arc::new(accounts::Accounts::new(Arc::clone(&db), config.hub.admin_password.clone()));
+    let device_access = Arc::new(acl::DeviceAccess::new(Arc::clone(&db)));
 
     // Load cloud enabled state from database
     let cloud_enabled = db.get_cloud_enabled().unwrap_or(true);
@@ -40,52 +117,168 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let tx = Arc::new(tx);
 
     // Hub state for web dashboard (load initial devices from database)
-    let initial_devices = db.load_devices().unwrap_or_default();
+    let initial_devices = db.load_device_summaries().unwrap_or_default();
     let mut hub_state = dashboard::HubState::new("Hub");
     hub_state.cloud_enabled = cloud_enabled; // Set initial cloud state
-    for (_device_id, telemetry) in initial_devices {
-        hub_state.update_device(telemetry);
+    for device in initial_devices {
+        // Devices stay offline until they are heard from again
+        hub_state.restore_last_seen(&device.telemetry.device_id, device.last_seen);
+        hub_state.update_device(device.telemetry);
     }
     let hub_state = Arc::new(Mutex::new(hub_state));
 
     // Channel for controlling cloud connection
     let (cloud_tx, cloud_rx) = mpsc::unbounded_channel::<CloudCommand>();
 
-    // Setup MQTT client
-    let (mqtt_client, eventloop) = mqtt::setup_mqtt_client().await;
+    // Setup MQTT client; a configuration reload may move it to another broker
+    let (mqtt_client, eventloop) = mqtt::setup_mqtt_client(&config.hub.mqtt);
     let mqtt_client = Arc::new(mqtt_client);
+    let (broker_tx, broker_rx) = mpsc::unbounded_channel::<MqttConfig>();
+
+    // Settings background tasks pick up again after a configuration reload
+    let live_settings = Arc::new(reload::LiveSettings::new(&config.hub));
+
+    // Commands awaiting a result from their device
+    let command_tracker = Arc::new(commands::CommandTracker::new());
+
+    // Automation rules, evaluated as telemetry, presence changes and time arrive
+    let rule_engine = Arc::new(rules::RuleEngine::new(
+        Arc::clone(&db),
+        Arc::clone(&mqtt_client),
+        Arc::clone(&command_tracker),
+        Arc::clone(&tx),
+    ));
+
+    // Scenes run from the web API and from WebSocket clients
+    let scene_runner = Arc::new(scenes::SceneRunner::new(
+        Arc::clone(&db),
+        Arc::clone(&hub_state),
+        Arc::clone(&mqtt_client),
+        Arc::clone(&command_tracker),
+        Arc::clone(&tx),
+    ));
+
+    // Cron and sunrise/sunset schedules for commands and scenes
+    let location = config.hub.location()
+        .map(|(latitude, longitude)| sun::Location { latitude, longitude });
+    let scheduler = Arc::new(scheduler::Scheduler::new(
+        Arc::clone(&db),
+        Arc::clone(&hub_state),
+        Arc::clone(&mqtt_client),
+        Arc::clone(&command_tracker),
+        Arc::clone(&tx),
+        Arc::clone(&scene_runner),
+        location,
+    ));
+
+    // Threshold alerts on incoming telemetry, delivered through the configured notification sinks
+    let notifications = Arc::new(notify::NotificationDispatcher::new(
+        Arc::clone(&db),
+        config.hub.notify_exec_allow.clone(),
+        shutdown.clone(),
+    ));
+    let alert_monitor = Arc::new(alerts::AlertMonitor::new(Arc::clone(&db), Arc::clone(&notifications)));
 
     // Clone for telemetry processing
+    let mqtt_listener = Arc::clone(&mqtt_client);
     let hub_state_mqtt = Arc::clone(&hub_state);
     let tx_mqtt = Arc::clone(&tx);
     let db_mqtt = Arc::clone(&db);
+    let tracker_mqtt = Arc::clone(&command_tracker);
+    let rules_mqtt = Arc::clone(&rule_engine);
+    let alerts_mqtt = Arc::clone(&alert_monitor);
+
+    let shutdown_mqtt = shutdown.clone();
+
+    // Spawn MQTT listener for telemetry and command results
+    shutdown.spawn(async move {
+        listen_and_process_telemetry(eventloop, mqtt_listener, broker_rx, tx_mqtt, hub_state_mqtt, db_mqtt, tracker_mqtt, rules_mqtt, alerts_mqtt, shutdown_mqtt).await;
+    });
+
+    // Spawn telemetry rollup and retention task
+    let db_retention = Arc::clone(&db);
+    let settings_retention = Arc::clone(&live_settings);
+    let shutdown_retention = shutdown.clone();
+    shutdown.spawn(async move {
+        retention::run_retention_task(db_retention, settings_retention, shutdown_retention).await;
+    });
+
+    // Spawn presence monitor (marks devices offline after missed telemetry)
+    let hub_state_presence = Arc::clone(&hub_state);
+    let tx_presence = Arc::clone(&tx);
+    let rules_presence = Arc::clone(&rule_engine);
+    let settings_presence = Arc::clone(&live_settings);
+    let shutdown_presence = shutdown.clone();
+    shutdown.spawn(async move {
+        presence::run_presence_monitor(
+            hub_state_presence,
+            tx_presence,
+            rules_presence,
+            settings_presence,
+            shutdown_presence,
+        )
+        .await;
+    });
 
-    // Spawn MQTT listener for telemetry
-    tokio::spawn(async move {
-        listen_and_process_telemetry(eventloop, tx_mqtt, hub_state_mqtt, db_mqtt).await;
+    // Spawn time trigger task for automation rules
+    let hub_state_rules = Arc::clone(&hub_state);
+    let rules_time = Arc::clone(&rule_engine);
+    let shutdown_rules = shutdown.clone();
+    shutdown.spawn(async move {
+        rules::run_time_triggers(rules_time, hub_state_rules, shutdown_rules).await;
+    });
+
+    // Spawn scheduler
+    let scheduler_task = Arc::clone(&scheduler);
+    let shutdown_scheduler = shutdown.clone();
+    shutdown.spawn(async move {
+        scheduler::run_scheduler(scheduler_task, shutdown_scheduler).await;
     });
 
     // Spawn cloud connection manager
     let tx_cloud_manager = Arc::clone(&tx);
-    let cloud_url = format!("ws://{}:{}", cloud_host, 8081);
-    let cloud_url_clone = cloud_url.clone();
-    tokio::spawn(async move {
-        manage_cloud_connection(cloud_rx, cloud_url, tx_cloud_manager).await;
+    let cloud_link = CloudLink::from_config(&config);
+    let cloud_url = cloud_link.url.clone();
+    let shutdown_cloud = shutdown.clone();
+    shutdown.spawn(async move {
+        manage_cloud_connection(cloud_rx, cloud_link, tx_cloud_manager, shutdown_cloud).await;
     });
 
     // If cloud should be enabled initially, send connect command
     if cloud_enabled {
         let _ = cloud_tx.send(CloudCommand::Connect);
-        println!("🌐 Cloud relay enabled: {}", cloud_url_clone);
+        println!("🌐 Cloud relay enabled: {}", cloud_url);
     } else {
         println!("🌐 Cloud relay disabled - local network only");
     }
 
+    // Configuration reload on SIGHUP and from the admin API
+    let reloader = Arc::new(reload::Reloader::new(
+        config_args,
+        args.cloud,
+        config.clone(),
+        cloud_tx.clone(),
+        broker_tx,
+        Arc::clone(&live_settings),
+        Arc::clone(&rule_engine),
+        Arc::clone(&alert_monitor),
+        Arc::clone(&scheduler),
+        Arc::clone(&notifications),
+    ));
+    #[cfg(unix)]
+    tokio::spawn(reload::reload_on_sighup(Arc::clone(&reloader)));
+
     // Start WebSocket server
     let tx_ws = Arc::clone(&tx);
     let mqtt_ws = Arc::clone(&mqtt_client);
-    tokio::spawn(async move {
-        if let Err(e) = websocket::start_websocket_server(tx_ws, mqtt_ws).await {
+    let tracker_ws = Arc::clone(&command_tracker);
+    let scenes_ws = Arc::clone(&scene_runner);
+    let accounts_ws = Arc::clone(&accounts);
+    let access_ws = Arc::clone(&device_access);
+    let ws_addr = format!("{}:{}", config.hub.host, config.hub.port);
+    let shutdown_ws = shutdown.clone();
+    shutdown.spawn(async move {
+        if let Err(e) = websocket::start_websocket_server(ws_addr, tx_ws, mqtt_ws, tracker_ws, scenes_ws, accounts_ws, access_ws, tls_acceptor, shutdown_ws).await {
             eprintln!("WebSocket server error: {}", e);
         }
     });
@@ -94,38 +287,116 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let hub_state_web = Arc::clone(&hub_state);
     let db_web = Arc::clone(&db);
     let cloud_tx_web = cloud_tx.clone();
-    tokio::spawn(async move {
-        if let Err(e) = start_web_server_with_db(hub_state_web, db_web, cloud_tx_web, 3000).await {
+    let rules_web = Arc::clone(&rule_engine);
+    let scenes_web = Arc::clone(&scene_runner);
+    let scheduler_web = Arc::clone(&scheduler);
+    let alerts_web = Arc::clone(&alert_monitor);
+    let accounts_web = Arc::clone(&accounts);
+    let access_web = Arc::clone(&device_access);
+    let reloader_web = Arc::clone(&reloader);
+    let tls_web = tls_files.clone();
+    let shutdown_web = shutdown.clone();
+    let web_addr = (config.hub.host.as_str(), config.hub.web_port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("hub.host {} does not resolve", config.hub.host))?;
+    // In-flight requests are answered before the dashboard stops
+    shutdown.spawn(async move {
+        if let Err(e) = start_web_server_with_db(hub_state_web, db_web, cloud_tx_web, rules_web, scenes_web, scheduler_web, alerts_web, accounts_web, access_web, reloader_web, web_addr, tls_web, shutdown_web).await {
             eprintln!("Web server error: {}", e);
         }
     });
 
-    println!("🔌 MQTT broker: 127.0.0.1:1883");
-    println!("🌐 Web dashboard: http://localhost:3000\n");
+    println!("🔌 MQTT broker: {}:{}", config.hub.mqtt.host, config.hub.mqtt.port);
+    println!("🌐 Web dashboard: {}://{}\n", if tls_files.is_some() { "https" } else { "http" }, web_addr);
 
-    // Keep main thread alive
-    tokio::signal::ctrl_c().await?;
-    println!("\n👋 Hub shutting down...");
+    // Run until Ctrl+C or SIGTERM, then give the tasks time to close their connections
+    shutdown::signal().await;
+    let timeout = live_settings.shutdown_timeout();
+    println!("\n👋 Hub shutting down (up to {} s)...", timeout.as_secs());
+    if !shutdown.shutdown(timeout).await {
+        eprintln!("⚠️ {} tasks still running after {} s, exiting anyway", shutdown.pending_tasks(), timeout.as_secs());
+    }
+    if let Err(e) = tokio::task::block_in_place(|| db.flush()) {
+        eprintln!("❌ Failed to flush the database: {}", e);
+    }
+    println!("👋 Hub stopped");
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn listen_and_process_telemetry(
     eventloop: rumqttc::EventLoop,
+    mqtt_client: Arc<rumqttc::AsyncClient>,
+    mut broker_rx: mpsc::UnboundedReceiver<MqttConfig>,
     broadcast_tx: Arc<broadcast::Sender<String>>,
     hub_state: Arc<Mutex<dashboard::HubState>>,
     db: Arc<database::Database>,
+    command_tracker: Arc<commands::CommandTracker>,
+    rule_engine: Arc<rules::RuleEngine>,
+    alert_monitor: Arc<alerts::AlertMonitor>,
+    shutdown: shutdown::Shutdown,
 ) {
     use rumqttc::{Event, Incoming};
 
     let mut eventloop = eventloop;
 
     loop {
-        match eventloop.poll().await {
+        let polled = tokio::select! {
+            polled = eventloop.poll() => polled,
+            Some(broker) = broker_rx.recv() => {
+                // Drops the current connection; the next poll connects to the new broker
+                println!("🔌 Switching MQTT broker to {}:{}", broker.host, broker.port);
+                eventloop.mqtt_options = mqtt::mqtt_options(&broker);
+                eventloop.clean();
+                continue;
+            }
+            _ = shutdown.cancelled() => {
+                mqtt::disconnect(&mqtt_client, &mut eventloop).await;
+                return;
+            }
+        };
+        match polled {
             Ok(notification) => {
                 match notification {
+                    Event::Incoming(Incoming::ConnAck(_)) => {
+                        println!("🔌 Connected to MQTT broker");
+                        mqtt::subscribe_topics(&mqtt_client);
+                    }
                     Event::Incoming(Incoming::Publish(publish)) => {
                         if let Ok(payload) = std::str::from_utf8(&publish.payload) {
+                            if publish.topic.ends_with("/command_result") {
+                                println!("📡 Received MQTT command result on {}: {}", publish.topic, payload);
+                                match serde_json::from_str::<CommandResult>(payload) {
+                                    Ok(result) => match command_tracker.resolve(&result) {
+                                        Some(ack) => {
+                                            let _ = broadcast_tx.send(ack.to_json());
+                                        }
+                                        None => println!("⚠️ Result for unknown or expired command, or from another device: {} ({})", result.command_id, result.device_id),
+                                    },
+                                    Err(e) => println!("❌ Failed to parse command result: {} (error: {})", payload, e),
+                                }
+                                continue;
+                            }
+
+                            if publish.topic.ends_with("/status") {
+                                let (Some(device_id), Some(status)) =
+                                    (mqtt::device_id_from_topic(&publish.topic), PresenceStatus::parse(payload))
+                                else {
+                                    println!("❌ Invalid device status on {}: {}", publish.topic, payload);
+                                    continue;
+                                };
+                                let mut state = hub_state.lock().await;
+                                if let Some(presence) = state.set_presence(device_id, status, Utc::now()) {
+                                    println!("💓 Device {} reported {}", presence.device_id, presence.status.as_str());
+                                    let fired = rule_engine.evaluate(&rules::RuleEvent::Presence(&presence), &state);
+                                    let _ = broadcast_tx.send(Envelope::presence(presence).to_json());
+                                    spawn_rule_actions(&rule_engine, fired, &hub_state, &shutdown);
+                                }
+                                continue;
+                            }
+
                             println!("📡 Received MQTT telemetry on {}: {}", publish.topic, payload);
 
                             // Try to parse as device telemetry and update hub state
@@ -136,23 +407,43 @@ async fn listen_and_process_telemetry(
                                     // Save to database (blocking operation within async context)
                                     let db_clone = Arc::clone(&db);
                                     let telemetry_clone = telemetry.clone();
-                                    tokio::task::block_in_place(|| {
+                                    let raised_alerts = tokio::task::block_in_place(|| {
                                         if let Err(e) = db_clone.save_device(&telemetry_clone) {
                                             eprintln!("❌ Failed to save device to database: {}", e);
                                         }
+                                        if let Err(e) = db_clone.append_sample(&telemetry_clone) {
+                                            eprintln!("❌ Failed to append telemetry sample: {}", e);
+                                        }
+                                        alert_monitor.process(&telemetry_clone)
                                     });
 
-                                    // Update in-memory state
+                                    // Broadcast telemetry to all WebSocket clients
+                                    let _ = broadcast_tx.send(Envelope::telemetry(telemetry.clone()).to_json());
+                                    for alert in raised_alerts {
+                                        println!("🚨 {}", Envelope::alert(alert.clone()));
+                                        let _ = broadcast_tx.send(Envelope::alert(alert).to_json());
+                                    }
+
+                                    // Update in-memory state; telemetry doubles as a heartbeat
                                     let mut state = hub_state.lock().await;
-                                    state.update_device(telemetry);
+                                    let previous = state.devices.get(&telemetry.device_id).cloned();
+                                    let came_online = state.record_telemetry(telemetry.clone(), Utc::now());
+                                    let mut fired = rule_engine.evaluate(
+                                        &rules::RuleEvent::Telemetry { previous: previous.as_ref(), current: &telemetry },
+                                        &state,
+                                    );
+                                    if let Some(presence) = came_online {
+                                        println!("💓 Device {} is online", presence.device_id);
+                                        fired.extend(rule_engine.evaluate(&rules::RuleEvent::Presence(&presence), &state));
+                                        let _ = broadcast_tx.send(Envelope::presence(presence).to_json());
+                                    }
+                                    drop(state);
+                                    spawn_rule_actions(&rule_engine, fired, &hub_state, &shutdown);
                                 }
                                 Err(e) => {
                                     println!("❌ Failed to parse telemetry payload: {} (error: {})", payload, e);
                                 }
                             }
-
-                            // Broadcast telemetry to all WebSocket clients
-                            let _ = broadcast_tx.send(payload.to_string());
                         }
                     }
                     _ => {}
@@ -160,86 +451,234 @@ async fn listen_and_process_telemetry(
             }
             Err(e) => {
                 eprintln!("❌ MQTT telemetry listener error: {}", e);
-                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                // Nothing to disconnect from while the broker is unreachable
+                tokio::select! {
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {}
+                    _ = shutdown.cancelled() => return,
+                }
             }
         }
     }
 }
 
+// Actions publish over the MQTT client this listener drives, so they must not block it
+fn spawn_rule_actions(
+    rule_engine: &Arc<rules::RuleEngine>,
+    fired: Vec<rules::Rule>,
+    hub_state: &Arc<Mutex<dashboard::HubState>>,
+    shutdown: &shutdown::Shutdown,
+) {
+    if fired.is_empty() {
+        return;
+    }
+    let rule_engine = Arc::clone(rule_engine);
+    let hub_state = Arc::clone(hub_state);
+    shutdown.spawn_until_cancelled(async move {
+        rule_engine.execute(fired, &hub_state).await;
+    });
+}
+
+// A running cloud connection and the token that closes it
+struct CloudConnection {
+    stop: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl CloudConnection {
+    // The relay gets a close frame, so it reports the hub offline right away
+    async fn close(self) {
+        self.stop.cancel();
+        let _ = self.task.await;
+    }
+}
+
 async fn manage_cloud_connection(
     mut rx: mpsc::UnboundedReceiver<CloudCommand>,
-    cloud_url: String,
+    mut link: CloudLink,
     broadcast_tx: Arc<broadcast::Sender<String>>,
+    shutdown: shutdown::Shutdown,
 ) {
-    let mut current_task: Option<JoinHandle<()>> = None;
+    let mut current: Option<CloudConnection> = None;
 
-    while let Some(command) = rx.recv().await {
+    loop {
+        let command = tokio::select! {
+            command = rx.recv() => command,
+            _ = shutdown.cancelled() => None,
+        };
+        let Some(command) = command else {
+            break;
+        };
         match command {
             CloudCommand::Connect => {
                 // If already connected, do nothing
-                if current_task.is_some() {
+                if current.is_some() {
                     continue;
                 }
 
-                println!("🌐 Connecting to cloud: {}", cloud_url);
-                let tx_clone = Arc::clone(&broadcast_tx);
-                let url_clone = cloud_url.clone();
-
-                let task = tokio::spawn(async move {
-                    if let Err(e) = websocket::connect_to_cloud(&url_clone, tx_clone).await {
-                        eprintln!("Cloud connection error: {}", e);
-                    }
-                });
-
-                current_task = Some(task);
+                println!("🌐 Connecting to cloud: {}", link.url);
+                current = Some(open_cloud_connection(&link, &broadcast_tx, &shutdown));
             }
             CloudCommand::Disconnect => {
-                // If connected, abort the task
-                if let Some(task) = current_task.take() {
-                    task.abort();
+                if let Some(connection) = current.take() {
+                    connection.close().await;
                     println!("🌐 Disconnected from cloud");
                 }
             }
+            CloudCommand::Reconfigure(new_link) => {
+                link = new_link;
+                // A live connection is restarted with the new settings; a disabled one stays off
+                if let Some(connection) = current.take() {
+                    connection.close().await;
+                    println!("🌐 Reconnecting to cloud: {}", link.url);
+                    current = Some(open_cloud_connection(&link, &broadcast_tx, &shutdown));
+                }
+            }
         }
     }
+
+    if let Some(connection) = current.take() {
+        connection.close().await;
+        println!("🌐 Disconnected from cloud");
+    }
 }
 
+fn open_cloud_connection(link: &CloudLink, broadcast_tx: &Arc<broadcast::Sender<String>>, shutdown: &shutdown::Shutdown) -> CloudConnection {
+    let link = link.clone();
+    let tx_clone = Arc::clone(broadcast_tx);
+    // Cancelled with the hub's shutdown token as well
+    let stop = shutdown.token().child_token();
+    let task_stop = stop.clone();
+    let task = tokio::spawn(async move {
+        if let Err(e) = websocket::connect_to_cloud(&link.url, link.token.as_deref(), link.ca_path.as_deref(), tx_clone, task_stop).await {
+            eprintln!("Cloud connection error: {}", e);
+        }
+    });
+    CloudConnection { stop, task }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn start_web_server_with_db(
     hub_state: Arc<Mutex<dashboard::HubState>>,
     db: Arc<database::Database>,
     cloud_tx: mpsc::UnboundedSender<CloudCommand>,
-    port: u16,
+    rule_engine: Arc<rules::RuleEngine>,
+    scene_runner: Arc<scenes::SceneRunner>,
+    scheduler: Arc<scheduler::Scheduler>,
+    alert_monitor: Arc<alerts::AlertMonitor>,
+    accounts: Arc<accounts::Accounts>,
+    device_access: Arc<acl::DeviceAccess>,
+    reloader: Arc<reload::Reloader>,
+    addr: SocketAddr,
+    tls: Option<TlsFiles>,
+    shutdown: shutdown::Shutdown,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let hub_state_filter = warp::any().map(move || Arc::clone(&hub_state));
     let db_for_toggle = Arc::clone(&db);
     let db_filter = warp::any().map(move || Arc::clone(&db));
+    let accounts_filter = {
+        let accounts = Arc::clone(&accounts);
+        warp::any().map(move || Arc::clone(&accounts))
+    };
+    // Every API route checks the caller's role right after its path and method
+    let auth = |role: Role| accounts::require(Arc::clone(&accounts), role);
 
-    // Serve Yew-based dashboard instead of static HTML
+    // Serve Yew-based dashboard instead of static HTML; without a session the browser is sent to the login page
     let dashboard = warp::path::end()
-        .map(move || {
-            let html_content = include_str!("../../pozor-dom-shared/src/dashboard/yew_index.html");
-            warp::reply::html(html_content)
+        .and(accounts::authenticated(Arc::clone(&accounts)).map(|_: accounts::User| true).or(warp::any().map(|| false)).unify())
+        .map(move |logged_in: bool| -> Box<dyn warp::Reply> {
+            if logged_in {
+                let html_content = include_str!("../../pozor-dom-shared/src/dashboard/yew_index.html");
+                Box::new(warp::reply::html(html_content))
+            } else {
+                Box::new(warp::redirect::see_other(warp::http::Uri::from_static("/login")))
+            }
         });
 
+    let login_page = warp::path!("login")
+        .and(warp::get())
+        .map(|| warp::reply::html(include_str!("../../pozor-dom-shared/src/dashboard/login.html")));
+
+    let secure_cookie = tls.is_some();
+    let api_login = warp::path!("api" / "login")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(accounts_filter.clone())
+        .and(warp::any().map(move || secure_cookie))
+        .and_then(login);
+
+    let api_logout = warp::path!("api" / "logout")
+        .and(warp::post())
+        .and(accounts::token())
+        .and(accounts_filter.clone())
+        .and_then(logout);
+
+    let api_me = warp::path!("api" / "me")
+        .and(warp::get())
+        .and(accounts::authenticated(Arc::clone(&accounts)))
+        .map(|user: accounts::User| warp::reply::json(&user));
+
+    let api_users_list = warp::path!("api" / "users")
+        .and(warp::get())
+        .and(auth(Role::Admin))
+        .and(db_filter.clone())
+        .and_then(list_users);
+
+    let api_users_create = warp::path!("api" / "users")
+        .and(warp::post())
+        .and(auth(Role::Admin))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and_then(create_user);
+
+    let api_user_update = warp::path!("api" / "users" / String)
+        .and(warp::put())
+        .and(auth(Role::Admin))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and(accounts_filter.clone())
+        .and_then(update_user);
+
+    let api_user_delete = warp::path!("api" / "users" / String)
+        .and(warp::delete())
+        .and(auth(Role::Admin))
+        .and(db_filter.clone())
+        .and_then(delete_user);
+
+    let api_user_acls = warp::path!("api" / "users" / String / "devices")
+        .and(warp::get())
+        .and(auth(Role::Admin))
+        .and(db_filter.clone())
+        .and_then(list_device_acls);
+
+    let api_user_acl_put = warp::path!("api" / "users" / String / "devices" / String)
+        .and(warp::put())
+        .and(auth(Role::Admin))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and_then(put_device_acl);
+
+    let api_user_acl_delete = warp::path!("api" / "users" / String / "devices" / String)
+        .and(warp::delete())
+        .and(auth(Role::Admin))
+        .and(db_filter.clone())
+        .and_then(delete_device_acl);
+
     // API endpoints that use database (synchronous)
     let api_devices = warp::path!("api" / "devices")
+        .and(auth(Role::Viewer))
         .and(db_filter.clone())
-        .map(|db: Arc<database::Database>| {
-            // Hub always has access to its database
-            match db.load_devices() {
-                Ok(devices) => {
-                    let device_list: Vec<&pozor_dom_shared::dashboard::lib::DeviceTelemetry> = devices.values().collect();
-                    warp::reply::with_status(warp::reply::json(&device_list), warp::http::StatusCode::OK)
-                }
-                Err(e) => {
-                    eprintln!("Database error loading devices: {}", e);
-                    // Return empty array on error
-                    warp::reply::with_status(warp::reply::json(&Vec::<&pozor_dom_shared::dashboard::lib::DeviceTelemetry>::new()), warp::http::StatusCode::INTERNAL_SERVER_ERROR)
-                }
-            }
-        });
+        .and(hub_state_filter.clone())
+        .and_then(get_devices);
+
+    let api_device_history = warp::path!("api" / "devices" / String / "history")
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(warp::query::<HistoryQuery>())
+        .and(db_filter.clone())
+        .and_then(get_device_history);
 
     let api_messages = warp::path!("api" / "messages")
+        .and(auth(Role::Viewer))
         .and(hub_state_filter.clone())
         .and_then(get_messages);
 
@@ -248,45 +687,660 @@ async fn start_web_server_with_db(
 
     let api_toggle_cloud = warp::path!("api" / "toggle-cloud")
         .and(warp::post())
+        .and(auth(Role::Admin))
         .and(hub_state_filter.clone())
         .and(cloud_tx_filter)
         .and(db_filter_for_toggle)
         .and_then(toggle_cloud);
 
+    let rules_filter = warp::any().map(move || Arc::clone(&rule_engine));
+
+    let api_rules_list = warp::path!("api" / "rules")
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(db_filter.clone())
+        .and_then(list_rules);
+
+    let api_rules_create = warp::path!("api" / "rules")
+        .and(warp::post())
+        .and(auth(Role::Operator))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and(rules_filter.clone())
+        .and_then(create_rule);
+
+    let api_rule_get = warp::path!("api" / "rules" / i64)
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(db_filter.clone())
+        .and_then(get_rule);
+
+    let api_rule_update = warp::path!("api" / "rules" / i64)
+        .and(warp::put())
+        .and(auth(Role::Operator))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and(rules_filter.clone())
+        .and_then(update_rule);
+
+    let api_rule_delete = warp::path!("api" / "rules" / i64)
+        .and(warp::delete())
+        .and(auth(Role::Operator))
+        .and(db_filter.clone())
+        .and(rules_filter)
+        .and_then(delete_rule);
+
+    let scenes_filter = warp::any().map(move || Arc::clone(&scene_runner));
+
+    let api_scenes_list = warp::path!("api" / "scenes")
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(db_filter.clone())
+        .and_then(list_scenes);
+
+    let api_scene_get = warp::path!("api" / "scenes" / String)
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(db_filter.clone())
+        .and_then(get_scene);
+
+    let api_scene_put = warp::path!("api" / "scenes" / String)
+        .and(warp::put())
+        .and(auth(Role::Operator))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and_then(put_scene);
+
+    let api_scene_delete = warp::path!("api" / "scenes" / String)
+        .and(warp::delete())
+        .and(auth(Role::Operator))
+        .and(db_filter.clone())
+        .and_then(delete_scene);
+
+    let api_scene_activate = warp::path!("api" / "scenes" / String / "activate")
+        .and(warp::post())
+        .and(accounts::authorized(Arc::clone(&accounts), Role::Operator))
+        .and(scenes_filter)
+        .and(warp::any().map(move || Arc::clone(&device_access)))
+        .and_then(activate_scene);
+
+    let scheduler_filter = warp::any().map(move || Arc::clone(&scheduler));
+
+    let api_schedules_list = warp::path!("api" / "schedules")
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(db_filter.clone())
+        .and(scheduler_filter.clone())
+        .and_then(list_schedules);
+
+    let api_schedules_create = warp::path!("api" / "schedules")
+        .and(warp::post())
+        .and(auth(Role::Operator))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and(scheduler_filter.clone())
+        .and_then(create_schedule);
+
+    let api_schedule_get = warp::path!("api" / "schedules" / i64)
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(db_filter.clone())
+        .and(scheduler_filter.clone())
+        .and_then(get_schedule);
+
+    let api_schedule_update = warp::path!("api" / "schedules" / i64)
+        .and(warp::put())
+        .and(auth(Role::Operator))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and(scheduler_filter.clone())
+        .and_then(update_schedule);
+
+    let api_schedule_delete = warp::path!("api" / "schedules" / i64)
+        .and(warp::delete())
+        .and(auth(Role::Operator))
+        .and(db_filter.clone())
+        .and(scheduler_filter)
+        .and_then(delete_schedule);
+
+    let alerts_filter = warp::any().map(move || Arc::clone(&alert_monitor));
+
+    let api_alerts_list = warp::path!("api" / "alerts")
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(warp::query::<AlertsQuery>())
+        .and(db_filter.clone())
+        .and_then(list_alerts);
+
+    let api_alerts_active = warp::path!("api" / "alerts" / "active")
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(alerts_filter.clone())
+        .and_then(list_active_alerts);
 
+    let api_alert_thresholds_list = warp::path!("api" / "alerts" / "thresholds")
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(db_filter.clone())
+        .and_then(list_alert_thresholds);
+
+    let api_alert_thresholds_put = warp::path!("api" / "alerts" / "thresholds" / String)
+        .and(warp::put())
+        .and(auth(Role::Admin))
+        .and(warp::body::json())
+        .and(db_filter.clone())
+        .and(alerts_filter.clone())
+        .and_then(put_alert_thresholds);
+
+    let api_alert_deliveries = warp::path!("api" / "alerts" / "deliveries")
+        .and(warp::get())
+        .and(auth(Role::Viewer))
+        .and(warp::query::<DeliveriesQuery>())
+        .and(db_filter.clone())
+        .and_then(list_alert_deliveries);
+
+    let api_alert_thresholds_delete = warp::path!("api" / "alerts" / "thresholds" / String)
+        .and(warp::delete())
+        .and(auth(Role::Admin))
+        .and(db_filter.clone())
+        .and(alerts_filter)
+        .and_then(delete_alert_thresholds);
+
+    let api_admin_reload = warp::path!("api" / "admin" / "reload")
+        .and(warp::post())
+        .and(auth(Role::Admin))
+        .and(warp::any().map(move || Arc::clone(&reloader)))
+        .and_then(reload_config);
 
     let routes = dashboard
+        .or(login_page)
+        .or(api_login)
+        .or(api_logout)
+        .or(api_me)
+        .or(api_users_list)
+        .or(api_users_create)
+        .or(api_user_update)
+        .or(api_user_delete)
+        .or(api_user_acls)
+        .or(api_user_acl_put)
+        .or(api_user_acl_delete)
         .or(api_devices)
+        .or(api_device_history)
         .or(api_messages)
         .or(api_toggle_cloud)
+        .or(api_rules_list)
+        .or(api_rules_create)
+        .or(api_rule_get)
+        .or(api_rule_update)
+        .or(api_rule_delete)
+        .or(api_scenes_list)
+        .or(api_scene_get)
+        .or(api_scene_put)
+        .or(api_scene_delete)
+        .or(api_scene_activate)
+        .or(api_schedules_list)
+        .or(api_schedules_create)
+        .or(api_schedule_get)
+        .or(api_schedule_update)
+        .or(api_schedule_delete)
+        .or(api_alerts_list)
+        .or(api_alerts_active)
+        .or(api_alert_thresholds_list)
+        .or(api_alert_thresholds_put)
+        .or(api_alert_thresholds_delete)
+        .or(api_alert_deliveries)
+        .or(api_admin_reload)
+        .recover(accounts::handle_rejection)
         .with(warp::cors().allow_any_origin());
 
-    println!("🌐 Web dashboard available at: http://localhost:{}", port);
-    warp::serve(routes).run(([127, 0, 0, 1], port)).await;
+    match tls {
+        Some(files) => {
+            println!("🌐 Web dashboard available at: https://{}", addr);
+            let (_, server) = warp::serve(routes)
+                .tls()
+                .cert_path(&files.cert_path)
+                .key_path(&files.key_path)
+                .bind_with_graceful_shutdown(addr, shutdown.token().cancelled_owned());
+            server.await;
+        }
+        None => {
+            println!("🌐 Web dashboard available at: http://{}", addr);
+            let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, shutdown.token().cancelled_owned());
+            server.await;
+        }
+    }
 
     Ok(())
 }
 
 
 
-async fn get_messages_protected(
-    hub_state: Arc<Mutex<dashboard::HubState>>,
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+    resolution: Option<String>,
+}
+
+const DEFAULT_HISTORY_LIMIT: usize = 1000;
+const MAX_HISTORY_LIMIT: usize = 10_000;
+
+fn parse_history_bound(value: &Option<String>) -> Result<Option<DateTime<Utc>>, String> {
+    match value {
+        Some(raw) => DateTime::parse_from_rfc3339(raw)
+            .map(|time| Some(time.with_timezone(&Utc)))
+            .map_err(|e| format!("Invalid timestamp '{}': {}", raw, e)),
+        None => Ok(None),
+    }
+}
+
+async fn get_device_history(
+    device_id: String,
+    query: HistoryQuery,
+    db: Arc<database::Database>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let state = hub_state.lock().await;
+    let (from, to) = match (parse_history_bound(&query.from), parse_history_bound(&query.to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(message), _) | (_, Err(message)) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": message })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+    let resolution = match query.resolution.as_deref().map(database::Resolution::parse) {
+        None => database::Resolution::Raw,
+        Some(Some(resolution)) => resolution,
+        Some(None) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": "resolution must be one of: raw, 1m, 1h" })),
+                warp::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).min(MAX_HISTORY_LIMIT);
+
+    let result = tokio::task::block_in_place(|| match resolution {
+        database::Resolution::Raw => db.load_history(&device_id, from, to, limit).map(|samples| warp::reply::json(&samples)),
+        _ => db.load_aggregates(&device_id, resolution, from, to, limit).map(|aggregates| warp::reply::json(&aggregates)),
+    });
+    match result {
+        Ok(reply) => Ok(warp::reply::with_status(reply, warp::http::StatusCode::OK)),
+        Err(e) => {
+            eprintln!("Database error loading history for {}: {}", device_id, e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": "Failed to load history" })),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+fn json_error(message: &str, status: warp::http::StatusCode) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&serde_json::json!({ "error": message })), status)
+}
+
+// Common ending of the CRUD handlers: the body with `status`, a 404 when there is nothing to show
+// and a logged 500 for a database error. `kind` names what is handled ("Rule", "Rules"), `key` which one
+fn crud_reply<T: serde::Serialize>(
+    kind: &str,
+    action: &str,
+    key: Option<&dyn std::fmt::Display>,
+    result: rusqlite::Result<Option<T>>,
+    status: warp::http::StatusCode,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    match result {
+        Ok(Some(body)) => warp::reply::with_status(warp::reply::json(&body), status),
+        Ok(None) => json_error(&format!("{} not found", kind), warp::http::StatusCode::NOT_FOUND),
+        Err(e) => {
+            let kind = kind.to_lowercase();
+            match key {
+                Some(key) => eprintln!("Database error trying to {} {} {}: {}", action, kind, key, e),
+                None => eprintln!("Database error trying to {} {}: {}", action, kind, e),
+            }
+            json_error(&format!("Failed to {} {}", action, kind), warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Picks up changes made through the API; the stored rules stay authoritative
+fn reload_rules(rule_engine: &rules::RuleEngine) {
+    if let Err(e) = tokio::task::block_in_place(|| rule_engine.reload()) {
+        eprintln!("❌ Failed to reload automation rules: {}", e);
+    }
+}
+
+async fn list_rules(db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let rules = tokio::task::block_in_place(|| db.load_rules()).map(Some);
+    Ok(crud_reply("Rules", "load", None, rules, warp::http::StatusCode::OK))
+}
+
+async fn get_rule(id: i64, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let rule = tokio::task::block_in_place(|| db.get_rule(id));
+    Ok(crud_reply("Rule", "load", Some(&id), rule, warp::http::StatusCode::OK))
+}
+
+async fn create_rule(
+    mut rule: rules::Rule,
+    db: Arc<database::Database>,
+    rule_engine: Arc<rules::RuleEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(message) = rule.validate() {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
+    }
+    let created = tokio::task::block_in_place(|| db.create_rule(&rule)).map(|id| {
+        rule.id = id;
+        reload_rules(&rule_engine);
+        println!("⚙️ Created rule {} '{}'", rule.id, rule.name);
+        Some(rule)
+    });
+    Ok(crud_reply("Rule", "create", None, created, warp::http::StatusCode::CREATED))
+}
+
+async fn update_rule(
+    id: i64,
+    mut rule: rules::Rule,
+    db: Arc<database::Database>,
+    rule_engine: Arc<rules::RuleEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(message) = rule.validate() {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
+    }
+    rule.id = id;
+    let updated = tokio::task::block_in_place(|| db.update_rule(id, &rule)).map(|found| {
+        found.then(|| {
+            reload_rules(&rule_engine);
+            println!("⚙️ Updated rule {} '{}'", rule.id, rule.name);
+            rule
+        })
+    });
+    Ok(crud_reply("Rule", "update", Some(&id), updated, warp::http::StatusCode::OK))
+}
+
+async fn delete_rule(
+    id: i64,
+    db: Arc<database::Database>,
+    rule_engine: Arc<rules::RuleEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let deleted = tokio::task::block_in_place(|| db.delete_rule(id)).map(|found| {
+        found.then(|| {
+            reload_rules(&rule_engine);
+            println!("⚙️ Deleted rule {}", id);
+            serde_json::json!({ "deleted": id })
+        })
+    });
+    Ok(crud_reply("Rule", "delete", Some(&id), deleted, warp::http::StatusCode::OK))
+}
+
+async fn list_scenes(db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let scenes = tokio::task::block_in_place(|| db.load_scenes()).map(Some);
+    Ok(crud_reply("Scenes", "load", None, scenes, warp::http::StatusCode::OK))
+}
+
+async fn get_scene(name: String, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let scene = tokio::task::block_in_place(|| db.get_scene(&name));
+    Ok(crud_reply("Scene", "load", Some(&name), scene, warp::http::StatusCode::OK))
+}
 
-    // Check if cloud access is allowed
-    if !state.cloud_enabled {
-        // Cloud is disabled, return forbidden
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&serde_json::json!({
-                "error": "Cloud access disabled",
-                "message": "Cloud connectivity is currently disabled on this hub"
-            })),
-            warp::http::StatusCode::FORBIDDEN
-        ));
+async fn put_scene(
+    name: String,
+    mut scene: scenes::Scene,
+    db: Arc<database::Database>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    scene.name = name.clone();
+    if let Err(message) = scene.validate() {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
     }
+    let saved = tokio::task::block_in_place(|| db.save_scene(&scene)).map(|()| {
+        println!("🎬 Saved scene '{}' ({} commands)", scene.name, scene.commands.len());
+        Some(scene)
+    });
+    Ok(crud_reply("Scene", "save", Some(&name), saved, warp::http::StatusCode::OK))
+}
+
+async fn delete_scene(name: String, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let deleted = tokio::task::block_in_place(|| db.delete_scene(&name)).map(|found| {
+        found.then(|| {
+            println!("🎬 Deleted scene '{}'", name);
+            serde_json::json!({ "deleted": name })
+        })
+    });
+    Ok(crud_reply("Scene", "delete", Some(&name), deleted, warp::http::StatusCode::OK))
+}
 
-    Ok(warp::reply::with_status(warp::reply::json(&state.messages), warp::http::StatusCode::OK))
+// Waits until every member command has a final status (at most the command timeout)
+async fn activate_scene(
+    name: String,
+    user: accounts::User,
+    scene_runner: Arc<scenes::SceneRunner>,
+    device_access: Arc<acl::DeviceAccess>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match scene_runner.prepare(&name).await {
+        Ok(scene) => {
+            // A scene runs as a whole, so one refused member stops all of it
+            match tokio::task::block_in_place(|| device_access.denied(&user, scene.commands())) {
+                Ok(denied) if !denied.is_empty() => {
+                    let devices: Vec<&str> = denied.iter().map(|command| command.device_id.as_str()).collect();
+                    println!("🚫 {} may not activate scene {} ({})", user.username, name, devices.join(", "));
+                    let message = format!("Not allowed to command devices: {}", devices.join(", "));
+                    return Ok(json_error(&message, warp::http::StatusCode::FORBIDDEN));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Database error checking device access for {}: {}", user.username, e);
+                    return Ok(json_error("Failed to check device access", warp::http::StatusCode::INTERNAL_SERVER_ERROR));
+                }
+            }
+
+            let result = scene_runner.run(scene).await;
+            Ok(warp::reply::with_status(warp::reply::json(&result), warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            let status = match &e {
+                scenes::SceneError::NotFound => warp::http::StatusCode::NOT_FOUND,
+                scenes::SceneError::Unavailable(_) => warp::http::StatusCode::CONFLICT,
+                scenes::SceneError::Database(_) => {
+                    eprintln!("Database error activating scene {}: {}", name, e);
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR
+                }
+            };
+            Ok(json_error(&e.to_string(), status))
+        }
+    }
+}
+
+// Picks up changes made through the API and refreshes next run times
+fn reload_schedules(scheduler: &scheduler::Scheduler) {
+    if let Err(e) = tokio::task::block_in_place(|| scheduler.reload()) {
+        eprintln!("❌ Failed to reload schedules: {}", e);
+    }
+}
+
+async fn list_schedules(
+    db: Arc<database::Database>,
+    scheduler: Arc<scheduler::Scheduler>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let schedules = tokio::task::block_in_place(|| db.load_schedules()).map(|mut schedules| {
+        for schedule in schedules.iter_mut() {
+            schedule.next_run = scheduler.next_run(schedule.id);
+        }
+        Some(schedules)
+    });
+    Ok(crud_reply("Schedules", "load", None, schedules, warp::http::StatusCode::OK))
+}
+
+async fn get_schedule(
+    id: i64,
+    db: Arc<database::Database>,
+    scheduler: Arc<scheduler::Scheduler>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let schedule = tokio::task::block_in_place(|| db.get_schedule(id)).map(|schedule| {
+        schedule.map(|mut schedule| {
+            schedule.next_run = scheduler.next_run(id);
+            schedule
+        })
+    });
+    Ok(crud_reply("Schedule", "load", Some(&id), schedule, warp::http::StatusCode::OK))
+}
+
+async fn create_schedule(
+    mut schedule: scheduler::Schedule,
+    db: Arc<database::Database>,
+    scheduler: Arc<scheduler::Scheduler>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(message) = schedule.validate(scheduler.location()) {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
+    }
+    schedule.next_run = None;
+    let created = tokio::task::block_in_place(|| db.create_schedule(&schedule)).map(|id| {
+        schedule.id = id;
+        reload_schedules(&scheduler);
+        schedule.next_run = scheduler.next_run(id);
+        println!("⏰ Created schedule {} '{}'", schedule.id, schedule.name);
+        Some(schedule)
+    });
+    Ok(crud_reply("Schedule", "create", None, created, warp::http::StatusCode::CREATED))
+}
+
+async fn update_schedule(
+    id: i64,
+    mut schedule: scheduler::Schedule,
+    db: Arc<database::Database>,
+    scheduler: Arc<scheduler::Scheduler>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(message) = schedule.validate(scheduler.location()) {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
+    }
+    schedule.id = id;
+    schedule.next_run = None;
+    let updated = tokio::task::block_in_place(|| db.update_schedule(id, &schedule)).map(|found| {
+        found.then(|| {
+            reload_schedules(&scheduler);
+            schedule.next_run = scheduler.next_run(id);
+            println!("⏰ Updated schedule {} '{}'", schedule.id, schedule.name);
+            schedule
+        })
+    });
+    Ok(crud_reply("Schedule", "update", Some(&id), updated, warp::http::StatusCode::OK))
+}
+
+async fn delete_schedule(
+    id: i64,
+    db: Arc<database::Database>,
+    scheduler: Arc<scheduler::Scheduler>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let deleted = tokio::task::block_in_place(|| db.delete_schedule(id)).map(|found| {
+        found.then(|| {
+            reload_schedules(&scheduler);
+            println!("⏰ Deleted schedule {}", id);
+            serde_json::json!({ "deleted": id })
+        })
+    });
+    Ok(crud_reply("Schedule", "delete", Some(&id), deleted, warp::http::StatusCode::OK))
+}
+
+#[derive(Debug, Deserialize)]
+struct AlertsQuery {
+    device_id: Option<String>,
+    limit: Option<usize>,
+}
+
+const DEFAULT_ALERTS_LIMIT: usize = 100;
+const MAX_ALERTS_LIMIT: usize = 1000;
+
+async fn list_alerts(query: AlertsQuery, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(DEFAULT_ALERTS_LIMIT).min(MAX_ALERTS_LIMIT);
+    let alerts = tokio::task::block_in_place(|| db.load_alerts(query.device_id.as_deref(), limit)).map(Some);
+    Ok(crud_reply("Alerts", "load", None, alerts, warp::http::StatusCode::OK))
+}
+
+async fn list_active_alerts(alert_monitor: Arc<alerts::AlertMonitor>) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&alert_monitor.active()))
+}
+
+// Picks up threshold changes made through the API
+fn reload_alert_thresholds(alert_monitor: &alerts::AlertMonitor) {
+    if let Err(e) = tokio::task::block_in_place(|| alert_monitor.reload()) {
+        eprintln!("❌ Failed to reload alert thresholds: {}", e);
+    }
+}
+
+async fn list_alert_thresholds(db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let thresholds = tokio::task::block_in_place(|| db.load_alert_thresholds()).map(Some);
+    Ok(crud_reply("Alert thresholds", "load", None, thresholds, warp::http::StatusCode::OK))
+}
+
+async fn put_alert_thresholds(
+    device_id: String,
+    mut thresholds: alerts::AlertThresholds,
+    db: Arc<database::Database>,
+    alert_monitor: Arc<alerts::AlertMonitor>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    thresholds.device_id = device_id.clone();
+    if let Err(message) = thresholds.validate(&alert_monitor.exec_allowlist()) {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
+    }
+    let saved = tokio::task::block_in_place(|| db.save_alert_thresholds(&thresholds)).map(|()| {
+        reload_alert_thresholds(&alert_monitor);
+        println!("🚨 Saved alert thresholds for {}", thresholds.device_id);
+        Some(thresholds)
+    });
+    Ok(crud_reply("Alert thresholds", "save", Some(&device_id), saved, warp::http::StatusCode::OK))
+}
+
+async fn delete_alert_thresholds(
+    device_id: String,
+    db: Arc<database::Database>,
+    alert_monitor: Arc<alerts::AlertMonitor>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let deleted = tokio::task::block_in_place(|| db.delete_alert_thresholds(&device_id)).map(|found| {
+        found.then(|| {
+            reload_alert_thresholds(&alert_monitor);
+            println!("🚨 Deleted alert thresholds for {}", device_id);
+            serde_json::json!({ "deleted": device_id })
+        })
+    });
+    Ok(crud_reply("Alert thresholds", "delete", Some(&device_id), deleted, warp::http::StatusCode::OK))
+}
+
+#[derive(Debug, Deserialize)]
+struct DeliveriesQuery {
+    alert_id: Option<i64>,
+    limit: Option<usize>,
+}
+
+async fn list_alert_deliveries(query: DeliveriesQuery, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(DEFAULT_ALERTS_LIMIT).min(MAX_ALERTS_LIMIT);
+    let deliveries = tokio::task::block_in_place(|| db.load_deliveries(query.alert_id, limit)).map(Some);
+    Ok(crud_reply("Alert deliveries", "load", None, deliveries, warp::http::StatusCode::OK))
+}
+
+async fn get_devices(
+    db: Arc<database::Database>,
+    hub_state: Arc<Mutex<dashboard::HubState>>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Hub always has access to its database; presence comes from the live state
+    match tokio::task::block_in_place(|| db.load_device_summaries()) {
+        Ok(mut devices) => {
+            let state = hub_state.lock().await;
+            for device in devices.iter_mut() {
+                if let Some(presence) = state.presence_of(&device.telemetry.device_id) {
+                    device.status = presence.status;
+                    device.last_seen = presence.last_seen;
+                }
+            }
+            Ok(warp::reply::with_status(warp::reply::json(&devices), warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            eprintln!("Database error loading devices: {}", e);
+            // Return empty array on error
+            Ok(warp::reply::with_status(
+                warp::reply::json(&Vec::<dashboard::DeviceSummary>::new()),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
 }
 
 async fn get_messages(
@@ -329,3 +1383,234 @@ async fn toggle_cloud(
         "message": format!("Cloud {} for {}", if is_now_enabled { "enabled" } else { "disabled" }, state.service_name)
     })))
 }
+
+#[derive(Debug, Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewUser {
+    username: String,
+    password: String,
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserUpdate {
+    password: Option<String>,
+    role: Option<Role>,
+}
+
+// Returns the bearer token, and sets it as a cookie for the browser dashboard
+async fn login(
+    request: LoginRequest,
+    accounts: Arc<accounts::Accounts>,
+    secure_cookie: bool,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match tokio::task::block_in_place(|| accounts.login(&request.username, &request.password)) {
+        Ok(Some(session)) => {
+            println!("🔑 {} logged in", session.user.username);
+            let cookie = format!(
+                "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}",
+                accounts::SESSION_COOKIE,
+                session.token,
+                accounts::SESSION_TTL_HOURS * 3600,
+                if secure_cookie { "; Secure" } else { "" }
+            );
+            Ok(Box::new(warp::reply::with_header(warp::reply::json(&session), "set-cookie", cookie)))
+        }
+        Ok(None) => {
+            println!("⚠️ Failed login for {}", request.username);
+            Ok(Box::new(json_error("Invalid username or password", warp::http::StatusCode::UNAUTHORIZED)))
+        }
+        Err(e) => {
+            eprintln!("Database error logging in {}: {}", request.username, e);
+            Ok(Box::new(json_error("Failed to log in", warp::http::StatusCode::INTERNAL_SERVER_ERROR)))
+        }
+    }
+}
+
+async fn logout(token: Option<String>, accounts: Arc<accounts::Accounts>) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(token) = token
+        && let Err(e) = tokio::task::block_in_place(|| accounts.logout(&token))
+    {
+        eprintln!("Database error logging out: {}", e);
+    }
+    let cookie = format!("{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0", accounts::SESSION_COOKIE);
+    Ok(warp::reply::with_header(warp::reply::json(&serde_json::json!({ "logged_out": true })), "set-cookie", cookie))
+}
+
+async fn list_users(db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    match tokio::task::block_in_place(|| db.load_users()) {
+        Ok(users) => Ok(warp::reply::with_status(warp::reply::json(&users), warp::http::StatusCode::OK)),
+        Err(e) => {
+            eprintln!("Database error loading users: {}", e);
+            Ok(json_error("Failed to load users", warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+async fn create_user(new_user: NewUser, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(message) = accounts::validate_username(&new_user.username).and_then(|_| accounts::validate_password(&new_user.password)) {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
+    }
+    let user = accounts::User { username: new_user.username, role: new_user.role, created_at: Utc::now().to_rfc3339() };
+    let password_hash = accounts::hash_password(&new_user.password);
+    match tokio::task::block_in_place(|| db.create_user(&user, &password_hash)) {
+        Ok(true) => {
+            println!("🔑 Created user {} ({})", user.username, user.role.as_str());
+            Ok(warp::reply::with_status(warp::reply::json(&user), warp::http::StatusCode::CREATED))
+        }
+        Ok(false) => Ok(json_error("User already exists", warp::http::StatusCode::CONFLICT)),
+        Err(e) => {
+            eprintln!("Database error creating user {}: {}", user.username, e);
+            Ok(json_error("Failed to create user", warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+// The last admin cannot be demoted or deleted, or nobody could manage users any more
+fn is_last_admin(db: &database::Database, user: &accounts::User) -> rusqlite::Result<bool> {
+    Ok(user.role == Role::Admin && db.count_admins()? <= 1)
+}
+
+async fn update_user(
+    username: String,
+    update: UserUpdate,
+    db: Arc<database::Database>,
+    accounts: Arc<accounts::Accounts>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(Err(message)) = update.password.as_deref().map(accounts::validate_password) {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
+    }
+    let result = tokio::task::block_in_place(|| -> rusqlite::Result<Result<accounts::User, (&'static str, warp::http::StatusCode)>> {
+        let Some(user) = db.get_user(&username)? else {
+            return Ok(Err(("User not found", warp::http::StatusCode::NOT_FOUND)));
+        };
+        if let Some(role) = update.role {
+            if role != Role::Admin && is_last_admin(&db, &user)? {
+                return Ok(Err(("Cannot demote the last admin", warp::http::StatusCode::CONFLICT)));
+            }
+            db.set_user_role(&username, role)?;
+        }
+        if let Some(password) = &update.password {
+            accounts.set_password(&username, password)?;
+        }
+        Ok(db.get_user(&username)?.ok_or(("User not found", warp::http::StatusCode::NOT_FOUND)))
+    });
+    match result {
+        Ok(Ok(user)) => {
+            println!("🔑 Updated user {} ({})", user.username, user.role.as_str());
+            Ok(warp::reply::with_status(warp::reply::json(&user), warp::http::StatusCode::OK))
+        }
+        Ok(Err((message, status))) => Ok(json_error(message, status)),
+        Err(e) => {
+            eprintln!("Database error updating user {}: {}", username, e);
+            Ok(json_error("Failed to update user", warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+async fn delete_user(username: String, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let result = tokio::task::block_in_place(|| -> rusqlite::Result<Result<(), (&'static str, warp::http::StatusCode)>> {
+        let Some(user) = db.get_user(&username)? else {
+            return Ok(Err(("User not found", warp::http::StatusCode::NOT_FOUND)));
+        };
+        if is_last_admin(&db, &user)? {
+            return Ok(Err(("Cannot delete the last admin", warp::http::StatusCode::CONFLICT)));
+        }
+        db.delete_user(&username)?;
+        Ok(Ok(()))
+    });
+    match result {
+        Ok(Ok(())) => {
+            println!("🔑 Deleted user {}", username);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "deleted": username })),
+                warp::http::StatusCode::OK,
+            ))
+        }
+        Ok(Err((message, status))) => Ok(json_error(message, status)),
+        Err(e) => {
+            eprintln!("Database error deleting user {}: {}", username, e);
+            Ok(json_error("Failed to delete user", warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+async fn list_device_acls(username: String, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    let result = tokio::task::block_in_place(|| -> rusqlite::Result<Option<Vec<acl::DeviceAcl>>> {
+        match db.get_user(&username)? {
+            Some(_) => Ok(Some(db.load_device_acls(&username)?)),
+            None => Ok(None),
+        }
+    });
+    match result {
+        Ok(Some(entries)) => Ok(warp::reply::with_status(warp::reply::json(&entries), warp::http::StatusCode::OK)),
+        Ok(None) => Ok(json_error("User not found", warp::http::StatusCode::NOT_FOUND)),
+        Err(e) => {
+            eprintln!("Database error loading device access for {}: {}", username, e);
+            Ok(json_error("Failed to load device access", warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+async fn put_device_acl(
+    username: String,
+    device_id: String,
+    mut entry: acl::DeviceAcl,
+    db: Arc<database::Database>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    entry.username = username;
+    entry.device_id = device_id;
+    if let Err(message) = entry.validate() {
+        return Ok(json_error(&message, warp::http::StatusCode::BAD_REQUEST));
+    }
+    let result = tokio::task::block_in_place(|| -> rusqlite::Result<bool> {
+        if db.get_user(&entry.username)?.is_none() {
+            return Ok(false);
+        }
+        db.save_device_acl(&entry)?;
+        Ok(true)
+    });
+    match result {
+        Ok(true) => {
+            println!("🔑 {} may command {}", entry.username, entry.device_id);
+            Ok(warp::reply::with_status(warp::reply::json(&entry), warp::http::StatusCode::OK))
+        }
+        Ok(false) => Ok(json_error("User not found", warp::http::StatusCode::NOT_FOUND)),
+        Err(e) => {
+            eprintln!("Database error saving device access for {}: {}", entry.username, e);
+            Ok(json_error("Failed to save device access", warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+async fn delete_device_acl(username: String, device_id: String, db: Arc<database::Database>) -> Result<impl warp::Reply, warp::Rejection> {
+    match tokio::task::block_in_place(|| db.delete_device_acl(&username, &device_id)) {
+        Ok(true) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "deleted": device_id })),
+            warp::http::StatusCode::OK,
+        )),
+        Ok(false) => Ok(json_error("Device access entry not found", warp::http::StatusCode::NOT_FOUND)),
+        Err(e) => {
+            eprintln!("Database error deleting device access for {}: {}", username, e);
+            Ok(json_error("Failed to delete device access", warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+async fn reload_config(reloader: Arc<reload::Reloader>) -> Result<impl warp::Reply, warp::Rejection> {
+    match reload::reload_and_log(&reloader) {
+        Ok(report) => Ok(warp::reply::with_status(warp::reply::json(&report), warp::http::StatusCode::OK)),
+        Err(reload::ReloadError::Invalid(problems)) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": "Invalid configuration", "problems": problems })),
+            warp::http::StatusCode::BAD_REQUEST,
+        )),
+        Err(reload::ReloadError::Database(_)) => {
+            Ok(json_error("Failed to reload automations", warp::http::StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
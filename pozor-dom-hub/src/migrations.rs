@@ -0,0 +1,560 @@
+// Versioned schema changes for the hub database. `PRAGMA user_version` records the last step a
+// file has had; pending steps run in order, each in a transaction together with its version bump
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use rusqlite::{Connection, OpenFlags, Result};
+
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    apply: fn(&Connection) -> Result<()>,
+}
+
+// Append only: a released step never changes, a schema change is a new step with the next version
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Baseline schema; databases from before versioning are upgraded in place",
+        apply: baseline,
+    },
+    Migration {
+        version: 2,
+        description: "Index telemetry samples by arrival time for rollups and retention",
+        apply: index_samples_received_at,
+    },
+    Migration {
+        version: 3,
+        description: "Grant existing non-admin users every device on hubs without access lists",
+        apply: grant_device_access_to_existing_users,
+    },
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+fn schema_error(message: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR), Some(message))
+}
+
+// Steps the database has not had yet; an error for a file written by a newer hub
+pub fn pending(conn: &Connection) -> Result<Vec<&'static Migration>> {
+    let version = schema_version(conn)?;
+    if version > latest_version() {
+        return Err(schema_error(format!(
+            "database schema version {} is newer than this hub supports ({}); upgrade pozor-dom-hub",
+            version,
+            latest_version()
+        )));
+    }
+    Ok(MIGRATIONS.iter().filter(|migration| migration.version > version).collect())
+}
+
+// Read-only users cannot migrate, so the schema has to be current already
+pub fn ensure_current(conn: &Connection) -> Result<()> {
+    if pending(conn)?.is_empty() {
+        return Ok(());
+    }
+    Err(schema_error(format!(
+        "database schema version {} is older than this hub expects ({}); run `pozor-dom-hub db migrate` first",
+        schema_version(conn)?,
+        latest_version()
+    )))
+}
+
+fn has_tables(conn: &Connection) -> Result<bool> {
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'", [], |row| row.get::<_, i64>(0))
+        .map(|count| count > 0)
+}
+
+#[derive(Debug)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub applied: Vec<&'static Migration>,
+    pub backup: Option<PathBuf>,
+}
+
+// Copies an existing database file next to it before its schema is changed
+fn backup(conn: &Connection, db_path: &str, version: u32) -> Result<PathBuf> {
+    let path = PathBuf::from(format!("{}.v{}-{}.bak", db_path, version, Utc::now().format("%Y%m%dT%H%M%SZ")));
+    conn.execute("VACUUM INTO ?1", [path.to_string_lossy()])?;
+    Ok(path)
+}
+
+pub fn migrate(conn: &mut Connection, db_path: &str) -> Result<MigrationReport> {
+    let from_version = schema_version(conn)?;
+    let pending = pending(conn)?;
+    let mut report = MigrationReport { from_version, to_version: from_version, applied: Vec::new(), backup: None };
+    if pending.is_empty() {
+        return Ok(report);
+    }
+
+    // A new database has nothing to lose; neither has an in-memory one
+    if Path::new(db_path).is_file() && has_tables(conn)? {
+        let path = backup(conn, db_path, from_version)?;
+        println!("💾 Backed up {} to {}", db_path, path.display());
+        report.backup = Some(path);
+    }
+
+    for migration in pending {
+        let tx = conn.transaction()?;
+        (migration.apply)(&tx)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+        println!("💾 Schema version {}: {}", migration.version, migration.description);
+        report.to_version = migration.version;
+        report.applied.push(migration);
+    }
+    Ok(report)
+}
+
+// What `migrate` would do to the file at `db_path`, without creating or changing it
+#[derive(Debug)]
+pub struct MigrationPlan {
+    pub exists: bool,
+    pub current_version: u32,
+    pub pending: Vec<&'static Migration>,
+    pub backup: bool,
+}
+
+pub fn plan(db_path: &str) -> Result<MigrationPlan> {
+    if !Path::new(db_path).is_file() {
+        return Ok(MigrationPlan { exists: false, current_version: 0, pending: MIGRATIONS.iter().collect(), backup: false });
+    }
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let pending = pending(&conn)?;
+    Ok(MigrationPlan {
+        exists: true,
+        current_version: schema_version(&conn)?,
+        backup: !pending.is_empty() && has_tables(&conn)?,
+        pending,
+    })
+}
+
+const DEVICES_TABLE: &str = "CREATE TABLE IF NOT EXISTS devices (
+    device_id TEXT PRIMARY KEY,
+    channel TEXT NOT NULL,
+    device_type TEXT NOT NULL,
+    capabilities TEXT NOT NULL,
+    signal_strength INTEGER NOT NULL,
+    timestamp TEXT NOT NULL,
+    last_seen TEXT NOT NULL
+)";
+
+// `temperature`/`humidity` are copied out of `capabilities` so the rollup can aggregate them in SQL
+const TELEMETRY_SAMPLES_TABLE: &str = "CREATE TABLE IF NOT EXISTS telemetry_samples (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    device_type TEXT NOT NULL,
+    capabilities TEXT NOT NULL,
+    temperature REAL,
+    humidity REAL,
+    signal_strength INTEGER NOT NULL,
+    timestamp TEXT NOT NULL,
+    received_at TEXT NOT NULL
+)";
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Tables created before the capability model stored fixed temperature/humidity
+// columns; rebuild them so those readings become capability entries
+fn upgrade_fixed_sensor_tables(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "devices", "capabilities")? && has_column(conn, "devices", "temperature")? {
+        conn.execute("ALTER TABLE devices RENAME TO devices_fixed_sensor", [])?;
+        conn.execute(DEVICES_TABLE, [])?;
+        conn.execute(
+            "INSERT INTO devices (device_id, channel, device_type, capabilities, signal_strength, timestamp, last_seen)
+             SELECT device_id, channel, 'sensor',
+                    json_object('temperature', CAST(temperature AS REAL), 'humidity', CAST(humidity AS REAL)),
+                    signal_strength, timestamp, last_seen
+             FROM devices_fixed_sensor",
+            [],
+        )?;
+        conn.execute("DROP TABLE devices_fixed_sensor", [])?;
+        println!("💾 Upgraded devices table to capability model");
+    }
+
+    if !has_column(conn, "telemetry_samples", "capabilities")? && has_column(conn, "telemetry_samples", "temperature")? {
+        conn.execute("ALTER TABLE telemetry_samples RENAME TO telemetry_samples_fixed_sensor", [])?;
+        conn.execute(TELEMETRY_SAMPLES_TABLE, [])?;
+        conn.execute(
+            "INSERT INTO telemetry_samples
+             (id, device_id, channel, device_type, capabilities, temperature, humidity, signal_strength, timestamp, received_at)
+             SELECT id, device_id, channel, 'sensor',
+                    json_object('temperature', CAST(temperature AS REAL), 'humidity', CAST(humidity AS REAL)),
+                    CAST(temperature AS REAL), CAST(humidity AS REAL),
+                    signal_strength, timestamp, received_at
+             FROM telemetry_samples_fixed_sensor",
+            [],
+        )?;
+        conn.execute("DROP TABLE telemetry_samples_fixed_sensor", [])?;
+        println!("💾 Upgraded telemetry_samples table to capability model");
+    }
+
+    Ok(())
+}
+
+// Version 1: everything `Database::new` created before migrations were versioned. Every statement
+// tolerates existing tables, so files from any earlier release end up with the same schema
+fn baseline(conn: &Connection) -> Result<()> {
+    upgrade_fixed_sensor_tables(conn)?;
+
+    // Create devices table if it doesn't exist
+    conn.execute(DEVICES_TABLE, [])?;
+
+    // Create messages table for logging
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            content TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create config table for storing configuration
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS config (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create telemetry history table (one row per received reading)
+    conn.execute(TELEMETRY_SAMPLES_TABLE, [])?;
+
+    // Create downsampled telemetry table (1m and 1h buckets)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS telemetry_aggregates (
+            device_id TEXT NOT NULL,
+            resolution TEXT NOT NULL,
+            bucket_start TEXT NOT NULL,
+            sample_count INTEGER NOT NULL,
+            temperature_min REAL,
+            temperature_max REAL,
+            temperature_avg REAL,
+            humidity_min REAL,
+            humidity_max REAL,
+            humidity_avg REAL,
+            signal_strength_min INTEGER NOT NULL,
+            signal_strength_max INTEGER NOT NULL,
+            signal_strength_avg REAL NOT NULL,
+            PRIMARY KEY (device_id, resolution, bucket_start)
+        )",
+        [],
+    )?;
+
+    // Create automation rules table; trigger, conditions and actions are kept as JSON
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            enabled INTEGER NOT NULL,
+            definition TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create scenes table; member commands are kept as JSON
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS scenes (
+            name TEXT PRIMARY KEY,
+            definition TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create schedules table; timing and action are kept as JSON
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schedules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            enabled INTEGER NOT NULL,
+            definition TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create alert history table; every raise and clear is a row
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS alerts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            device_id TEXT NOT NULL,
+            metric TEXT NOT NULL,
+            condition TEXT NOT NULL,
+            threshold REAL NOT NULL,
+            value REAL NOT NULL,
+            state TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create alert thresholds table, one JSON definition per device ('*' for the defaults)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS alert_thresholds (
+            device_id TEXT PRIMARY KEY,
+            definition TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create alert delivery log; one row per attempt
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS notification_deliveries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            alert_id INTEGER NOT NULL,
+            sink TEXT NOT NULL,
+            target TEXT NOT NULL,
+            attempt INTEGER NOT NULL,
+            success INTEGER NOT NULL,
+            detail TEXT,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create hub user accounts; passwords are stored as PBKDF2 hashes
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+            username TEXT PRIMARY KEY,
+            password_hash TEXT NOT NULL,
+            role TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create login sessions, keyed by the SHA-256 digest of the bearer token
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            token_digest TEXT PRIMARY KEY,
+            username TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            expires_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Create per-user device access lists; `actions` is a JSON array, empty for every action
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS device_acls (
+            username TEXT NOT NULL,
+            device_id TEXT NOT NULL,
+            actions TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            PRIMARY KEY (username, device_id)
+        )",
+        [],
+    )?;
+
+    // Create indexes for better performance
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_devices_channel ON devices(channel)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_devices_timestamp ON devices(timestamp)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_messages_timestamp ON messages(timestamp)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_samples_device_time ON telemetry_samples(device_id, received_at)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_alerts_device_metric ON alerts(device_id, metric)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_deliveries_alert ON notification_deliveries(alert_id)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_sessions_username ON sessions(username)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn index_samples_received_at(conn: &Connection) -> Result<()> {
+    conn.execute("CREATE INDEX idx_samples_received_at ON telemetry_samples(received_at)", [])?;
+    Ok(())
+}
+
+// Non-admins now need an entry for every device they command. A hub without entries used to
+// limit users by role only, so its users get `*` and keep the access they had
+fn grant_device_access_to_existing_users(conn: &Connection) -> Result<()> {
+    let lists_in_use: bool = conn.query_row("SELECT EXISTS(SELECT 1 FROM device_acls)", [], |row| row.get(0))?;
+    if !lists_in_use {
+        conn.execute(
+            "INSERT INTO device_acls (username, device_id, actions, updated_at)
+             SELECT username, '*', '[]', ?1 FROM users WHERE role != 'admin'",
+            [Utc::now().to_rfc3339()],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A database file of its own, removed with its backups when dropped
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("pozor-dom-{}-{}.db", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            if let (Some(dir), Some(name)) = (self.0.parent(), self.0.file_name()) {
+                let prefix = name.to_string_lossy().to_string();
+                for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+                    if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                        let _ = std::fs::remove_file(entry.path());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_versions_are_ordered() {
+        let versions: Vec<u32> = MIGRATIONS.iter().map(|migration| migration.version).collect();
+        let expected: Vec<u32> = (1..=MIGRATIONS.len() as u32).collect();
+        assert_eq!(versions, expected);
+    }
+
+    #[test]
+    fn test_new_database_runs_every_step_once() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let report = migrate(&mut conn, ":memory:").unwrap();
+        assert_eq!((report.from_version, report.to_version), (0, latest_version()));
+        assert_eq!(report.applied.len(), MIGRATIONS.len());
+        assert!(report.backup.is_none());
+
+        let again = migrate(&mut conn, ":memory:").unwrap();
+        assert!(again.applied.is_empty());
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn test_unversioned_file_is_backed_up_and_upgraded() {
+        let db = TempDb::new("migrate-legacy");
+        {
+            let conn = Connection::open(db.path()).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE devices (
+                    device_id TEXT PRIMARY KEY, channel TEXT NOT NULL, temperature REAL, humidity REAL,
+                    signal_strength INTEGER NOT NULL, timestamp TEXT NOT NULL, last_seen TEXT NOT NULL
+                );
+                INSERT INTO devices VALUES ('sensor-001', 'ZigBee', 21.5, 40.0, -60, '2024-01-01T00:00:00Z', '2024-01-01T00:00:00Z');",
+            )
+            .unwrap();
+        }
+
+        let plan = plan(db.path()).unwrap();
+        assert!(plan.exists && plan.backup);
+        assert_eq!((plan.current_version, plan.pending.len()), (0, MIGRATIONS.len()));
+        // A dry run leaves the file alone
+        assert_eq!(schema_version(&Connection::open(db.path()).unwrap()).unwrap(), 0);
+
+        let mut conn = Connection::open(db.path()).unwrap();
+        let report = migrate(&mut conn, db.path()).unwrap();
+        assert_eq!(report.to_version, latest_version());
+        let capabilities: String = conn.query_row("SELECT capabilities FROM devices", [], |row| row.get(0)).unwrap();
+        assert!(capabilities.contains("temperature"));
+
+        // The backup is the file as it was: unversioned, with the old columns
+        let backup = Connection::open(report.backup.expect("backup of an existing file")).unwrap();
+        assert_eq!(schema_version(&backup).unwrap(), 0);
+        let temperature: f64 = backup.query_row("SELECT temperature FROM devices", [], |row| row.get(0)).unwrap();
+        assert_eq!(temperature, 21.5);
+    }
+
+    #[test]
+    fn test_users_keep_device_access_on_hubs_without_lists() {
+        let grants = |lists: &str| {
+            let mut conn = Connection::open_in_memory().unwrap();
+            for migration in &MIGRATIONS[..2] {
+                (migration.apply)(&conn).unwrap();
+            }
+            conn.pragma_update(None, "user_version", 2).unwrap();
+            conn.execute_batch(&format!(
+                "INSERT INTO users VALUES ('admin', '', 'admin', ''), ('alice', '', 'operator', ''), ('bob', '', 'viewer', '');
+                 {}",
+                lists
+            ))
+            .unwrap();
+            migrate(&mut conn, ":memory:").unwrap();
+            let mut stmt = conn.prepare("SELECT username || ':' || device_id FROM device_acls ORDER BY username").unwrap();
+            stmt.query_map([], |row| row.get::<_, String>(0)).unwrap().collect::<Result<Vec<_>>>().unwrap()
+        };
+
+        assert_eq!(grants(""), vec!["alice:*", "bob:*"]);
+        // Where lists were already in use, users without entries had no devices and still have none
+        assert_eq!(grants("INSERT INTO device_acls VALUES ('alice', 'light-*', '[]', '');"), vec!["alice:light-*"]);
+    }
+
+    #[test]
+    fn test_newer_schema_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+        let error = migrate(&mut conn, ":memory:").unwrap_err();
+        assert!(error.to_string().contains("newer than this hub supports"));
+    }
+
+    #[test]
+    fn test_read_only_access_needs_current_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(ensure_current(&conn).unwrap_err().to_string().contains("db migrate"));
+        migrate(&mut conn, ":memory:").unwrap();
+        assert!(ensure_current(&conn).is_ok());
+    }
+
+    #[test]
+    fn test_plan_for_missing_file() {
+        let db = TempDb::new("migrate-missing");
+        let plan = plan(db.path()).unwrap();
+        assert!(!plan.exists && !plan.backup);
+        assert_eq!(plan.pending.len(), MIGRATIONS.len());
+        assert!(!Path::new(db.path()).exists());
+    }
+}
@@ -1,55 +1,90 @@
-use rumqttc::{AsyncClient, MqttOptions, QoS};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, QoS};
 use std::sync::Arc;
 use std::time::Duration;
-use serde_json::Value;
+use pozor_dom_shared::config::MqttConfig;
+use pozor_dom_shared::envelope::{DeviceCommand, Envelope};
 
-pub async fn setup_mqtt_client() -> (AsyncClient, rumqttc::EventLoop) {
-    let mut opts = MqttOptions::new("pozor-dom-hub", "127.0.0.1", 1883);
+pub fn mqtt_options(config: &MqttConfig) -> MqttOptions {
+    let mut opts = MqttOptions::new("pozor-dom-hub", config.host.as_str(), config.port);
     opts.set_keep_alive(Duration::from_secs(5));
+    opts
+}
 
-    let (client, eventloop) = AsyncClient::new(opts, 100);
+pub fn setup_mqtt_client(config: &MqttConfig) -> (AsyncClient, EventLoop) {
+    AsyncClient::new(mqtt_options(config), 100)
+}
 
-    tokio::time::sleep(Duration::from_millis(500)).await;
+// Requests already queued (command publishes) go out first, then DISCONNECT
+pub async fn disconnect(client: &AsyncClient, eventloop: &mut EventLoop) {
+    if client.try_disconnect().is_err() {
+        return;
+    }
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                println!("🔌 Disconnected from MQTT broker");
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("MQTT error while disconnecting: {}", e);
+                return;
+            }
+        }
+    }
+}
 
-    // Subscribe to telemetry
-    match client.subscribe("pozor-dom/device/+/telemetry", QoS::AtMostOnce).await {
-        Ok(_) => println!("✅ Hub subscribed to device telemetry"),
-        Err(e) => eprintln!("❌ Hub failed to subscribe to telemetry: {}", e),
+// Subscriptions end with the (clean) session, so they are renewed on every connection to the broker
+pub fn subscribe_topics(client: &AsyncClient) {
+    let topics = [
+        ("pozor-dom/device/+/telemetry", QoS::AtMostOnce, "device telemetry"),
+        ("pozor-dom/device/+/command_result", QoS::AtLeastOnce, "device command results"),
+        // Birth messages and last wills, retained
+        ("pozor-dom/device/+/status", QoS::AtLeastOnce, "device status"),
+    ];
+    for (topic, qos, name) in topics {
+        match client.try_subscribe(topic, qos) {
+            Ok(_) => println!("✅ Hub subscribed to {}", name),
+            Err(e) => eprintln!("❌ Hub failed to subscribe to {}: {}", name, e),
+        }
     }
+}
 
-    (client, eventloop)
+// `pozor-dom/device/{id}/{kind}` -> `{id}`
+pub fn device_id_from_topic(topic: &str) -> Option<&str> {
+    let mut parts = topic.split('/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("pozor-dom"), Some("device"), Some(device_id)) if !device_id.is_empty() => Some(device_id),
+        _ => None,
+    }
 }
 
 
 
 pub async fn send_device_command(
-    command: &Value,
+    command: &DeviceCommand,
     mqtt_client: &Arc<AsyncClient>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if let (Some(device_id), Some(channel), Some(action)) = (
-        command["device_id"].as_str(),
-        command["channel"].as_str(),
-        command["action"].as_str(),
-    ) {
-        let topic = format!("pozor-dom/hub/command/{}", device_id);
-
-        println!(
-            "� Relaying to MQTT - Device: {} | Channel: {} | Action: {}",
-            device_id, channel, action
-        );
-
-        mqtt_client
-            .publish(
-                &topic,
-                QoS::AtLeastOnce,
-                false,
-                command.to_string().into_bytes(),
-            )
-            .await?;
-
-        println!("✅ Published to MQTT: {}", topic);
-        Ok(())
-    } else {
-        Err("Invalid command format".into())
+    if command.device_id.is_empty() || command.action.is_empty() {
+        return Err("Invalid command format".into());
     }
+
+    let topic = format!("pozor-dom/hub/command/{}", command.device_id);
+
+    println!(
+        "📤 Relaying to MQTT - Device: {} | Channel: {} | Action: {}",
+        command.device_id, command.channel, command.action
+    );
+
+    mqtt_client
+        .publish(
+            &topic,
+            QoS::AtLeastOnce,
+            false,
+            Envelope::command(command.clone()).to_json().into_bytes(),
+        )
+        .await?;
+
+    println!("✅ Published to MQTT: {}", topic);
+    Ok(())
 }
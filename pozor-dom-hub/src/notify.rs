@@ -0,0 +1,555 @@
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use pozor_dom_shared::envelope::{AlertEvent, Envelope};
+use crate::database::{Database, NotificationDelivery};
+use crate::shutdown::Shutdown;
+
+// Upper bound for a single delivery attempt
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Where an alert should be delivered besides the hub's own broadcast
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    // POSTs the alert as JSON
+    Webhook {
+        url: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+    },
+    // Plain SMTP to a relay on the local network, without TLS or authentication
+    Email {
+        smtp_host: String,
+        #[serde(default = "default_smtp_port")]
+        smtp_port: u16,
+        from: String,
+        to: Vec<String>,
+    },
+    // Runs a program listed in POZOR_DOM_NOTIFY_EXEC_ALLOW with the alert JSON on stdin
+    Exec {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+fn has_line_break(value: &str) -> bool {
+    value.contains(['\r', '\n'])
+}
+
+impl SinkConfig {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SinkConfig::Webhook { .. } => "webhook",
+            SinkConfig::Email { .. } => "email",
+            SinkConfig::Exec { .. } => "exec",
+        }
+    }
+
+    pub fn validate(&self, exec_allowlist: &[String]) -> Result<(), String> {
+        match self {
+            SinkConfig::Webhook { url, headers } => {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err("webhook url must start with http:// or https://".to_string());
+                }
+                if headers.iter().any(|(name, value)| name.is_empty() || has_line_break(name) || has_line_break(value)) {
+                    return Err("invalid webhook header".to_string());
+                }
+            }
+            SinkConfig::Email { smtp_host, from, to, .. } => {
+                if smtp_host.is_empty() || from.is_empty() || to.is_empty() {
+                    return Err("email sinks need smtp_host, from and at least one recipient".to_string());
+                }
+                // Addresses end up in SMTP commands and headers verbatim
+                if std::iter::once(from).chain(to).any(|address| has_line_break(address) || address.contains(['<', '>'])) {
+                    return Err("invalid email address".to_string());
+                }
+            }
+            SinkConfig::Exec { program, .. } => {
+                if !exec_allowlist.contains(program) {
+                    return Err(format!("{} is not listed in POZOR_DOM_NOTIFY_EXEC_ALLOW", program));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub trait Notifier: Send + Sync {
+    // Where notifications go, for the delivery log
+    fn target(&self) -> String;
+    fn send<'a>(&'a self, alert: &'a AlertEvent) -> BoxFuture<'a, Result<(), String>>;
+}
+
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+    headers: BTreeMap<String, String>,
+}
+
+impl Notifier for WebhookNotifier {
+    fn target(&self) -> String {
+        self.url.clone()
+    }
+
+    fn send<'a>(&'a self, alert: &'a AlertEvent) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let mut request = self.client.post(&self.url).timeout(DELIVERY_TIMEOUT).json(alert);
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
+            }
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("HTTP {}", response.status()));
+            }
+            Ok(())
+        })
+    }
+}
+
+pub struct EmailNotifier {
+    smtp_host: String,
+    smtp_port: u16,
+    from: String,
+    to: Vec<String>,
+}
+
+struct SmtpSession {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl SmtpSession {
+    // Reads one possibly multi-line reply ("250-..." continues, "250 ..." ends it)
+    async fn reply(&mut self) -> Result<(u16, String), String> {
+        let mut text = String::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 {
+                return Err("SMTP server closed the connection".to_string());
+            }
+            let code = line
+                .get(..3)
+                .and_then(|code| code.parse::<u16>().ok())
+                .ok_or_else(|| format!("invalid SMTP reply: {}", line.trim_end()))?;
+            text.push_str(line.get(4..).unwrap_or_default().trim_end());
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok((code, text));
+            }
+            text.push(' ');
+        }
+    }
+
+    // Fails unless the reply is in the same class (2xx, 3xx) as `expected`
+    async fn expect(&mut self, what: &str, expected: u16) -> Result<(), String> {
+        let (code, text) = self.reply().await?;
+        if code / 100 != expected / 100 {
+            return Err(format!("{}: {} {}", what, code, text));
+        }
+        Ok(())
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<(), String> {
+        self.writer.write_all(format!("{}\r\n", command).as_bytes()).await.map_err(|e| e.to_string())?;
+        let verb = command.split([' ', ':']).next().unwrap_or(command);
+        self.expect(verb, expected).await
+    }
+}
+
+impl EmailNotifier {
+    fn message(&self, alert: &AlertEvent) -> String {
+        let subject = format!("Pozor-dom: {} {} {}", alert.device_id, alert.metric.as_str(), alert.state.as_str());
+        let body = format!("{}\r\nTime: {}\r\n", Envelope::alert(alert.clone()), alert.timestamp.to_rfc3339());
+        let mut message = format!(
+            "From: <{}>\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.from,
+            self.to.iter().map(|to| format!("<{}>", to)).collect::<Vec<_>>().join(", "),
+            subject,
+            chrono::Utc::now().to_rfc2822(),
+        );
+        // Dot-stuffing, so that no body line ends the DATA section early
+        for line in body.lines() {
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+
+    async fn deliver(&self, alert: &AlertEvent) -> Result<(), String> {
+        let stream = TcpStream::connect((self.smtp_host.as_str(), self.smtp_port)).await.map_err(|e| e.to_string())?;
+        let (reader, writer) = stream.into_split();
+        let mut session = SmtpSession { reader: BufReader::new(reader), writer };
+
+        session.expect("greeting", 220).await?;
+        session.command("EHLO pozor-dom-hub", 250).await?;
+        session.command(&format!("MAIL FROM:<{}>", self.from), 250).await?;
+        for to in &self.to {
+            session.command(&format!("RCPT TO:<{}>", to), 250).await?;
+        }
+        session.command("DATA", 354).await?;
+        session.writer.write_all(self.message(alert).as_bytes()).await.map_err(|e| e.to_string())?;
+        session.command(".", 250).await?;
+        // The message is accepted at this point
+        let _ = session.command("QUIT", 221).await;
+        Ok(())
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn target(&self) -> String {
+        self.to.join(", ")
+    }
+
+    fn send<'a>(&'a self, alert: &'a AlertEvent) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            tokio::time::timeout(DELIVERY_TIMEOUT, self.deliver(alert))
+                .await
+                .map_err(|_| "SMTP delivery timed out".to_string())?
+        })
+    }
+}
+
+pub struct ExecNotifier {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Notifier for ExecNotifier {
+    fn target(&self) -> String {
+        self.program.clone()
+    }
+
+    fn send<'a>(&'a self, alert: &'a AlertEvent) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let json = serde_json::to_string(alert).map_err(|e| e.to_string())?;
+            let mut child = tokio::process::Command::new(&self.program)
+                .args(&self.args)
+                .env("POZOR_DOM_ALERT_DEVICE", &alert.device_id)
+                .env("POZOR_DOM_ALERT_METRIC", alert.metric.as_str())
+                .env("POZOR_DOM_ALERT_STATE", alert.state.as_str())
+                .env("POZOR_DOM_ALERT_VALUE", alert.value.to_string())
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| format!("failed to start {}: {}", self.program, e))?;
+
+            // Programs that ignore stdin may exit before reading it
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(json.as_bytes()).await;
+            }
+            let output = tokio::time::timeout(DELIVERY_TIMEOUT, child.wait_with_output())
+                .await
+                .map_err(|_| format!("{} timed out", self.program))?
+                .map_err(|e| e.to_string())?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("{} {}", output.status, stderr.trim()));
+            }
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    // Doubled after every failed attempt
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 4, initial_backoff: Duration::from_secs(2) }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff * 2u32.saturating_pow(attempt.saturating_sub(1))
+    }
+}
+
+pub struct NotificationDispatcher {
+    db: Arc<Database>,
+    http: reqwest::Client,
+    // Replaced when the configuration is reloaded
+    exec_allowlist: RwLock<Vec<String>>,
+    retry: RetryPolicy,
+    // Deliveries in flight are waited for on shutdown, so their log entries are written
+    shutdown: Shutdown,
+}
+
+impl NotificationDispatcher {
+    pub fn new(db: Arc<Database>, exec_allowlist: Vec<String>, shutdown: Shutdown) -> Self {
+        if !exec_allowlist.is_empty() {
+            println!("📣 Alert exec sinks may run: {}", exec_allowlist.join(", "));
+        }
+        Self { db, http: reqwest::Client::new(), exec_allowlist: RwLock::new(exec_allowlist), retry: RetryPolicy::default(), shutdown }
+    }
+
+    pub fn exec_allowlist(&self) -> Vec<String> {
+        self.exec_allowlist.read().unwrap().clone()
+    }
+
+    pub fn set_exec_allowlist(&self, exec_allowlist: Vec<String>) {
+        *self.exec_allowlist.write().unwrap() = exec_allowlist;
+    }
+
+    // Sinks are checked again here: the allowlist may have shrunk since they were saved
+    fn notifier(&self, sink: &SinkConfig) -> Result<Box<dyn Notifier>, String> {
+        sink.validate(&self.exec_allowlist())?;
+        Ok(match sink.clone() {
+            SinkConfig::Webhook { url, headers } => Box::new(WebhookNotifier { client: self.http.clone(), url, headers }),
+            SinkConfig::Email { smtp_host, smtp_port, from, to } => Box::new(EmailNotifier { smtp_host, smtp_port, from, to }),
+            SinkConfig::Exec { program, args } => Box::new(ExecNotifier { program, args }),
+        })
+    }
+
+    // Delivers to every sink in the background; each attempt is written to the delivery log
+    pub fn dispatch(self: &Arc<Self>, alert: AlertEvent, sinks: Vec<SinkConfig>) {
+        for sink in sinks {
+            let dispatcher = Arc::clone(self);
+            let alert = alert.clone();
+            self.shutdown.spawn(async move {
+                dispatcher.deliver(&alert, &sink).await;
+            });
+        }
+    }
+
+    async fn deliver(&self, alert: &AlertEvent, sink: &SinkConfig) -> bool {
+        let notifier = match self.notifier(sink) {
+            Ok(notifier) => notifier,
+            Err(e) => {
+                eprintln!("❌ Not delivering alert {} via {}: {}", alert.id, sink.kind(), e);
+                self.log(alert, sink.kind(), "", 1, &Err(e));
+                return false;
+            }
+        };
+
+        for attempt in 1..=self.retry.max_attempts {
+            let result = notifier.send(alert).await;
+            self.log(alert, sink.kind(), &notifier.target(), attempt, &result);
+            match result {
+                Ok(()) => {
+                    println!("📣 Alert {} delivered via {} to {}", alert.id, sink.kind(), notifier.target());
+                    return true;
+                }
+                Err(e) => {
+                    eprintln!("❌ Alert {} delivery via {} failed (attempt {}): {}", alert.id, sink.kind(), attempt, e);
+                    if attempt < self.retry.max_attempts {
+                        tokio::select! {
+                            _ = tokio::time::sleep(self.retry.backoff(attempt)) => {}
+                            _ = self.shutdown.cancelled() => {
+                                eprintln!("⚠️ Alert {} via {}: no more retries, the hub is shutting down", alert.id, sink.kind());
+                                return false;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    fn log(&self, alert: &AlertEvent, sink: &str, target: &str, attempt: u32, result: &Result<(), String>) {
+        let delivery = NotificationDelivery {
+            id: 0,
+            alert_id: alert.id,
+            sink: sink.to_string(),
+            target: target.to_string(),
+            attempt,
+            success: result.is_ok(),
+            detail: result.as_ref().err().cloned(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        if let Err(e) = tokio::task::block_in_place(|| self.db.insert_delivery(&delivery)) {
+            eprintln!("❌ Failed to log alert delivery: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use pozor_dom_shared::envelope::{AlertCondition, AlertMetric, AlertState};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn alert() -> AlertEvent {
+        AlertEvent {
+            id: 1,
+            device_id: "device-wifi-001".to_string(),
+            metric: AlertMetric::Temperature,
+            condition: AlertCondition::Above,
+            threshold: 28.0,
+            value: 29.5,
+            state: AlertState::Raised,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn dispatcher(exec_allowlist: Vec<String>) -> NotificationDispatcher {
+        NotificationDispatcher {
+            db: Arc::new(Database::new(":memory:").unwrap()),
+            http: reqwest::Client::new(),
+            exec_allowlist: RwLock::new(exec_allowlist),
+            retry: RetryPolicy { max_attempts: 3, initial_backoff: Duration::from_millis(10) },
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    // Stand-in webhook receiver: answers the first `failures` requests with 503, then 200
+    async fn http_stand_in(failures: usize) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for request in 0.. {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = Vec::new();
+                let mut chunk = [0u8; 4096];
+                // Headers, then as much body as Content-Length announces
+                let body = loop {
+                    let read = stream.read(&mut chunk).await.unwrap();
+                    if read == 0 {
+                        break String::new();
+                    }
+                    buffer.extend_from_slice(&chunk[..read]);
+                    let text = String::from_utf8_lossy(&buffer).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break body.to_string();
+                        }
+                    }
+                };
+                let status = if request < failures { "503 Service Unavailable" } else { "200 OK" };
+                let _ = stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).as_bytes()).await;
+                let _ = tx.send(body);
+            }
+        });
+        (url, rx)
+    }
+
+    // Stand-in SMTP relay that accepts everything and hands over the DATA section
+    async fn smtp_stand_in() -> (u16, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 stand-in ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = match line.split(' ').next().unwrap() {
+                    "EHLO" => b"250-stand-in\r\n250 8BITMIME\r\n",
+                    "DATA" => {
+                        writer.write_all(b"354 end with .\r\n").await.unwrap();
+                        let mut data = String::new();
+                        while let Some(line) = lines.next_line().await.unwrap() {
+                            if line == "." {
+                                break;
+                            }
+                            data.push_str(&line);
+                            data.push('\n');
+                        }
+                        let _ = tx.send(data);
+                        b"250 queued\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 OK\r\n",
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+        });
+        (port, rx)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_webhook_retries_until_delivered() {
+        let (url, mut requests) = http_stand_in(1).await;
+        let dispatcher = dispatcher(Vec::new());
+        let sink = SinkConfig::Webhook { url, headers: BTreeMap::from([("X-Token".to_string(), "secret".to_string())]) };
+        let alert = alert();
+
+        assert!(dispatcher.deliver(&alert, &sink).await);
+        let _rejected = requests.recv().await.unwrap();
+        let delivered: AlertEvent = serde_json::from_str(&requests.recv().await.unwrap()).unwrap();
+        assert_eq!(delivered, alert);
+
+        let log = dispatcher.db.load_deliveries(Some(1), 10).unwrap();
+        let attempts: Vec<(u32, bool)> = log.iter().map(|delivery| (delivery.attempt, delivery.success)).collect();
+        assert_eq!(attempts, vec![(2, true), (1, false)]);
+        assert_eq!(log[1].detail.as_deref(), Some("HTTP 503 Service Unavailable"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_webhook_gives_up_after_max_attempts() {
+        let (url, _requests) = http_stand_in(usize::MAX).await;
+        let dispatcher = dispatcher(Vec::new());
+
+        assert!(!dispatcher.deliver(&alert(), &SinkConfig::Webhook { url, headers: BTreeMap::new() }).await);
+        assert_eq!(dispatcher.db.load_deliveries(None, 10).unwrap().len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_email_via_smtp() {
+        let (smtp_port, mut messages) = smtp_stand_in().await;
+        let dispatcher = dispatcher(Vec::new());
+        let sink = SinkConfig::Email {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port,
+            from: "hub@pozor-dom.local".to_string(),
+            to: vec!["owner@example.com".to_string()],
+        };
+
+        assert!(dispatcher.deliver(&alert(), &sink).await);
+        let message = messages.recv().await.unwrap();
+        assert!(message.contains("Subject: Pozor-dom: device-wifi-001 temperature raised"));
+        assert!(message.contains("Alert raised: device-wifi-001 temperature above 28 (value 29.5)"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_exec_needs_allowlist() {
+        let sink = SinkConfig::Exec {
+            program: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), r#"test "$POZOR_DOM_ALERT_STATE" = raised && grep -q device-wifi-001"#.to_string()],
+        };
+        assert!(sink.validate(&[]).is_err());
+        assert!(!dispatcher(Vec::new()).deliver(&alert(), &sink).await);
+        assert!(dispatcher(vec!["/bin/sh".to_string()]).deliver(&alert(), &sink).await);
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let retry = RetryPolicy::default();
+        assert_eq!(retry.backoff(1), Duration::from_secs(2));
+        assert_eq!(retry.backoff(3), Duration::from_secs(8));
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
+use tokio::sync::{broadcast, Mutex};
+use pozor_dom_shared::dashboard::HubState;
+use pozor_dom_shared::envelope::Envelope;
+use crate::reload::LiveSettings;
+use crate::rules::{RuleEngine, RuleEvent};
+use crate::shutdown::Shutdown;
+
+// How often devices are checked for missed telemetry
+const PRESENCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub async fn run_presence_monitor(
+    hub_state: Arc<Mutex<HubState>>,
+    broadcast_tx: Arc<broadcast::Sender<String>>,
+    rule_engine: Arc<RuleEngine>,
+    settings: Arc<LiveSettings>,
+    shutdown: Shutdown,
+) {
+    println!(
+        "💓 Presence: devices go offline after {} missed telemetry intervals",
+        settings.presence_missed_intervals()
+    );
+
+    let mut interval = tokio::time::interval(PRESENCE_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return,
+        }
+
+        let mut fired = Vec::new();
+        {
+            let mut state = hub_state.lock().await;
+            for presence in state.expire_stale(Utc::now(), settings.presence_missed_intervals()) {
+                println!("💤 Device {} went offline (no telemetry since {})", presence.device_id, presence.last_seen);
+                fired.extend(rule_engine.evaluate(&RuleEvent::Presence(&presence), &state));
+                let _ = broadcast_tx.send(Envelope::presence(presence).to_json());
+            }
+        }
+        // Rule actions send device commands, so they stop with the hub
+        if !fired.is_empty() {
+            tokio::select! {
+                _ = rule_engine.execute(fired, &hub_state) => {}
+                _ = shutdown.cancelled() => return,
+            }
+        }
+    }
+}
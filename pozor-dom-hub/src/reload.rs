@@ -0,0 +1,267 @@
+// Configuration reload without a restart, on SIGHUP or POST /api/admin/reload: the file,
+// environment and --set overrides are read again and applied to the running services
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use pozor_dom_shared::config::{Config, ConfigArgs, HubConfig, MqttConfig};
+use crate::alerts::AlertMonitor;
+use crate::notify::NotificationDispatcher;
+use crate::rules::RuleEngine;
+use crate::scheduler::Scheduler;
+use crate::sun::Location;
+use crate::{CloudCommand, CloudLink};
+
+// Settings read by background tasks on every tick
+pub struct LiveSettings {
+    raw_retention_hours: AtomicU64,
+    presence_missed_intervals: AtomicU32,
+    shutdown_timeout_secs: AtomicU64,
+}
+
+impl LiveSettings {
+    pub fn new(hub: &HubConfig) -> Self {
+        Self {
+            raw_retention_hours: AtomicU64::new(hub.raw_retention_hours),
+            presence_missed_intervals: AtomicU32::new(hub.presence_missed_intervals),
+            shutdown_timeout_secs: AtomicU64::new(hub.shutdown_timeout_secs),
+        }
+    }
+
+    pub fn raw_retention_hours(&self) -> u64 {
+        self.raw_retention_hours.load(Ordering::Relaxed)
+    }
+
+    pub fn presence_missed_intervals(&self) -> u32 {
+        self.presence_missed_intervals.load(Ordering::Relaxed)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs.load(Ordering::Relaxed))
+    }
+
+    fn apply(&self, hub: &HubConfig) {
+        self.raw_retention_hours.store(hub.raw_retention_hours, Ordering::Relaxed);
+        self.presence_missed_intervals.store(hub.presence_missed_intervals, Ordering::Relaxed);
+        self.shutdown_timeout_secs.store(hub.shutdown_timeout_secs, Ordering::Relaxed);
+    }
+}
+
+// What happens to the running hub when a setting changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Effect {
+    Live,
+    Restart,
+    // Read once when the database is created
+    Ignored,
+    // Settings of the other binaries
+    NotHub,
+}
+
+fn effect(key: &str) -> Effect {
+    match key {
+        "hub.host" | "hub.port" | "hub.web_port" | "hub.db_path" | "tls.cert" | "tls.key" => Effect::Restart,
+        "hub.admin_password" => Effect::Ignored,
+        _ if key.starts_with("hub.") || key.starts_with("tls.") => Effect::Live,
+        _ => Effect::NotHub,
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ReloadReport {
+    // Changed settings now in effect
+    pub applied: Vec<String>,
+    // Changed settings that take effect on the next start
+    pub restart_required: Vec<String>,
+    pub ignored: Vec<String>,
+    // Automations re-read from the database
+    pub rules: usize,
+    pub alert_thresholds: usize,
+    pub schedules: usize,
+}
+
+#[derive(Debug)]
+pub enum ReloadError {
+    // The new configuration was rejected and nothing was applied
+    Invalid(Vec<String>),
+    Database(rusqlite::Error),
+}
+
+impl std::fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ReloadError::Invalid(problems) => write!(f, "Invalid configuration: {}", problems.join("; ")),
+            ReloadError::Database(e) => write!(f, "Failed to reload automations: {}", e),
+        }
+    }
+}
+
+pub struct Reloader {
+    config_args: ConfigArgs,
+    // The cloud relay given on the command line wins over the file, as at startup
+    cloud_override: Option<String>,
+    // Settings in effect; restart-only ones keep the values the hub started with
+    current: Mutex<Config>,
+    cloud_tx: mpsc::UnboundedSender<CloudCommand>,
+    broker_tx: mpsc::UnboundedSender<MqttConfig>,
+    settings: Arc<LiveSettings>,
+    rule_engine: Arc<RuleEngine>,
+    alert_monitor: Arc<AlertMonitor>,
+    scheduler: Arc<Scheduler>,
+    notifications: Arc<NotificationDispatcher>,
+}
+
+impl Reloader {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config_args: ConfigArgs,
+        cloud_override: Option<String>,
+        config: Config,
+        cloud_tx: mpsc::UnboundedSender<CloudCommand>,
+        broker_tx: mpsc::UnboundedSender<MqttConfig>,
+        settings: Arc<LiveSettings>,
+        rule_engine: Arc<RuleEngine>,
+        alert_monitor: Arc<AlertMonitor>,
+        scheduler: Arc<Scheduler>,
+        notifications: Arc<NotificationDispatcher>,
+    ) -> Self {
+        Self {
+            config_args,
+            cloud_override,
+            current: Mutex::new(config),
+            cloud_tx,
+            broker_tx,
+            settings,
+            rule_engine,
+            alert_monitor,
+            scheduler,
+            notifications,
+        }
+    }
+
+    // Touches the database, so async callers wrap it in block_in_place
+    pub fn reload(&self) -> Result<ReloadReport, ReloadError> {
+        let mut new = Config::load(&self.config_args).map_err(ReloadError::Invalid)?;
+        if let Some(cloud) = &self.cloud_override {
+            new.hub.cloud_url = cloud.clone();
+        }
+
+        // One reload at a time, so two callers never apply settings out of order
+        let mut current = self.current.lock().unwrap();
+        let mut report = ReloadReport {
+            rules: self.rule_engine.reload().map_err(ReloadError::Database)?,
+            alert_thresholds: self.alert_monitor.reload().map_err(ReloadError::Database)?,
+            // Sunrise and sunset schedules are worked out again for the new location
+            schedules: self
+                .scheduler
+                .set_location(new.hub.location().map(|(latitude, longitude)| Location { latitude, longitude }))
+                .map_err(ReloadError::Database)?,
+            ..ReloadReport::default()
+        };
+
+        for key in current.changed_keys(&new) {
+            match effect(&key) {
+                Effect::Live => report.applied.push(key),
+                Effect::Restart => report.restart_required.push(key),
+                Effect::Ignored => report.ignored.push(key),
+                Effect::NotHub => {}
+            }
+        }
+
+        let cloud_link = CloudLink::from_config(&new);
+        if cloud_link != CloudLink::from_config(&current) {
+            let _ = self.cloud_tx.send(CloudCommand::Reconfigure(cloud_link));
+        }
+        if new.hub.mqtt != current.hub.mqtt {
+            let _ = self.broker_tx.send(new.hub.mqtt.clone());
+        }
+        self.settings.apply(&new.hub);
+        self.notifications.set_exec_allowlist(new.hub.notify_exec_allow.clone());
+
+        // Listeners and the database stay as they were started
+        new.hub.host = current.hub.host.clone();
+        new.hub.port = current.hub.port;
+        new.hub.web_port = current.hub.web_port;
+        new.hub.db_path = current.hub.db_path.clone();
+        new.tls.cert = current.tls.cert.clone();
+        new.tls.key = current.tls.key.clone();
+        *current = new;
+
+        Ok(report)
+    }
+}
+
+fn log_reload(result: &Result<ReloadReport, ReloadError>) {
+    match result {
+        Ok(report) => {
+            println!(
+                "🔄 Configuration reloaded: {} rules, {} alert thresholds, {} schedules",
+                report.rules, report.alert_thresholds, report.schedules
+            );
+            if !report.applied.is_empty() {
+                println!("🔄 Applied: {}", report.applied.join(", "));
+            }
+            if !report.restart_required.is_empty() {
+                println!("⚠️ Restart required for: {}", report.restart_required.join(", "));
+            }
+            if !report.ignored.is_empty() {
+                println!("⚠️ Ignored after the first start: {}", report.ignored.join(", "));
+            }
+        }
+        Err(e) => eprintln!("❌ Configuration reload failed: {}", e),
+    }
+}
+
+pub fn reload_and_log(reloader: &Reloader) -> Result<ReloadReport, ReloadError> {
+    let result = tokio::task::block_in_place(|| reloader.reload());
+    log_reload(&result);
+    result
+}
+
+#[cfg(unix)]
+pub async fn reload_on_sighup(reloader: Arc<Reloader>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            eprintln!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        println!("🔄 SIGHUP received, reloading configuration");
+        let _ = reload_and_log(&reloader);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setting_effects() {
+        assert_eq!(effect("hub.mqtt.host"), Effect::Live);
+        assert_eq!(effect("hub.cloud_token"), Effect::Live);
+        assert_eq!(effect("hub.notify_exec_allow"), Effect::Live);
+        assert_eq!(effect("tls.ca"), Effect::Live);
+        assert_eq!(effect("hub.web_port"), Effect::Restart);
+        assert_eq!(effect("tls.cert"), Effect::Restart);
+        assert_eq!(effect("hub.admin_password"), Effect::Ignored);
+        assert_eq!(effect("cloud.hub_tokens.home"), Effect::NotHub);
+        assert_eq!(effect("client.server_url"), Effect::NotHub);
+    }
+
+    #[test]
+    fn test_live_settings_apply() {
+        let mut hub = HubConfig::default();
+        let settings = LiveSettings::new(&hub);
+        hub.raw_retention_hours = 72;
+        hub.presence_missed_intervals = 5;
+        hub.shutdown_timeout_secs = 30;
+        settings.apply(&hub);
+        assert_eq!((settings.raw_retention_hours(), settings.presence_missed_intervals()), (72, 5));
+        assert_eq!(settings.shutdown_timeout(), Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{TimeDelta, Utc};
+use crate::database::{format_sample_time, Database, Resolution};
+use crate::reload::LiveSettings;
+use crate::shutdown::Shutdown;
+
+// How often raw samples are rolled up and pruned
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    pub raw_max_age: TimeDelta,
+}
+
+impl RetentionPolicy {
+    pub fn new(raw_retention_hours: u64) -> Self {
+        // Raw rows feed the hourly rollup, so never keep them for less than an hour
+        let hours = raw_retention_hours.max(1);
+        Self {
+            raw_max_age: TimeDelta::hours(hours as i64),
+        }
+    }
+}
+
+// A pass that has started is finished on shutdown; the next one is not started
+pub async fn run_retention_task(db: Arc<Database>, settings: Arc<LiveSettings>, shutdown: Shutdown) {
+    let mut policy = RetentionPolicy::new(settings.raw_retention_hours());
+    println!("🧹 Telemetry retention: raw samples kept for {} h", policy.raw_max_age.num_hours());
+
+    let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return,
+        }
+
+        // The retention period may have been changed by a configuration reload
+        let current = RetentionPolicy::new(settings.raw_retention_hours());
+        if current.raw_max_age != policy.raw_max_age {
+            println!("🧹 Telemetry retention: raw samples now kept for {} h", current.raw_max_age.num_hours());
+            policy = current;
+        }
+
+        let db = Arc::clone(&db);
+        let policy = policy.clone();
+        let result = tokio::task::spawn_blocking(move || apply_retention(&db, &policy)).await;
+
+        match result {
+            Ok(Ok(0)) => {}
+            Ok(Ok(deleted)) => println!("🧹 Pruned {} raw telemetry samples", deleted),
+            Ok(Err(e)) => eprintln!("❌ Telemetry retention failed: {}", e),
+            Err(e) => eprintln!("❌ Telemetry retention task panicked: {}", e),
+        }
+    }
+}
+
+fn apply_retention(db: &Database, policy: &RetentionPolicy) -> rusqlite::Result<usize> {
+    let now = Utc::now();
+    let minute_watermark = db.rollup_samples(Resolution::Minute, now)?;
+    let hour_watermark = db.rollup_samples(Resolution::Hour, now)?;
+
+    // Only drop raw rows that both rollups have already consumed
+    let age_cutoff = format_sample_time(now - policy.raw_max_age);
+    let cutoff = [age_cutoff, minute_watermark, hour_watermark]
+        .into_iter()
+        .min()
+        .unwrap_or_default();
+
+    db.delete_samples_before(&cutoff)
+}
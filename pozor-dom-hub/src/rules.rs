@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, Datelike, Local, NaiveTime, Timelike, Utc, Weekday};
+use rumqttc::AsyncClient;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use pozor_dom_shared::dashboard::{DevicePresence, DeviceTelemetry, HubState};
+use pozor_dom_shared::device::{Capability, CapabilityValue, PresenceStatus};
+use pozor_dom_shared::envelope::{DeviceCommand, Envelope};
+use crate::commands::{self, CommandTracker};
+use crate::database::Database;
+use crate::shutdown::Shutdown;
+
+// Rule times are local hub time, e.g. "07:30"
+const TIME_FORMAT: &str = "%H:%M";
+
+// How often time triggers are checked
+const TIME_TRIGGER_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Rule {
+    // Assigned by the database; ignored on create/update
+    #[serde(default)]
+    pub id: i64,
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+    // Minimum time between two firings of the same rule
+    #[serde(default)]
+    pub cooldown_secs: u64,
+}
+
+// Rules and schedules are enabled unless the request says otherwise
+pub fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    // A reading starts satisfying `capability operator value` (fires on the edge, not on every reading)
+    Telemetry {
+        device_id: String,
+        capability: Capability,
+        operator: Operator,
+        value: CapabilityValue,
+    },
+    // A capability changes value, optionally only when it changes to `to`
+    StateChange {
+        device_id: String,
+        capability: Capability,
+        #[serde(default)]
+        to: Option<CapabilityValue>,
+    },
+    // A device goes online or offline
+    Presence { device_id: String, status: PresenceStatus },
+    // Local time of day; every day unless `days` is given
+    Time {
+        at: String,
+        #[serde(default)]
+        days: Vec<Weekday>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    // Latest known reading of a device
+    Capability {
+        device_id: String,
+        capability: Capability,
+        operator: Operator,
+        value: CapabilityValue,
+    },
+    Presence { device_id: String, status: PresenceStatus },
+    // Local time between `after` and `before`; wraps past midnight when `after` > `before`
+    TimeWindow { after: String, before: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Command { device_id: String, action: String },
+    Notify { message: String },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+    #[serde(alias = "==")]
+    Eq,
+    #[serde(alias = "!=")]
+    Ne,
+    #[serde(alias = ">")]
+    Gt,
+    #[serde(alias = ">=")]
+    Gte,
+    #[serde(alias = "<")]
+    Lt,
+    #[serde(alias = "<=")]
+    Lte,
+}
+
+// Something the rules can react to
+pub enum RuleEvent<'a> {
+    Telemetry {
+        previous: Option<&'a DeviceTelemetry>,
+        current: &'a DeviceTelemetry,
+    },
+    Presence(&'a DevicePresence),
+    Tick,
+}
+
+impl Operator {
+    pub fn matches(&self, actual: &CapabilityValue, expected: &CapabilityValue) -> bool {
+        match (actual.as_f64(), expected.as_f64()) {
+            (Some(actual), Some(expected)) => match self {
+                Operator::Eq => actual == expected,
+                Operator::Ne => actual != expected,
+                Operator::Gt => actual > expected,
+                Operator::Gte => actual >= expected,
+                Operator::Lt => actual < expected,
+                Operator::Lte => actual <= expected,
+            },
+            // Booleans and text only compare for equality
+            _ => match self {
+                Operator::Eq => actual == expected,
+                Operator::Ne => actual != expected,
+                _ => false,
+            },
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, TIME_FORMAT).map_err(|_| format!("invalid time '{}', expected HH:MM", value))
+}
+
+fn reading_matches(telemetry: Option<&DeviceTelemetry>, capability: Capability, operator: Operator, value: &CapabilityValue) -> bool {
+    telemetry
+        .and_then(|telemetry| telemetry.get(capability))
+        .is_some_and(|actual| operator.matches(actual, value))
+}
+
+impl Rule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("rule name must not be empty".to_string());
+        }
+        if self.actions.is_empty() {
+            return Err("rule must have at least one action".to_string());
+        }
+        if let Trigger::Time { at, .. } = &self.trigger {
+            parse_time(at)?;
+        }
+        for condition in &self.conditions {
+            if let Condition::TimeWindow { after, before } = condition {
+                parse_time(after)?;
+                parse_time(before)?;
+            }
+        }
+        for action in &self.actions {
+            match action {
+                Action::Command { device_id, action } if device_id.is_empty() || action.is_empty() => {
+                    return Err("command actions need a device_id and an action".to_string());
+                }
+                Action::Notify { message } if message.is_empty() => {
+                    return Err("notify actions need a message".to_string());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn triggered_by(&self, event: &RuleEvent, now: DateTime<Local>) -> bool {
+        match (&self.trigger, event) {
+            (Trigger::Telemetry { device_id, capability, operator, value }, RuleEvent::Telemetry { previous, current }) => {
+                current.device_id == *device_id
+                    && reading_matches(Some(*current), *capability, *operator, value)
+                    && !reading_matches(*previous, *capability, *operator, value)
+            }
+            (Trigger::StateChange { device_id, capability, to }, RuleEvent::Telemetry { previous, current }) => {
+                let before = previous.and_then(|previous| previous.get(*capability));
+                let after = current.get(*capability);
+                current.device_id == *device_id
+                    && before.is_some()
+                    && after.is_some()
+                    && before != after
+                    && to.as_ref().is_none_or(|to| Some(to) == after)
+            }
+            (Trigger::Presence { device_id, status }, RuleEvent::Presence(presence)) => {
+                presence.device_id == *device_id && presence.status == *status
+            }
+            (Trigger::Time { at, days }, RuleEvent::Tick) => {
+                // Compared as a time of day, so "7:30" fires like "07:30"
+                parse_time(at).is_ok_and(|at| (now.hour(), now.minute()) == (at.hour(), at.minute()))
+                    && (days.is_empty() || days.contains(&now.weekday()))
+            }
+            _ => false,
+        }
+    }
+
+    fn conditions_hold(&self, state: &HubState, now: DateTime<Local>) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::Capability { device_id, capability, operator, value } => {
+                reading_matches(state.devices.get(device_id), *capability, *operator, value)
+            }
+            Condition::Presence { device_id, status } => {
+                state.presence_of(device_id).map(|presence| presence.status).unwrap_or_default() == *status
+            }
+            Condition::TimeWindow { after, before } => match (parse_time(after), parse_time(before)) {
+                (Ok(after), Ok(before)) => {
+                    let time = now.time();
+                    if after <= before {
+                        time >= after && time < before
+                    } else {
+                        time >= after || time < before
+                    }
+                }
+                _ => false,
+            },
+        })
+    }
+
+    fn cooldown(&self) -> chrono::TimeDelta {
+        // Time triggers match for a whole minute; never fire them twice in it
+        let minimum = if matches!(self.trigger, Trigger::Time { .. }) { 60 } else { 0 };
+        chrono::TimeDelta::seconds(self.cooldown_secs.max(minimum) as i64)
+    }
+}
+
+pub struct RuleEngine {
+    db: Arc<Database>,
+    rules: RwLock<Vec<Rule>>,
+    last_fired: Mutex<HashMap<i64, DateTime<Utc>>>,
+    mqtt_client: Arc<AsyncClient>,
+    command_tracker: Arc<CommandTracker>,
+    broadcast_tx: Arc<broadcast::Sender<String>>,
+}
+
+impl RuleEngine {
+    pub fn new(
+        db: Arc<Database>,
+        mqtt_client: Arc<AsyncClient>,
+        command_tracker: Arc<CommandTracker>,
+        broadcast_tx: Arc<broadcast::Sender<String>>,
+    ) -> Self {
+        let engine = Self {
+            db,
+            rules: RwLock::new(Vec::new()),
+            last_fired: Mutex::new(HashMap::new()),
+            mqtt_client,
+            command_tracker,
+            broadcast_tx,
+        };
+        match engine.reload() {
+            Ok(count) => println!("⚙️ Loaded {} automation rules", count),
+            Err(e) => eprintln!("❌ Failed to load automation rules: {}", e),
+        }
+        engine
+    }
+
+    // Re-reads the rules after they were changed through the API
+    pub fn reload(&self) -> rusqlite::Result<usize> {
+        let rules = self.db.load_rules()?;
+        let count = rules.len();
+        *self.rules.write().unwrap() = rules;
+        Ok(count)
+    }
+
+    // Enabled rules triggered by `event` whose conditions hold; marks them as fired
+    pub fn evaluate(&self, event: &RuleEvent, state: &HubState) -> Vec<Rule> {
+        let now = Utc::now();
+        let local_now = now.with_timezone(&Local);
+        let rules = self.rules.read().unwrap();
+        let mut last_fired = self.last_fired.lock().unwrap();
+
+        rules
+            .iter()
+            .filter(|rule| rule.enabled && rule.triggered_by(event, local_now) && rule.conditions_hold(state, local_now))
+            .filter(|rule| {
+                let cooling_down = last_fired.get(&rule.id).is_some_and(|fired| now - *fired < rule.cooldown());
+                if !cooling_down {
+                    last_fired.insert(rule.id, now);
+                }
+                !cooling_down
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Runs the actions of fired rules; channels for commands come from the hub state
+    pub async fn execute(&self, rules: Vec<Rule>, hub_state: &tokio::sync::Mutex<HubState>) {
+        for rule in rules {
+            println!("⚙️ Rule '{}' fired", rule.name);
+            for action in &rule.actions {
+                match action {
+                    Action::Command { device_id, action } => {
+                        let channel = hub_state
+                            .lock()
+                            .await
+                            .devices
+                            .get(device_id)
+                            .map(|device| device.channel.clone())
+                            .unwrap_or_default();
+                        let command = DeviceCommand {
+                            command_id: String::new(),
+                            device_id: device_id.clone(),
+                            channel,
+                            action: action.clone(),
+                            timestamp: Utc::now().to_rfc3339(),
+                        };
+                        commands::dispatch_command(command, Arc::clone(&self.command_tracker), &self.mqtt_client, &self.broadcast_tx).await;
+                    }
+                    Action::Notify { message } => {
+                        let notification = Envelope::notification(&format!("Rule '{}'", rule.name), message);
+                        let _ = self.broadcast_tx.send(notification.to_json());
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Fires rules with time triggers; telemetry and presence rules are evaluated where those events arrive
+pub async fn run_time_triggers(engine: Arc<RuleEngine>, hub_state: Arc<tokio::sync::Mutex<HubState>>, shutdown: Shutdown) {
+    let mut interval = tokio::time::interval(TIME_TRIGGER_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return,
+        }
+
+        let fired = {
+            let state = hub_state.lock().await;
+            engine.evaluate(&RuleEvent::Tick, &state)
+        };
+        if !fired.is_empty() {
+            tokio::select! {
+                _ = engine.execute(fired, &hub_state) => {}
+                _ = shutdown.cancelled() => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use pozor_dom_shared::device::DeviceType;
+    use std::collections::BTreeMap;
+
+    fn reading(device_id: &str, capability: Capability, value: CapabilityValue) -> DeviceTelemetry {
+        DeviceTelemetry {
+            device_id: device_id.to_string(),
+            channel: "WiFi".to_string(),
+            device_type: DeviceType::Sensor,
+            capabilities: BTreeMap::from([(capability, value)]),
+            signal_strength: -50,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn rule(json: serde_json::Value) -> Rule {
+        let rule: Rule = serde_json::from_value(json).unwrap();
+        rule.validate().unwrap();
+        rule
+    }
+
+    #[test]
+    fn test_telemetry_trigger_fires_on_crossing_only() {
+        let rule = rule(serde_json::json!({
+            "name": "Too hot",
+            "trigger": {"type": "telemetry", "device_id": "sensor-1", "capability": "temperature", "operator": ">", "value": 28},
+            "actions": [{"type": "command", "device_id": "plug-1", "action": "turn_on"}]
+        }));
+        let now = Local::now();
+        let cool = reading("sensor-1", Capability::Temperature, 25.0.into());
+        let hot = reading("sensor-1", Capability::Temperature, 30.0.into());
+
+        assert!(rule.triggered_by(&RuleEvent::Telemetry { previous: Some(&cool), current: &hot }, now));
+        assert!(rule.triggered_by(&RuleEvent::Telemetry { previous: None, current: &hot }, now));
+        assert!(!rule.triggered_by(&RuleEvent::Telemetry { previous: Some(&hot), current: &hot }, now));
+        assert!(!rule.triggered_by(&RuleEvent::Telemetry { previous: Some(&hot), current: &cool }, now));
+    }
+
+    #[test]
+    fn test_state_change_and_conditions() {
+        let rule = rule(serde_json::json!({
+            "name": "Door opened at night",
+            "trigger": {"type": "state_change", "device_id": "door-1", "capability": "contact", "to": true},
+            "conditions": [
+                {"type": "time_window", "after": "22:00", "before": "06:00"},
+                {"type": "capability", "device_id": "lock-1", "capability": "lock", "operator": "eq", "value": true}
+            ],
+            "actions": [{"type": "notify", "message": "Front door opened"}]
+        }));
+        let closed = reading("door-1", Capability::Contact, false.into());
+        let open = reading("door-1", Capability::Contact, true.into());
+        let event = RuleEvent::Telemetry { previous: Some(&closed), current: &open };
+        let night = Local.with_ymd_and_hms(2024, 1, 1, 23, 30, 0).unwrap();
+        let day = Local.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        assert!(rule.triggered_by(&event, night));
+        assert!(!rule.triggered_by(&RuleEvent::Telemetry { previous: Some(&open), current: &closed }, night));
+
+        let mut state = HubState::new("Test Hub");
+        state.update_device(reading("lock-1", Capability::Lock, true.into()));
+        assert!(rule.conditions_hold(&state, night));
+        assert!(!rule.conditions_hold(&state, day));
+
+        state.update_device(reading("lock-1", Capability::Lock, false.into()));
+        assert!(!rule.conditions_hold(&state, night));
+    }
+
+    #[test]
+    fn test_time_trigger_and_validation() {
+        let rule = rule(serde_json::json!({
+            "name": "Morning lights",
+            "trigger": {"type": "time", "at": "07:30", "days": ["mon", "tue"]},
+            "actions": [{"type": "command", "device_id": "light-1", "action": "set_brightness 40"}]
+        }));
+        // 2024-01-01 was a Monday
+        assert!(rule.triggered_by(&RuleEvent::Tick, Local.with_ymd_and_hms(2024, 1, 1, 7, 30, 15).unwrap()));
+        assert!(!rule.triggered_by(&RuleEvent::Tick, Local.with_ymd_and_hms(2024, 1, 1, 7, 31, 0).unwrap()));
+        assert!(!rule.triggered_by(&RuleEvent::Tick, Local.with_ymd_and_hms(2024, 1, 3, 7, 30, 0).unwrap()));
+        assert_eq!(rule.cooldown(), chrono::TimeDelta::seconds(60));
+
+        let mut unpadded = rule.clone();
+        unpadded.trigger = Trigger::Time { at: "7:30".to_string(), days: Vec::new() };
+        assert!(unpadded.validate().is_ok());
+        assert!(unpadded.triggered_by(&RuleEvent::Tick, Local.with_ymd_and_hms(2024, 1, 3, 7, 30, 0).unwrap()));
+
+        let mut invalid = rule.clone();
+        invalid.trigger = Trigger::Time { at: "7.30".to_string(), days: Vec::new() };
+        assert!(invalid.validate().is_err());
+        invalid.trigger = rule.trigger.clone();
+        invalid.actions.clear();
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use chrono::Utc;
+use futures_util::future::join_all;
+use rumqttc::AsyncClient;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+use pozor_dom_shared::dashboard::HubState;
+use pozor_dom_shared::device::PresenceStatus;
+use pozor_dom_shared::envelope::{CommandStatus, DeviceCommand, Envelope, SceneResult};
+use crate::commands::{self, CommandTracker};
+use crate::database::Database;
+
+// A named batch of device commands, e.g. "night-mode"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Scene {
+    // Taken from the URL when the scene is saved
+    #[serde(default)]
+    pub name: String,
+    pub commands: Vec<SceneCommand>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SceneCommand {
+    pub device_id: String,
+    pub action: String,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    NotFound,
+    // Some member devices are offline, so nothing was sent
+    Unavailable(Vec<String>),
+    Database(rusqlite::Error),
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SceneError::NotFound => write!(f, "Scene not found"),
+            SceneError::Unavailable(devices) => write!(f, "Scene devices are offline: {}", devices.join(", ")),
+            SceneError::Database(e) => write!(f, "Failed to load scene: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for SceneError {
+    fn from(e: rusqlite::Error) -> Self {
+        SceneError::Database(e)
+    }
+}
+
+impl Scene {
+    pub fn validate(&self) -> Result<(), String> {
+        // Names end up in URLs (/api/scenes/{name}/activate)
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err("scene name must be non-empty and use only letters, digits, '-' and '_'".to_string());
+        }
+        if self.commands.is_empty() {
+            return Err("scene must have at least one command".to_string());
+        }
+        let mut devices = HashSet::new();
+        for command in &self.commands {
+            if command.device_id.is_empty() || command.action.is_empty() {
+                return Err("scene commands need a device_id and an action".to_string());
+            }
+            // Members run concurrently, so two commands for one device would race
+            if !devices.insert(command.device_id.as_str()) {
+                return Err(format!("device {} appears more than once", command.device_id));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Scene whose members were all checked and are ready to be sent
+pub struct PreparedScene {
+    name: String,
+    commands: Vec<DeviceCommand>,
+}
+
+impl PreparedScene {
+    pub fn commands(&self) -> &[DeviceCommand] {
+        &self.commands
+    }
+}
+
+pub struct SceneRunner {
+    db: Arc<Database>,
+    hub_state: Arc<Mutex<HubState>>,
+    mqtt_client: Arc<AsyncClient>,
+    command_tracker: Arc<CommandTracker>,
+    broadcast_tx: Arc<broadcast::Sender<String>>,
+}
+
+impl SceneRunner {
+    pub fn new(
+        db: Arc<Database>,
+        hub_state: Arc<Mutex<HubState>>,
+        mqtt_client: Arc<AsyncClient>,
+        command_tracker: Arc<CommandTracker>,
+        broadcast_tx: Arc<broadcast::Sender<String>>,
+    ) -> Self {
+        Self { db, hub_state, mqtt_client, command_tracker, broadcast_tx }
+    }
+
+    // Checks every member up front so a scene is sent as a whole or not at all
+    pub async fn prepare(&self, name: &str) -> Result<PreparedScene, SceneError> {
+        let scene = tokio::task::block_in_place(|| self.db.get_scene(name))?.ok_or(SceneError::NotFound)?;
+
+        let state = self.hub_state.lock().await;
+        let offline: Vec<String> = scene
+            .commands
+            .iter()
+            .filter(|member| state.presence_of(&member.device_id).map(|presence| presence.status) != Some(PresenceStatus::Online))
+            .map(|member| member.device_id.clone())
+            .collect();
+        if !offline.is_empty() {
+            return Err(SceneError::Unavailable(offline));
+        }
+
+        let timestamp = Utc::now().to_rfc3339();
+        let commands = scene
+            .commands
+            .iter()
+            .map(|member| DeviceCommand {
+                command_id: String::new(),
+                device_id: member.device_id.clone(),
+                channel: state.devices.get(&member.device_id).map(|device| device.channel.clone()).unwrap_or_default(),
+                action: member.action.clone(),
+                timestamp: timestamp.clone(),
+            })
+            .collect();
+        Ok(PreparedScene { name: scene.name, commands })
+    }
+
+    // Sends all member commands at once and waits for each device's final status
+    pub async fn run(&self, scene: PreparedScene) -> SceneResult {
+        println!("🎬 Activating scene '{}' ({} commands)", scene.name, scene.commands.len());
+        let outcomes = join_all(scene.commands.into_iter().map(|command| {
+            commands::dispatch_command_and_wait(command, Arc::clone(&self.command_tracker), &self.mqtt_client, &self.broadcast_tx)
+        }))
+        .await;
+
+        let result = SceneResult {
+            success: outcomes.iter().all(|ack| ack.status == CommandStatus::Succeeded),
+            name: scene.name,
+            outcomes,
+        };
+        println!("🎬 Scene '{}' finished: {}", result.name, if result.success { "succeeded" } else { "partially failed" });
+        let _ = self.broadcast_tx.send(Envelope::scene_result(result.clone()).to_json());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene(json: serde_json::Value) -> Scene {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_scene_validation() {
+        let night = scene(serde_json::json!({
+            "name": "night-mode",
+            "commands": [
+                {"device_id": "light-bulb-001", "action": "turn_off"},
+                {"device_id": "thermostat-001", "action": "set_target 19"},
+                {"device_id": "lock-front-001", "action": "lock"}
+            ]
+        }));
+        assert!(night.validate().is_ok());
+
+        let mut renamed = night.clone();
+        renamed.name = "night mode".to_string();
+        assert!(renamed.validate().is_err());
+
+        let mut duplicated = night.clone();
+        duplicated.commands.push(SceneCommand { device_id: "light-bulb-001".to_string(), action: "turn_on".to_string() });
+        assert!(duplicated.validate().is_err());
+
+        assert!(scene(serde_json::json!({"name": "empty", "commands": []})).validate().is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, Datelike, Local, TimeDelta, Utc, Weekday};
+use rumqttc::AsyncClient;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use pozor_dom_shared::dashboard::HubState;
+use pozor_dom_shared::envelope::{DeviceCommand, Envelope};
+use crate::commands::{self, CommandTracker};
+use crate::cron::CronExpression;
+use crate::database::Database;
+use crate::scenes::SceneRunner;
+use crate::shutdown::Shutdown;
+use crate::sun::{self, Location, SunEvent};
+
+// How often due schedules are checked
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(1);
+
+// Sun offsets beyond this would land on another day's event
+const MAX_SUN_OFFSET_MINUTES: i64 = 12 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Schedule {
+    // Assigned by the database; ignored on create/update
+    #[serde(default)]
+    pub id: i64,
+    pub name: String,
+    #[serde(default = "crate::rules::default_enabled")]
+    pub enabled: bool,
+    pub when: ScheduleTime,
+    pub action: ScheduledAction,
+    // Filled in by the hub in API responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduleTime {
+    // Five-field cron expression in local hub time, e.g. "30 7 * * mon-fri"
+    Cron { expression: String },
+    // Sunrise or sunset at the configured location, shifted by `offset_minutes`; every day unless `days` is given
+    Sun {
+        event: SunEvent,
+        #[serde(default)]
+        offset_minutes: i64,
+        #[serde(default)]
+        days: Vec<Weekday>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduledAction {
+    Command { device_id: String, action: String },
+    Scene { name: String },
+}
+
+impl ScheduleTime {
+    pub fn next_after(&self, after: DateTime<Utc>, location: Option<Location>) -> Option<DateTime<Utc>> {
+        match self {
+            ScheduleTime::Cron { expression } => {
+                let cron = CronExpression::parse(expression).ok()?;
+                cron.next_after(after.with_timezone(&Local)).map(|time| time.with_timezone(&Utc))
+            }
+            ScheduleTime::Sun { event, offset_minutes, days } => {
+                let location = location?;
+                // Start a day early: a large negative offset can pull tomorrow's event into today
+                let mut date = after.with_timezone(&Local).date_naive().pred_opt()?;
+                // Searching a whole year covers polar nights
+                for _ in 0..=367 {
+                    if (days.is_empty() || days.contains(&date.weekday()))
+                        && let Some(time) = sun::sun_event_time(date, *event, location)
+                    {
+                        let time = time + TimeDelta::minutes(*offset_minutes);
+                        if time > after {
+                            return Some(time);
+                        }
+                    }
+                    date = date.succ_opt()?;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Schedule {
+    pub fn validate(&self, location: Option<Location>) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("schedule name must not be empty".to_string());
+        }
+        match &self.when {
+            ScheduleTime::Cron { expression } => {
+                CronExpression::parse(expression).map_err(|e| format!("invalid cron expression: {}", e))?;
+            }
+            ScheduleTime::Sun { offset_minutes, .. } => {
+                if location.is_none() {
+                    return Err("sunrise/sunset schedules need POZOR_DOM_LATITUDE and POZOR_DOM_LONGITUDE".to_string());
+                }
+                if offset_minutes.abs() > MAX_SUN_OFFSET_MINUTES {
+                    return Err(format!("offset_minutes must be within ±{}", MAX_SUN_OFFSET_MINUTES));
+                }
+            }
+        }
+        match &self.action {
+            ScheduledAction::Command { device_id, action } if device_id.is_empty() || action.is_empty() => {
+                Err("command actions need a device_id and an action".to_string())
+            }
+            ScheduledAction::Scene { name } if name.is_empty() => Err("scene actions need a scene name".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct Scheduler {
+    db: Arc<Database>,
+    hub_state: Arc<tokio::sync::Mutex<HubState>>,
+    mqtt_client: Arc<AsyncClient>,
+    command_tracker: Arc<CommandTracker>,
+    broadcast_tx: Arc<broadcast::Sender<String>>,
+    scene_runner: Arc<SceneRunner>,
+    // Replaced when the configuration is reloaded
+    location: RwLock<Option<Location>>,
+    // Enabled schedules by id, with their next run
+    upcoming: Mutex<HashMap<i64, (Schedule, DateTime<Utc>)>>,
+}
+
+impl Scheduler {
+    pub fn new(
+        db: Arc<Database>,
+        hub_state: Arc<tokio::sync::Mutex<HubState>>,
+        mqtt_client: Arc<AsyncClient>,
+        command_tracker: Arc<CommandTracker>,
+        broadcast_tx: Arc<broadcast::Sender<String>>,
+        scene_runner: Arc<SceneRunner>,
+        location: Option<Location>,
+    ) -> Self {
+        let scheduler = Self {
+            db,
+            hub_state,
+            mqtt_client,
+            command_tracker,
+            broadcast_tx,
+            scene_runner,
+            location: RwLock::new(location),
+            upcoming: Mutex::new(HashMap::new()),
+        };
+        match scheduler.reload() {
+            Ok(count) => println!("⏰ Loaded {} schedules", count),
+            Err(e) => eprintln!("❌ Failed to load schedules: {}", e),
+        }
+        scheduler
+    }
+
+    pub fn location(&self) -> Option<Location> {
+        *self.location.read().unwrap()
+    }
+
+    // Sunrise and sunset times move with the location, so the next runs are worked out again
+    pub fn set_location(&self, location: Option<Location>) -> rusqlite::Result<usize> {
+        *self.location.write().unwrap() = location;
+        self.reload()
+    }
+
+    // Re-reads the schedules after they were changed through the API; runs missed while the hub was down are skipped
+    pub fn reload(&self) -> rusqlite::Result<usize> {
+        let schedules = self.db.load_schedules()?;
+        let count = schedules.len();
+        let now = Utc::now();
+
+        let mut upcoming = HashMap::new();
+        for schedule in schedules.into_iter().filter(|schedule| schedule.enabled) {
+            match schedule.when.next_after(now, self.location()) {
+                Some(next_run) => {
+                    upcoming.insert(schedule.id, (schedule, next_run));
+                }
+                None => println!("⚠️ Schedule '{}' will never run", schedule.name),
+            }
+        }
+        *self.upcoming.lock().unwrap() = upcoming;
+        Ok(count)
+    }
+
+    pub fn next_run(&self, id: i64) -> Option<DateTime<Utc>> {
+        self.upcoming.lock().unwrap().get(&id).map(|(_, next_run)| *next_run)
+    }
+
+    // Schedules whose time has come; their next run is moved forward
+    fn take_due(&self, now: DateTime<Utc>) -> Vec<Schedule> {
+        let mut upcoming = self.upcoming.lock().unwrap();
+        let due: Vec<Schedule> = upcoming
+            .values()
+            .filter(|(_, next_run)| *next_run <= now)
+            .map(|(schedule, _)| schedule.clone())
+            .collect();
+        for schedule in &due {
+            match schedule.when.next_after(now, self.location()) {
+                Some(next_run) => {
+                    upcoming.insert(schedule.id, (schedule.clone(), next_run));
+                }
+                None => {
+                    upcoming.remove(&schedule.id);
+                }
+            }
+        }
+        due
+    }
+
+    async fn execute(&self, schedule: Schedule) {
+        println!("⏰ Schedule '{}' is due", schedule.name);
+        match schedule.action {
+            ScheduledAction::Command { device_id, action } => {
+                let channel = self
+                    .hub_state
+                    .lock()
+                    .await
+                    .devices
+                    .get(&device_id)
+                    .map(|device| device.channel.clone())
+                    .unwrap_or_default();
+                let command = DeviceCommand {
+                    command_id: String::new(),
+                    device_id,
+                    channel,
+                    action,
+                    timestamp: Utc::now().to_rfc3339(),
+                };
+                commands::dispatch_command(command, Arc::clone(&self.command_tracker), &self.mqtt_client, &self.broadcast_tx).await;
+            }
+            ScheduledAction::Scene { name } => match self.scene_runner.prepare(&name).await {
+                Ok(scene) => {
+                    self.scene_runner.run(scene).await;
+                }
+                Err(e) => {
+                    eprintln!("❌ Schedule '{}' could not start scene {}: {}", schedule.name, name, e);
+                    let notification = Envelope::notification(&format!("Schedule '{}'", schedule.name), &format!("Scene {}: {}", name, e));
+                    let _ = self.broadcast_tx.send(notification.to_json());
+                }
+            },
+        }
+    }
+}
+
+pub async fn run_scheduler(scheduler: Arc<Scheduler>, shutdown: Shutdown) {
+    match scheduler.location() {
+        Some(location) => println!("⏰ Scheduler: location {:.4}, {:.4}", location.latitude, location.longitude),
+        None => println!("⏰ Scheduler: no location configured, sunrise/sunset schedules are disabled"),
+    }
+
+    let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return,
+        }
+
+        for schedule in scheduler.take_due(Utc::now()) {
+            let scheduler = Arc::clone(&scheduler);
+            shutdown.spawn_until_cancelled(async move {
+                scheduler.execute(schedule).await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(json: serde_json::Value) -> Schedule {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_sun_schedule_next_run() {
+        let moscow = Location { latitude: 55.7558, longitude: 37.6173 };
+        let porch = schedule(serde_json::json!({
+            "name": "Porch light",
+            "when": {"type": "sun", "event": "sunset", "offset_minutes": -15},
+            "action": {"type": "command", "device_id": "light-bulb-001", "action": "turn_on"}
+        }));
+        assert!(porch.validate(Some(moscow)).is_ok());
+        assert!(porch.validate(None).is_err());
+
+        let after = DateTime::parse_from_rfc3339("2024-06-21T12:00:00Z").unwrap().with_timezone(&Utc);
+        let next_run = porch.when.next_after(after, Some(moscow)).unwrap();
+        let sunset = sun::sun_event_time(next_run.with_timezone(&Local).date_naive(), SunEvent::Sunset, moscow).unwrap();
+        assert_eq!(next_run, sunset - TimeDelta::minutes(15));
+        assert!(next_run > after && next_run - after < TimeDelta::days(1));
+        assert_eq!(porch.when.next_after(after, None), None);
+    }
+
+    #[test]
+    fn test_schedule_validation() {
+        let night = schedule(serde_json::json!({
+            "name": "Night mode",
+            "when": {"type": "cron", "expression": "0 23 * * *"},
+            "action": {"type": "scene", "name": "night-mode"}
+        }));
+        assert!(night.enabled);
+        assert!(night.validate(None).is_ok());
+
+        let mut invalid = night.clone();
+        invalid.when = ScheduleTime::Cron { expression: "0 25 * * *".to_string() };
+        assert!(invalid.validate(None).is_err());
+
+        invalid.when = night.when.clone();
+        invalid.action = ScheduledAction::Scene { name: String::new() };
+        assert!(invalid.validate(None).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+// Coordinated hub shutdown: Ctrl+C or SIGTERM cancels one token, the tasks holding it finish what
+// they are doing (close frames, MQTT disconnect, database writes) and the hub waits for them
+use std::future::Future;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    // Tasks the hub waits for before exiting
+    tracker: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    // Spawned tasks must watch `cancelled()`, or the hub waits for them until the timeout
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tracker.spawn(task)
+    }
+
+    // For work that must not outlive the hub, such as device commands: dropped where it stands once
+    // shutdown starts, and not started at all after that
+    pub fn spawn_until_cancelled<F>(&self, task: F) -> JoinHandle<Option<F::Output>>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let token = self.token.clone();
+        self.tracker.spawn(async move {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => None,
+                output = task => Some(output),
+            }
+        })
+    }
+
+    // Cancels the token and waits for the tracked tasks; false when the timeout ran out first
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        self.token.cancel();
+        self.tracker.close();
+        tokio::time::timeout(timeout, self.tracker.wait()).await.is_ok()
+    }
+
+    pub fn pending_tasks(&self) -> usize {
+        self.tracker.len()
+    }
+}
+
+// Ctrl+C, or SIGTERM from a service manager
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => eprintln!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_waits_for_tracked_tasks() {
+        let shutdown = Shutdown::new();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let task_shutdown = shutdown.clone();
+        shutdown.spawn(async move {
+            task_shutdown.cancelled().await;
+            // Cleanup after cancellation still runs before shutdown() returns
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let _ = tx.send(());
+        });
+
+        assert!(shutdown.shutdown(Duration::from_secs(5)).await);
+        assert!(shutdown.token().is_cancelled());
+        assert!(rx.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_until_cancelled() {
+        let shutdown = Shutdown::new();
+        let running = shutdown.spawn_until_cancelled(tokio::time::sleep(Duration::from_secs(60)));
+        assert!(shutdown.shutdown(Duration::from_secs(5)).await);
+        assert_eq!(running.await.unwrap(), None);
+
+        let (tx, mut rx) = tokio::sync::oneshot::channel::<()>();
+        let late = shutdown.spawn_until_cancelled(async move {
+            let _ = tx.send(());
+        });
+        assert_eq!(late.await.unwrap(), None);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_timeout() {
+        let shutdown = Shutdown::new();
+        shutdown.spawn(tokio::time::sleep(Duration::from_secs(60)));
+
+        assert!(!shutdown.shutdown(Duration::from_millis(20)).await);
+        assert_eq!(shutdown.pending_tasks(), 1);
+    }
+}
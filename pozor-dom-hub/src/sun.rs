@@ -0,0 +1,114 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+// Hub location from POZOR_DOM_LATITUDE / POZOR_DOM_LONGITUDE (degrees, east and north positive)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+// Official zenith: the sun's upper limb touches the horizon, corrected for refraction
+const ZENITH_DEGREES: f64 = 90.833;
+
+fn sin(degrees: f64) -> f64 {
+    degrees.to_radians().sin()
+}
+
+fn cos(degrees: f64) -> f64 {
+    degrees.to_radians().cos()
+}
+
+fn tan(degrees: f64) -> f64 {
+    degrees.to_radians().tan()
+}
+
+// Sunrise/sunset for a calendar date at `location`, accurate to a minute or two (NOAA almanac method).
+// None during polar day or night, when the event does not happen that day.
+pub fn sun_event_time(date: NaiveDate, event: SunEvent, location: Location) -> Option<DateTime<Utc>> {
+    let longitude_hours = location.longitude / 15.0;
+    let approximate_hour = match event {
+        SunEvent::Sunrise => 6.0,
+        SunEvent::Sunset => 18.0,
+    };
+    let t = date.ordinal() as f64 + (approximate_hour - longitude_hours) / 24.0;
+
+    // Sun's mean anomaly and true longitude
+    let mean_anomaly = 0.9856 * t - 3.289;
+    let true_longitude =
+        (mean_anomaly + 1.916 * sin(mean_anomaly) + 0.020 * sin(2.0 * mean_anomaly) + 282.634).rem_euclid(360.0);
+
+    // Right ascension, in the same quadrant as the true longitude
+    let mut right_ascension = (0.91764 * tan(true_longitude)).atan().to_degrees().rem_euclid(360.0);
+    right_ascension += (true_longitude / 90.0).floor() * 90.0 - (right_ascension / 90.0).floor() * 90.0;
+    let right_ascension_hours = right_ascension / 15.0;
+
+    // Declination and local hour angle
+    let sin_declination = 0.39782 * sin(true_longitude);
+    let cos_declination = sin_declination.asin().cos();
+    let cos_hour_angle = (cos(ZENITH_DEGREES) - sin_declination * sin(location.latitude))
+        / (cos_declination * cos(location.latitude));
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = match event {
+        SunEvent::Sunrise => 360.0 - cos_hour_angle.acos().to_degrees(),
+        SunEvent::Sunset => cos_hour_angle.acos().to_degrees(),
+    } / 15.0;
+
+    let local_mean_time = hour_angle + right_ascension_hours - 0.06571 * t - 6.622;
+    let utc_hours = (local_mean_time - longitude_hours).rem_euclid(24.0);
+
+    // The UTC hour may belong to the previous or next UTC day; pick the one nearest local solar noon
+    let midnight = date.and_hms_opt(0, 0, 0)?.and_utc();
+    let mut time = midnight + Duration::milliseconds((utc_hours * 3_600_000.0) as i64);
+    let solar_noon = midnight + Duration::minutes((12.0 * 60.0 - location.longitude * 4.0) as i64);
+    if time - solar_noon > Duration::hours(12) {
+        time -= Duration::days(1);
+    } else if solar_noon - time > Duration::hours(12) {
+        time += Duration::days(1);
+    }
+    Some(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn assert_close(actual: Option<DateTime<Utc>>, expected: DateTime<Utc>) {
+        let actual = actual.expect("event should happen");
+        assert!((actual - expected).num_minutes().abs() <= 3, "{} is not close to {}", actual, expected);
+    }
+
+    #[test]
+    fn test_moscow_midsummer() {
+        let moscow = Location { latitude: 55.7558, longitude: 37.6173 };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        // 03:44 and 21:18 Moscow time (UTC+3)
+        assert_close(sun_event_time(date, SunEvent::Sunrise, moscow), Utc.with_ymd_and_hms(2024, 6, 21, 0, 44, 0).unwrap());
+        assert_close(sun_event_time(date, SunEvent::Sunset, moscow), Utc.with_ymd_and_hms(2024, 6, 21, 18, 18, 0).unwrap());
+    }
+
+    #[test]
+    fn test_far_west_sunset_falls_on_next_utc_day() {
+        let los_angeles = Location { latitude: 34.0522, longitude: -118.2437 };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        // 20:08 PDT (UTC-7)
+        assert_close(sun_event_time(date, SunEvent::Sunset, los_angeles), Utc.with_ymd_and_hms(2024, 6, 22, 3, 8, 0).unwrap());
+    }
+
+    #[test]
+    fn test_polar_night_has_no_sunrise() {
+        let tromso = Location { latitude: 69.6492, longitude: 18.9553 };
+        let date = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+        assert_eq!(sun_event_time(date, SunEvent::Sunrise, tromso), None);
+    }
+}
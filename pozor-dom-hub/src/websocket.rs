@@ -1,28 +1,74 @@
 use futures_util::{SinkExt, StreamExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_tungstenite::accept_hdr_async;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message};
 use std::sync::Arc;
 use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
 use rumqttc::AsyncClient;
-use serde_json::Value;
+use pozor_dom_shared::envelope::{DeviceCommand, Envelope, Payload};
+use pozor_dom_shared::{connection, messages, tls};
+use crate::accounts::{self, Accounts, Role, User};
+use crate::acl::DeviceAccess;
+use crate::commands::{self, CommandTracker};
+use crate::scenes::SceneRunner;
+use crate::shutdown::Shutdown;
 
+// Sent to clients and the cloud relay when the hub stops
+fn going_away() -> Message {
+    Message::Close(Some(CloseFrame { code: CloseCode::Away, reason: "Hub shutting down".into() }))
+}
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start_websocket_server(
+    addr: String,
     broadcast_tx: Arc<broadcast::Sender<String>>,
     mqtt_client: Arc<AsyncClient>,
+    command_tracker: Arc<CommandTracker>,
+    scene_runner: Arc<SceneRunner>,
+    accounts: Arc<Accounts>,
+    device_access: Arc<DeviceAccess>,
+    tls_acceptor: Option<tls::TlsAcceptor>,
+    shutdown: Shutdown,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let listener = TcpListener::bind("127.0.0.1:8082").await?;
-    println!("✅ Hub WebSocket server listening on: 127.0.0.1:8082");
+    let listener = TcpListener::bind(&addr).await?;
+    let scheme = if tls_acceptor.is_some() { "wss" } else { "ws" };
+    println!("✅ Hub WebSocket server listening on: {}://{}", scheme, addr);
 
     loop {
-        match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => {
+                println!("🔌 WebSocket server no longer accepts connections");
+                return Ok(());
+            }
+        };
+        match accepted {
             Ok((stream, addr)) => {
                 println!("📱 New WebSocket connection from: {}", addr);
                 let tx = Arc::clone(&broadcast_tx);
                 let mqtt = Arc::clone(&mqtt_client);
+                let tracker = Arc::clone(&command_tracker);
+                let scenes = Arc::clone(&scene_runner);
+                let accounts = Arc::clone(&accounts);
+                let access = Arc::clone(&device_access);
+                let tls_acceptor = tls_acceptor.clone();
+                let client_shutdown = shutdown.clone();
 
-                tokio::spawn(async move {
-                    if let Err(e) = handle_client(stream, tx, mqtt).await {
+                // Tracked, so the hub waits for the close frame before exiting
+                shutdown.spawn(async move {
+                    let result = match tls_acceptor {
+                        Some(acceptor) => match acceptor.accept(stream).await {
+                            Ok(stream) => handle_client(stream, tx, mqtt, tracker, scenes, accounts, access, client_shutdown).await,
+                            Err(e) => Err(format!("TLS handshake with {} failed: {}", addr, e).into()),
+                        },
+                        None => handle_client(stream, tx, mqtt, tracker, scenes, accounts, access, client_shutdown).await,
+                    };
+                    if let Err(e) = result {
                         eprintln!("Client handler error: {}", e);
                     }
                 });
@@ -32,21 +78,50 @@ pub async fn start_websocket_server(
     }
 }
 
-async fn handle_client(
-    stream: TcpStream,
+#[allow(clippy::too_many_arguments)]
+async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
     broadcast_tx: Arc<broadcast::Sender<String>>,
     mqtt_client: Arc<AsyncClient>,
+    command_tracker: Arc<CommandTracker>,
+    scene_runner: Arc<SceneRunner>,
+    accounts: Arc<Accounts>,
+    device_access: Arc<DeviceAccess>,
+    shutdown: Shutdown,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    match accept_async(stream).await {
+    // Same session tokens as the web API; clients without one get a 401 during the handshake
+    let mut user = None;
+    // The handshake callback's error type is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    let authenticate = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        let found = accounts::handshake_token(request)
+            .and_then(|token| tokio::task::block_in_place(|| accounts.authenticate(&token)).ok().flatten());
+        match found {
+            Some(found) => {
+                user = Some(found);
+                Ok(response)
+            }
+            None => {
+                let mut response = ErrorResponse::new(Some("missing or invalid session token".to_string()));
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(response)
+            }
+        }
+    };
+
+    match accept_hdr_async(stream, authenticate).await {
         Ok(ws_stream) => {
+            let Some(user) = user else {
+                return Ok(());
+            };
             let (mut write, mut read) = ws_stream.split();
             let mut rx = broadcast_tx.subscribe();
-            let client_id = format!("client-{}", std::time::SystemTime::now()
+            let client_id = format!("{}-{}", user.username, std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_millis());
 
-            println!("👤 Client connected: {}", client_id);
+            println!("👤 Client connected: {} ({})", client_id, user.role.as_str());
 
             loop {
                 tokio::select! {
@@ -55,17 +130,80 @@ async fn handle_client(
                             Some(Ok(Message::Text(text))) => {
                                 println!("📨 Received from {}: {}", client_id, text);
 
-                                // Parse and handle commands
-                                if let Ok(json) = serde_json::from_str::<Value>(&text) {
-                                    if json["type"].as_str() == Some("command") {
-                                        if let Err(e) = crate::mqtt::send_device_command(&json, &mqtt_client).await {
-                                            eprintln!("Failed to send device command: {}", e);
+                                let envelope = match messages::parse_envelope(&text) {
+                                    Ok(envelope) => envelope,
+                                    Err(e) => {
+                                        eprintln!("❌ Unrecognized frame from {}: {}", client_id, e);
+                                        let reply = messages::create_error_message(&format!("Unrecognized frame: {}", e));
+                                        if write.send(reply).await.is_err() {
+                                            break;
                                         }
+                                        continue;
+                                    }
+                                };
+
+                                if let Some(reply) = messages::version_error(&envelope) {
+                                    eprintln!("❌ Unsupported protocol version {} from {}", envelope.version, client_id);
+                                    if write.send(reply).await.is_err() {
+                                        break;
+                                    }
+                                    continue;
+                                }
+
+                                if let Some(required) = required_role(&envelope.payload).filter(|required| user.role < *required) {
+                                    eprintln!("❌ Rejected frame from {}: requires the {} role", client_id, required.as_str());
+                                    let reply = messages::create_error_message(&format!("Requires the {} role", required.as_str()));
+                                    if write.send(reply).await.is_err() {
+                                        break;
                                     }
+                                    continue;
                                 }
 
-                                // Broadcast to all clients
-                                let _ = broadcast_tx.send(text.to_string());
+                                match envelope.payload {
+                                    Payload::Command(command) => {
+                                        let refused = access_replies(&device_access, &user, std::slice::from_ref(&command));
+                                        if refused.is_empty() {
+                                            commands::dispatch_command(command, Arc::clone(&command_tracker), &mqtt_client, &broadcast_tx).await;
+                                        } else if send_replies(&mut write, refused).await.is_err() {
+                                            break;
+                                        }
+                                    }
+                                    Payload::ActivateScene { name } => {
+                                        // Only the requester hears why a scene could not start; the result goes to everyone
+                                        match scene_runner.prepare(&name).await {
+                                            Ok(scene) => {
+                                                // A scene runs as a whole, so one refused member stops all of it
+                                                let refused = access_replies(&device_access, &user, scene.commands());
+                                                if refused.is_empty() {
+                                                    let runner = Arc::clone(&scene_runner);
+                                                    shutdown.spawn_until_cancelled(async move {
+                                                        runner.run(scene).await;
+                                                    });
+                                                } else if send_replies(&mut write, refused).await.is_err() {
+                                                    break;
+                                                }
+                                            }
+                                            Err(e) => {
+                                                let reply = messages::to_ws_message(&Envelope::error(&format!("Scene {}: {}", name, e)));
+                                                if write.send(reply).await.is_err() {
+                                                    break;
+                                                }
+                                            }
+                                        }
+                                    }
+                                    Payload::Chat { .. } | Payload::Echo { .. } => {
+                                        // Broadcast to all clients
+                                        let _ = broadcast_tx.send(envelope.to_json());
+                                    }
+                                    // Telemetry, acks, alerts and the rest come from the hub; any role could forge them otherwise
+                                    _ => {
+                                        eprintln!("❌ Rejected hub-only frame from {}", client_id);
+                                        let reply = messages::create_error_message("Only the hub may send this frame");
+                                        if write.send(reply).await.is_err() {
+                                            break;
+                                        }
+                                    }
+                                }
                             }
                             Some(Ok(Message::Close(_))) => {
                                 println!("👤 Client closed: {}", client_id);
@@ -86,6 +224,10 @@ async fn handle_client(
                             }
                         }
                     }
+                    _ = shutdown.cancelled() => {
+                        let _ = write.send(going_away()).await;
+                        break;
+                    }
                 }
             }
 
@@ -99,12 +241,60 @@ async fn handle_client(
     }
 }
 
+// Frames that act on devices need more than read access
+fn required_role(payload: &Payload) -> Option<Role> {
+    match payload {
+        Payload::Command(_) | Payload::ActivateScene { .. } => Some(Role::Operator),
+        _ => None,
+    }
+}
+
+// One permission_denied frame per command the user's device access list refuses; empty when all may be sent
+fn access_replies(access: &DeviceAccess, user: &User, commands: &[DeviceCommand]) -> Vec<Message> {
+    match tokio::task::block_in_place(|| access.denied(user, commands)) {
+        Ok(denied) => denied
+            .into_iter()
+            .map(|command| {
+                eprintln!("🚫 {} may not send {} to {}", user.username, command.action, command.device_id);
+                messages::to_ws_message(&Envelope::permission_denied(&user.username, command))
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Database error checking device access for {}: {}", user.username, e);
+            vec![messages::create_error_message("Failed to check device access")]
+        }
+    }
+}
+
+async fn send_replies<W>(write: &mut W, replies: Vec<Message>) -> Result<(), W::Error>
+where
+    W: futures_util::Sink<Message> + Unpin,
+{
+    for reply in replies {
+        write.send(reply).await?;
+    }
+    Ok(())
+}
+
+// Runs until `stop` is cancelled, reconnecting whenever the relay goes away
 pub async fn connect_to_cloud(
     url: &str,
+    token: Option<&str>,
+    ca_path: Option<&str>,
     broadcast_tx: Arc<broadcast::Sender<String>>,
+    stop: CancellationToken,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if token.is_none() {
+        println!("⚠️  hub.cloud_token (POZOR_DOM_HUB_CLOUD_TOKEN) is not set; the cloud relay will reject this hub");
+    }
     loop {
-        match tokio_tungstenite::connect_async(url).await {
+        // The relay authenticates the hub by its pre-shared token during the handshake
+        let request = connection::authorized_request(url, token)?;
+        let connected = tokio::select! {
+            connected = tls::connect(request, ca_path) => connected,
+            _ = stop.cancelled() => return Ok(()),
+        };
+        match connected {
             Ok((ws_stream, _)) => {
                 println!("✅ Connected to Cloud: {}", url);
 
@@ -130,6 +320,10 @@ pub async fn connect_to_cloud(
                                 _ => {}
                             }
                         }
+                        _ = stop.cancelled() => {
+                            let _ = write.send(going_away()).await;
+                            return Ok(());
+                        }
                     }
                 }
 
@@ -137,7 +331,10 @@ pub async fn connect_to_cloud(
             }
             Err(e) => {
                 eprintln!("❌ Failed to connect to Cloud: {}", e);
-                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {}
+                    _ = stop.cancelled() => return Ok(()),
+                }
             }
         }
     }
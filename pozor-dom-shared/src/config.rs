@@ -0,0 +1,563 @@
+// Layered configuration for every binary: built-in defaults, then the TOML file, then
+// POZOR_DOM_* environment variables, then `--set section.key=value` flags
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+// Read from the working directory when neither --config nor POZOR_DOM_CONFIG names a file
+pub const DEFAULT_CONFIG_FILE: &str = "pozor-dom.toml";
+
+const REDACTED: &str = "********";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub cloud: CloudConfig,
+    pub hub: HubConfig,
+    pub client: ClientConfig,
+    pub tls: TlsConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CloudConfig {
+    // Relay listener; use the public address in production
+    pub host: String,
+    pub port: u16,
+    pub dashboard_port: u16,
+    // Hub dashboards by hub id; /api/hubs/{id}/proxy/... is forwarded to the hub's /api/...
+    pub hub_api_urls: BTreeMap<String, String>,
+    // Tokens the relay accepts, by hub id and by user name
+    pub hub_tokens: BTreeMap<String, String>,
+    pub user_tokens: BTreeMap<String, String>,
+    // Hubs each user may subscribe to; "*" allows every hub
+    pub user_hubs: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HubConfig {
+    // Address of the WebSocket server and the web dashboard
+    pub host: String,
+    pub port: u16,
+    pub web_port: u16,
+    pub db_path: String,
+    // Cloud relay: a full ws:// or wss:// URL, or a bare host for ws:// on the default relay port
+    pub cloud_url: String,
+    // Token the hub presents to the cloud relay
+    pub cloud_token: Option<String>,
+    // Password for the initial "admin" account; a random one is generated and printed when unset
+    pub admin_password: Option<String>,
+    // Raw telemetry samples are rolled up into 1m/1h aggregates and deleted after this many hours
+    pub raw_retention_hours: u64,
+    // A device is marked offline after this many publish intervals without telemetry
+    pub presence_missed_intervals: u32,
+    // Seconds to wait for WebSocket clients, MQTT and pending database writes on shutdown
+    pub shutdown_timeout_secs: u64,
+    // Location for sunrise/sunset schedules (decimal degrees, north/east positive)
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // Programs alert `exec` sinks may run; empty means exec sinks are disabled
+    pub notify_exec_allow: Vec<String>,
+    pub mqtt: MqttConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    // Used when no URL is given on the command line
+    pub server_url: String,
+    // User token for the cloud relay, or a hub session token
+    pub token: Option<String>,
+}
+
+// PEM files; with cert and key the WebSocket servers and dashboards use TLS,
+// and `ca` is trusted for wss:// and https:// connections besides the public roots
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub ca: Option<String>,
+}
+
+impl Default for CloudConfig {
+    fn default() -> Self {
+        Self {
+            host: super::DEFAULT_CLOUD_HOST.to_string(),
+            port: super::CLOUD_PORT,
+            dashboard_port: 8080,
+            hub_api_urls: BTreeMap::new(),
+            hub_tokens: BTreeMap::new(),
+            user_tokens: BTreeMap::new(),
+            user_hubs: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        Self {
+            host: super::DEFAULT_HUB_HOST.to_string(),
+            port: super::HUB_PORT,
+            web_port: 3000,
+            db_path: "pozor_dom_hub.db".to_string(),
+            cloud_url: super::cloud_url(),
+            cloud_token: None,
+            admin_password: None,
+            raw_retention_hours: super::DEFAULT_RAW_RETENTION_HOURS,
+            presence_missed_intervals: super::DEFAULT_PRESENCE_MISSED_INTERVALS,
+            shutdown_timeout_secs: super::DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+            latitude: None,
+            longitude: None,
+            notify_exec_allow: Vec::new(),
+            mqtt: MqttConfig::default(),
+        }
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self { host: "127.0.0.1".to_string(), port: 1883 }
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self { server_url: super::cloud_url(), token: None }
+    }
+}
+
+impl HubConfig {
+    pub fn cloud_relay_url(&self) -> String {
+        if self.cloud_url.contains("://") {
+            self.cloud_url.clone()
+        } else {
+            super::cloud_url_with_host(&self.cloud_url)
+        }
+    }
+
+    pub fn location(&self) -> Option<(f64, f64)> {
+        self.latitude.zip(self.longitude)
+    }
+}
+
+// How an environment variable's text becomes a config value
+#[derive(Clone, Copy)]
+enum EnvKind {
+    Text,
+    Number,
+    // "a,b,c"
+    List,
+    // "name:value,name:value"
+    Pairs,
+    // "name:a|b,name:c"
+    PairLists,
+}
+
+// Each variable may feed several keys. The hub's and the client's relay tokens have variables of their
+// own: a host running both would otherwise connect the client with the hub's identity
+const ENV_VARS: &[(&str, &[&str], EnvKind)] = &[
+    ("POZOR_DOM_CLOUD_HOST", &["cloud.host"], EnvKind::Text),
+    ("POZOR_DOM_CLOUD_PORT", &["cloud.port"], EnvKind::Number),
+    ("POZOR_DOM_CLOUD_DASHBOARD_PORT", &["cloud.dashboard_port"], EnvKind::Number),
+    ("POZOR_DOM_HUB_API_URLS", &["cloud.hub_api_urls"], EnvKind::Pairs),
+    ("POZOR_DOM_HUB_TOKENS", &["cloud.hub_tokens"], EnvKind::Pairs),
+    ("POZOR_DOM_USER_TOKENS", &["cloud.user_tokens"], EnvKind::Pairs),
+    ("POZOR_DOM_USER_HUBS", &["cloud.user_hubs"], EnvKind::PairLists),
+    ("POZOR_DOM_HUB_HOST", &["hub.host"], EnvKind::Text),
+    ("POZOR_DOM_HUB_PORT", &["hub.port"], EnvKind::Number),
+    ("POZOR_DOM_HUB_WEB_PORT", &["hub.web_port"], EnvKind::Number),
+    ("POZOR_DOM_HUB_DB", &["hub.db_path"], EnvKind::Text),
+    ("POZOR_DOM_CLOUD_URL", &["hub.cloud_url"], EnvKind::Text),
+    ("POZOR_DOM_HUB_CLOUD_TOKEN", &["hub.cloud_token"], EnvKind::Text),
+    ("POZOR_DOM_USER_TOKEN", &["client.token"], EnvKind::Text),
+    ("POZOR_DOM_ADMIN_PASSWORD", &["hub.admin_password"], EnvKind::Text),
+    ("POZOR_DOM_RAW_RETENTION_HOURS", &["hub.raw_retention_hours"], EnvKind::Number),
+    ("POZOR_DOM_PRESENCE_MISSED_INTERVALS", &["hub.presence_missed_intervals"], EnvKind::Number),
+    ("POZOR_DOM_SHUTDOWN_TIMEOUT", &["hub.shutdown_timeout_secs"], EnvKind::Number),
+    ("POZOR_DOM_LATITUDE", &["hub.latitude"], EnvKind::Number),
+    ("POZOR_DOM_LONGITUDE", &["hub.longitude"], EnvKind::Number),
+    ("POZOR_DOM_NOTIFY_EXEC_ALLOW", &["hub.notify_exec_allow"], EnvKind::List),
+    ("POZOR_DOM_MQTT_HOST", &["hub.mqtt.host"], EnvKind::Text),
+    ("POZOR_DOM_MQTT_PORT", &["hub.mqtt.port"], EnvKind::Number),
+    ("POZOR_DOM_TLS_CERT", &["tls.cert"], EnvKind::Text),
+    ("POZOR_DOM_TLS_KEY", &["tls.key"], EnvKind::Text),
+    ("POZOR_DOM_TLS_CA", &["tls.ca"], EnvKind::Text),
+];
+
+fn split_list(value: &str, separator: char) -> Vec<String> {
+    value.split(separator).map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect()
+}
+
+pub fn parse_token_list(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once(':'))
+        .map(|(name, token)| (name.trim().to_string(), token.trim().to_string()))
+        .filter(|(name, token)| !name.is_empty() && !token.is_empty())
+        .collect()
+}
+
+fn env_value(name: &str, value: &str, kind: EnvKind) -> Result<toml::Value, String> {
+    Ok(match kind {
+        EnvKind::Text => toml::Value::String(value.to_string()),
+        EnvKind::Number => match (value.trim().parse::<i64>(), value.trim().parse::<f64>()) {
+            (Ok(integer), _) => toml::Value::Integer(integer),
+            (_, Ok(float)) => toml::Value::Float(float),
+            _ => return Err(format!("{}: expected a number, got '{}'", name, value)),
+        },
+        EnvKind::List => toml::Value::Array(split_list(value, ',').into_iter().map(toml::Value::String).collect()),
+        EnvKind::Pairs => toml::Value::Table(
+            parse_token_list(value).into_iter().map(|(key, value)| (key, toml::Value::String(value))).collect(),
+        ),
+        EnvKind::PairLists => toml::Value::Table(
+            parse_token_list(value)
+                .into_iter()
+                .map(|(key, list)| (key, toml::Value::Array(split_list(&list, '|').into_iter().map(toml::Value::String).collect())))
+                .collect(),
+        ),
+    })
+}
+
+// `--set` values are TOML literals (3000, true, ["a", "b"]); anything else is taken as a string
+fn override_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+// Replaces the value at a dotted key, creating the tables on the way
+fn set_key(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<(), String> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().filter(|last| !last.is_empty()).ok_or_else(|| format!("invalid key '{}'", key))?;
+    let mut current = table;
+    for part in parts {
+        let entry = current.entry(part.to_string()).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        current = entry.as_table_mut().ok_or_else(|| format!("{}: '{}' is not a table", key, part))?;
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+// Where the configuration comes from, as given on the command line; flattened into every binary's CLI
+#[derive(Debug, Clone, Default, PartialEq, clap::Args)]
+pub struct ConfigArgs {
+    #[arg(long = "config", global = true, value_name = "FILE", help = "Configuration file (default: POZOR_DOM_CONFIG, then ./pozor-dom.toml)")]
+    pub path: Option<PathBuf>,
+    // "section.key=value"
+    #[arg(long = "set", global = true, value_name = "SECTION.KEY=VALUE", help = "Override one setting; may be repeated")]
+    pub overrides: Vec<String>,
+    #[arg(long = "print-config", global = true, help = "Print the effective configuration (secrets hidden) and exit")]
+    pub print: bool,
+}
+
+impl ConfigArgs {
+    // An explicit file must exist; the default one is optional
+    fn file(&self) -> Result<Option<String>, String> {
+        let explicit = self.path.clone().or_else(|| env::var("POZOR_DOM_CONFIG").ok().filter(|path| !path.is_empty()).map(PathBuf::from));
+        match explicit {
+            Some(path) => std::fs::read_to_string(&path).map(Some).map_err(|e| format!("{}: {}", path.display(), e)),
+            None => Ok(std::fs::read_to_string(DEFAULT_CONFIG_FILE).ok()),
+        }
+    }
+}
+
+impl Config {
+    // File, process environment and command line flags, validated
+    pub fn load(args: &ConfigArgs) -> Result<Self, Vec<String>> {
+        let file = args.file().map_err(|e| vec![e])?;
+        Self::from_sources(file.as_deref(), |name| env::var(name).ok(), &args.overrides)
+    }
+
+    pub fn from_sources(
+        file: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+        overrides: &[String],
+    ) -> Result<Self, Vec<String>> {
+        let mut problems = Vec::new();
+        let mut table = match file.map(toml::from_str::<toml::Table>) {
+            Some(Ok(table)) => table,
+            Some(Err(e)) => return Err(vec![format!("config file: {}", e)]),
+            None => toml::Table::new(),
+        };
+
+        for (name, keys, kind) in ENV_VARS {
+            let Some(value) = env(name).filter(|value| !value.is_empty()) else {
+                continue;
+            };
+            match env_value(name, &value, *kind) {
+                Ok(value) => {
+                    for key in *keys {
+                        if let Err(e) = set_key(&mut table, key, value.clone()) {
+                            problems.push(format!("{}: {}", name, e));
+                        }
+                    }
+                }
+                Err(e) => problems.push(e),
+            }
+        }
+
+        for assignment in overrides {
+            let result = match assignment.split_once('=') {
+                Some((key, raw)) => set_key(&mut table, key.trim(), override_value(raw.trim())),
+                None => Err(format!("expected section.key=value, got '{}'", assignment)),
+            };
+            if let Err(e) = result {
+                problems.push(format!("--set {}", e));
+            }
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        let config: Config = toml::Value::Table(table).try_into().map_err(|e: toml::de::Error| vec![e.to_string().trim().to_string()])?;
+        let problems = config.validate();
+        if problems.is_empty() { Ok(config) } else { Err(problems) }
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let ports = [
+            ("cloud.port", self.cloud.port),
+            ("cloud.dashboard_port", self.cloud.dashboard_port),
+            ("hub.port", self.hub.port),
+            ("hub.web_port", self.hub.web_port),
+            ("hub.mqtt.port", self.hub.mqtt.port),
+        ];
+        for (key, port) in ports {
+            if port == 0 {
+                problems.push(format!("{} must not be 0", key));
+            }
+        }
+        if self.cloud.port == self.cloud.dashboard_port {
+            problems.push("cloud.port and cloud.dashboard_port must differ".to_string());
+        }
+        if self.hub.port == self.hub.web_port {
+            problems.push("hub.port and hub.web_port must differ".to_string());
+        }
+
+        let hub_api_urls = self.cloud.hub_api_urls.iter().map(|(hub, url)| (format!("cloud.hub_api_urls.{}", hub), url, ["http://", "https://"]));
+        let urls = hub_api_urls.chain([("client.server_url".to_string(), &self.client.server_url, ["ws://", "wss://"])]);
+        for (key, url, schemes) in urls {
+            if !schemes.iter().any(|scheme| url.starts_with(scheme)) {
+                problems.push(format!("{} must start with {} or {}", key, schemes[0], schemes[1]));
+            }
+        }
+        if self.hub.cloud_url.is_empty()
+            || (self.hub.cloud_url.contains("://") && !["ws://", "wss://"].iter().any(|scheme| self.hub.cloud_url.starts_with(scheme)))
+        {
+            problems.push("hub.cloud_url must be a host or a ws:// or wss:// URL".to_string());
+        }
+
+        for (key, value) in [("cloud.host", &self.cloud.host), ("hub.host", &self.hub.host), ("hub.db_path", &self.hub.db_path), ("hub.mqtt.host", &self.hub.mqtt.host)] {
+            if value.trim().is_empty() {
+                problems.push(format!("{} must not be empty", key));
+            }
+        }
+        if self.hub.raw_retention_hours == 0 {
+            problems.push("hub.raw_retention_hours must be at least 1".to_string());
+        }
+        if self.hub.presence_missed_intervals == 0 {
+            problems.push("hub.presence_missed_intervals must be at least 1".to_string());
+        }
+        if self.hub.shutdown_timeout_secs == 0 {
+            problems.push("hub.shutdown_timeout_secs must be at least 1".to_string());
+        }
+        match (self.hub.latitude, self.hub.longitude) {
+            (Some(latitude), Some(longitude)) => {
+                if !(-90.0..=90.0).contains(&latitude) {
+                    problems.push("hub.latitude must be between -90 and 90".to_string());
+                }
+                if !(-180.0..=180.0).contains(&longitude) {
+                    problems.push("hub.longitude must be between -180 and 180".to_string());
+                }
+            }
+            (None, None) => {}
+            _ => problems.push("hub.latitude and hub.longitude must be set together".to_string()),
+        }
+        if self.tls.cert.is_some() != self.tls.key.is_some() {
+            problems.push("tls.cert and tls.key must be set together".to_string());
+        }
+        problems
+    }
+
+    // Dotted keys whose values differ between the two configurations, e.g. "hub.mqtt.port"
+    pub fn changed_keys(&self, other: &Config) -> Vec<String> {
+        let (ours, theirs) = (self.leaves(), other.leaves());
+        let keys: BTreeSet<&String> = ours.keys().chain(theirs.keys()).collect();
+        keys.into_iter().filter(|key| ours.get(*key) != theirs.get(*key)).cloned().collect()
+    }
+
+    fn leaves(&self) -> BTreeMap<String, toml::Value> {
+        fn flatten(prefix: &str, value: toml::Value, leaves: &mut BTreeMap<String, toml::Value>) {
+            match value {
+                toml::Value::Table(table) => {
+                    for (key, value) in table {
+                        let key = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+                        flatten(&key, value, leaves);
+                    }
+                }
+                value => {
+                    leaves.insert(prefix.to_string(), value);
+                }
+            }
+        }
+        let mut leaves = BTreeMap::new();
+        // Plain data with string keys always serializes
+        flatten("", toml::Value::try_from(self).expect("config serialization"), &mut leaves);
+        leaves
+    }
+
+    // The effective configuration as TOML, with tokens and passwords hidden
+    pub fn to_redacted_toml(&self) -> String {
+        let mut config = self.clone();
+        let redact = |secret: &mut String| *secret = REDACTED.to_string();
+        config.cloud.hub_tokens.values_mut().for_each(redact);
+        config.cloud.user_tokens.values_mut().for_each(redact);
+        config.hub.cloud_token.iter_mut().for_each(redact);
+        config.hub.admin_password.iter_mut().for_each(redact);
+        config.client.token.iter_mut().for_each(redact);
+        // Plain data with string keys always serializes
+        toml::to_string_pretty(&config).expect("config serialization")
+    }
+}
+
+// Startup for every binary: exits with the problems on an invalid configuration, or after printing it
+// for --print-config
+pub fn load_or_exit(args: &ConfigArgs) -> Config {
+    let config = match Config::load(args) {
+        Ok(config) => config,
+        Err(problems) => {
+            eprintln!("❌ Invalid configuration:");
+            for problem in problems {
+                eprintln!("  - {}", problem);
+            }
+            std::process::exit(2);
+        }
+    };
+    if args.print {
+        print!("{}", config.to_redacted_toml());
+        std::process::exit(0);
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(file: Option<&str>, env: &[(&str, &str)], overrides: &[&str]) -> Result<Config, Vec<String>> {
+        let env: BTreeMap<String, String> = env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        let overrides: Vec<String> = overrides.iter().map(|assignment| assignment.to_string()).collect();
+        Config::from_sources(file, |name| env.get(name).cloned(), &overrides)
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = load(None, &[], &[]).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.hub.web_port, 3000);
+        assert_eq!(config.hub.mqtt.port, 1883);
+        assert_eq!(config.cloud.dashboard_port, 8080);
+        assert_eq!(config.hub.cloud_relay_url(), "ws://127.0.0.1:8081");
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let file = r#"
+            [hub]
+            web_port = 3100
+            db_path = "/var/lib/pozor-dom/hub.db"
+            cloud_url = "cloud.example.com"
+
+            [hub.mqtt]
+            host = "broker.local"
+
+            [cloud.user_hubs]
+            alice = ["home"]
+        "#;
+        let env = [("POZOR_DOM_HUB_WEB_PORT", "3200"), ("POZOR_DOM_HUB_CLOUD_TOKEN", "hub-secret"), ("POZOR_DOM_USER_HUBS", "bob:home|cottage")];
+        let config = load(Some(file), &env, &["hub.web_port=3300", "hub.mqtt.port=1884"]).unwrap();
+
+        assert_eq!(config.hub.web_port, 3300);
+        assert_eq!(config.hub.db_path, "/var/lib/pozor-dom/hub.db");
+        assert_eq!(config.hub.mqtt.host, "broker.local");
+        assert_eq!(config.hub.mqtt.port, 1884);
+        assert_eq!(config.hub.cloud_relay_url(), "ws://cloud.example.com:8081");
+        assert_eq!(config.hub.cloud_token.as_deref(), Some("hub-secret"));
+        // The client never picks up the hub's identity
+        assert_eq!(config.client.token, None);
+        // An environment variable replaces the whole table from the file
+        assert_eq!(config.cloud.user_hubs.keys().collect::<Vec<_>>(), vec!["bob"]);
+        assert_eq!(config.cloud.user_hubs["bob"], vec!["home", "cottage"]);
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let problems = load(Some("[hub]\nweb_port = 8082\n"), &[("POZOR_DOM_TLS_CERT", "cert.pem")], &[]).unwrap_err();
+        assert!(problems.contains(&"hub.port and hub.web_port must differ".to_string()));
+        assert!(problems.contains(&"tls.cert and tls.key must be set together".to_string()));
+
+        assert!(load(None, &[("POZOR_DOM_HUB_PORT", "eighty")], &[]).unwrap_err()[0].contains("POZOR_DOM_HUB_PORT"));
+        assert!(load(None, &[("POZOR_DOM_LATITUDE", "55.7")], &[]).is_err());
+        assert!(load(None, &[], &["hub.web_prot=3000"]).unwrap_err()[0].contains("web_prot"));
+        assert!(load(None, &[], &["client.server_url=http://localhost"]).is_err());
+        assert!(load(None, &[("POZOR_DOM_HUB_API_URLS", "home:localhost:3000")], &[]).unwrap_err()[0].contains("cloud.hub_api_urls.home"));
+        let hub_api = load(None, &[("POZOR_DOM_HUB_API_URLS", "home:http://localhost:3000")], &[]).unwrap();
+        assert_eq!(hub_api.cloud.hub_api_urls["home"], "http://localhost:3000");
+        assert!(load(Some("[hub"), &[], &[]).is_err());
+    }
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        config: ConfigArgs,
+        #[command(subcommand)]
+        command: Option<Command>,
+    }
+
+    #[derive(clap::Subcommand)]
+    enum Command {
+        Serve,
+    }
+
+    #[test]
+    fn test_config_args_are_global() {
+        use clap::Parser;
+        let args = ["pozor-dom-hub", "--config", "hub.toml", "serve", "--set=hub.web_port=3100", "--set", "hub.port=8090", "--print-config"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.config.path, Some(PathBuf::from("hub.toml")));
+        assert_eq!(cli.config.overrides, vec!["hub.web_port=3100", "hub.port=8090"]);
+        assert!(cli.config.print);
+        assert!(matches!(cli.command, Some(Command::Serve)));
+        assert!(Cli::try_parse_from(["pozor-dom-hub", "--set"]).is_err());
+    }
+
+    #[test]
+    fn test_changed_keys() {
+        let old = load(None, &[], &[]).unwrap();
+        let new = load(None, &[("POZOR_DOM_HUB_CLOUD_TOKEN", "hub-secret")], &["hub.mqtt.port=1884", "hub.notify_exec_allow=[\"/bin/true\"]"]).unwrap();
+        assert_eq!(old.changed_keys(&old.clone()), Vec::<String>::new());
+        assert_eq!(old.changed_keys(&new), vec!["hub.cloud_token", "hub.mqtt.port", "hub.notify_exec_allow"]);
+    }
+
+    #[test]
+    fn test_redacted_toml_round_trips() {
+        let config = load(None, &[("POZOR_DOM_HUB_TOKENS", "home:hub-secret"), ("POZOR_DOM_ADMIN_PASSWORD", "admin-secret")], &[]).unwrap();
+        let printed = config.to_redacted_toml();
+        assert!(!printed.contains("hub-secret") && !printed.contains("admin-secret"));
+        let reloaded = load(Some(&printed), &[], &[]).unwrap();
+        assert_eq!(reloaded.cloud.hub_tokens["home"], REDACTED);
+        assert_eq!(reloaded.hub.web_port, config.hub.web_port);
+    }
+}
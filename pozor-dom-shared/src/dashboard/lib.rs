@@ -6,24 +6,163 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 #[cfg(feature = "server")]
 use warp::Filter;
-#[cfg(feature = "server")]
+#[cfg(any(feature = "server", feature = "wasm"))]
 use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "server", feature = "wasm"))]
+use chrono::{DateTime, Utc};
+#[cfg(any(feature = "server", feature = "wasm"))]
+use std::collections::BTreeMap;
+#[cfg(any(feature = "server", feature = "wasm"))]
+use crate::device::{Capability, CapabilityValue, DeviceType, PresenceStatus};
 
 #[cfg(any(feature = "server", feature = "wasm"))]
-#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(from = "compat::RawDeviceTelemetry")]
 pub struct DeviceTelemetry {
     pub device_id: String,
     pub channel: String,
-    pub temperature: String,
-    pub humidity: String,
+    pub device_type: DeviceType,
+    pub capabilities: BTreeMap<Capability, CapabilityValue>,
     pub signal_strength: i32,
-    pub timestamp: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[cfg(any(feature = "server", feature = "wasm"))]
+impl DeviceTelemetry {
+    pub fn get(&self, capability: Capability) -> Option<&CapabilityValue> {
+        self.capabilities.get(&capability)
+    }
+
+    pub fn temperature(&self) -> Option<f64> {
+        self.get(Capability::Temperature).and_then(CapabilityValue::as_f64)
+    }
+
+    pub fn humidity(&self) -> Option<f64> {
+        self.get(Capability::Humidity).and_then(CapabilityValue::as_f64)
+    }
+}
+
+// Presence change broadcast by the hub; `last_seen` is the hub clock when the device was last heard from
+#[cfg(any(feature = "server", feature = "wasm"))]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DevicePresence {
+    pub device_id: String,
+    pub status: PresenceStatus,
+    pub last_seen: DateTime<Utc>,
+}
+
+// Entry of `/api/devices`: the latest reading plus presence
+#[cfg(any(feature = "server", feature = "wasm"))]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceSummary {
+    #[serde(flatten)]
+    pub telemetry: DeviceTelemetry,
+    #[serde(default)]
+    pub status: PresenceStatus,
+    pub last_seen: DateTime<Utc>,
+}
+
+// Deserializers that also accept the stringly-typed payloads older devices still publish
+#[cfg(any(feature = "server", feature = "wasm"))]
+pub mod compat {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, de::Error};
+    use std::collections::BTreeMap;
+    use crate::device::{Capability, CapabilityValue, DeviceType};
+    use super::DeviceTelemetry;
+
+    // Wire shape accepted for telemetry: the capability map, plus the flat
+    // `temperature`/`humidity` fields every device published before capabilities existed
+    #[derive(Deserialize)]
+    pub struct RawDeviceTelemetry {
+        device_id: String,
+        channel: String,
+        #[serde(default)]
+        device_type: DeviceType,
+        #[serde(default)]
+        capabilities: BTreeMap<Capability, CapabilityValue>,
+        #[serde(default, deserialize_with = "optional_number_or_string")]
+        temperature: Option<f64>,
+        #[serde(default, deserialize_with = "optional_number_or_string")]
+        humidity: Option<f64>,
+        signal_strength: i32,
+        #[serde(deserialize_with = "rfc3339_utc")]
+        timestamp: DateTime<Utc>,
+    }
+
+    impl From<RawDeviceTelemetry> for DeviceTelemetry {
+        fn from(raw: RawDeviceTelemetry) -> Self {
+            let mut capabilities = raw.capabilities;
+            if let Some(temperature) = raw.temperature {
+                capabilities.entry(Capability::Temperature).or_insert(CapabilityValue::Number(temperature));
+            }
+            if let Some(humidity) = raw.humidity {
+                capabilities.entry(Capability::Humidity).or_insert(CapabilityValue::Number(humidity));
+            }
+
+            DeviceTelemetry {
+                device_id: raw.device_id,
+                channel: raw.channel,
+                device_type: raw.device_type,
+                capabilities,
+                signal_strength: raw.signal_strength,
+                timestamp: raw.timestamp,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(f64),
+        String(String),
+    }
+
+    pub fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        match NumberOrString::deserialize(deserializer)? {
+            NumberOrString::Number(value) => Ok(value),
+            NumberOrString::String(raw) => raw.trim().parse().map_err(|e| D::Error::custom(format!("invalid number '{}': {}", raw, e))),
+        }
+    }
+
+    fn optional_number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+        number_or_string(deserializer).map(Some)
+    }
+
+    // Any RFC 3339 offset is accepted and normalized to UTC
+    pub fn rfc3339_utc<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        parse_rfc3339_utc(&raw).map_err(D::Error::custom)
+    }
+
+    pub fn parse_rfc3339_utc(raw: &str) -> Result<DateTime<Utc>, String> {
+        DateTime::parse_from_rfc3339(raw.trim())
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(|e| format!("invalid timestamp '{}': {}", raw, e))
+    }
+}
+
+// Assumed publish interval until a device has sent two readings
+#[cfg(feature = "server")]
+pub const DEFAULT_PUBLISH_INTERVAL_SECS: f64 = 5.0;
+#[cfg(feature = "server")]
+const MIN_PUBLISH_INTERVAL_SECS: f64 = 1.0;
+
+// Heartbeat bookkeeping for one device
+#[cfg(feature = "server")]
+#[derive(Debug, Clone)]
+pub struct PresenceEntry {
+    pub status: PresenceStatus,
+    pub last_seen: DateTime<Utc>,
+    // Smoothed gap between readings, used to decide when a device has gone quiet
+    pub interval_secs: f64,
 }
 
 #[cfg(feature = "server")]
 #[derive(Clone)]
 pub struct HubState {
     pub devices: HashMap<String, DeviceTelemetry>,
+    pub presence: HashMap<String, PresenceEntry>,
     pub messages: Vec<String>,
     pub cloud_enabled: bool,
     pub service_name: String,
@@ -34,16 +173,105 @@ impl HubState {
     pub fn new(service_name: &str) -> Self {
         Self {
             devices: HashMap::new(),
+            presence: HashMap::new(),
             messages: Vec::new(),
             cloud_enabled: false,
             service_name: service_name.to_string(),
         }
     }
 
+    // Stores the latest reading without touching presence (restored or relayed data)
     pub fn update_device(&mut self, telemetry: DeviceTelemetry) {
         self.devices.insert(telemetry.device_id.clone(), telemetry);
     }
 
+    // Reading received live from the device; Some when it just came online
+    pub fn record_telemetry(&mut self, telemetry: DeviceTelemetry, now: DateTime<Utc>) -> Option<DevicePresence> {
+        let device_id = telemetry.device_id.clone();
+        self.update_device(telemetry);
+        self.mark_seen(&device_id, now)
+    }
+
+    // Last-seen time known from a previous run; the device stays offline until it is heard from
+    pub fn restore_last_seen(&mut self, device_id: &str, last_seen: DateTime<Utc>) {
+        self.presence.entry(device_id.to_string()).or_insert(PresenceEntry {
+            status: PresenceStatus::Offline,
+            last_seen,
+            interval_secs: DEFAULT_PUBLISH_INTERVAL_SECS,
+        });
+    }
+
+    // Status announced by the device itself (birth message or MQTT last will); Some on a change
+    pub fn set_presence(&mut self, device_id: &str, status: PresenceStatus, now: DateTime<Utc>) -> Option<DevicePresence> {
+        match status {
+            PresenceStatus::Online => self.mark_seen(device_id, now),
+            PresenceStatus::Offline => {
+                let entry = self.presence.entry(device_id.to_string()).or_insert(PresenceEntry {
+                    status: PresenceStatus::Online,
+                    last_seen: now,
+                    interval_secs: DEFAULT_PUBLISH_INTERVAL_SECS,
+                });
+                if entry.status == PresenceStatus::Offline {
+                    return None;
+                }
+                entry.status = PresenceStatus::Offline;
+                Some(presence_update(device_id, entry))
+            }
+        }
+    }
+
+    fn mark_seen(&mut self, device_id: &str, now: DateTime<Utc>) -> Option<DevicePresence> {
+        let entry = self.presence.entry(device_id.to_string()).or_insert(PresenceEntry {
+            status: PresenceStatus::Offline,
+            last_seen: now,
+            interval_secs: DEFAULT_PUBLISH_INTERVAL_SECS,
+        });
+
+        if entry.status == PresenceStatus::Online {
+            let gap = (now - entry.last_seen).num_milliseconds() as f64 / 1000.0;
+            if gap > 0.0 {
+                entry.interval_secs = (entry.interval_secs * 0.7 + gap * 0.3).max(MIN_PUBLISH_INTERVAL_SECS);
+            }
+            entry.last_seen = now;
+            return None;
+        }
+
+        entry.status = PresenceStatus::Online;
+        entry.last_seen = now;
+        Some(presence_update(device_id, entry))
+    }
+
+    // Marks online devices that missed `missed_intervals` publish intervals as offline
+    pub fn expire_stale(&mut self, now: DateTime<Utc>, missed_intervals: u32) -> Vec<DevicePresence> {
+        let mut expired = Vec::new();
+        for (device_id, entry) in self.presence.iter_mut() {
+            let silent_secs = (now - entry.last_seen).num_milliseconds() as f64 / 1000.0;
+            if entry.status == PresenceStatus::Online && silent_secs > entry.interval_secs * missed_intervals as f64 {
+                entry.status = PresenceStatus::Offline;
+                expired.push(presence_update(device_id, entry));
+            }
+        }
+        expired
+    }
+
+    pub fn presence_of(&self, device_id: &str) -> Option<DevicePresence> {
+        self.presence.get(device_id).map(|entry| presence_update(device_id, entry))
+    }
+
+    pub fn device_summaries(&self) -> Vec<DeviceSummary> {
+        self.devices
+            .values()
+            .map(|telemetry| {
+                let presence = self.presence.get(&telemetry.device_id);
+                DeviceSummary {
+                    telemetry: telemetry.clone(),
+                    status: presence.map(|p| p.status).unwrap_or_default(),
+                    last_seen: presence.map(|p| p.last_seen).unwrap_or(telemetry.timestamp),
+                }
+            })
+            .collect()
+    }
+
     pub fn add_message(&mut self, message: String) {
         self.messages.push(message);
         // Keep only last 100 messages
@@ -57,6 +285,15 @@ impl HubState {
     }
 }
 
+#[cfg(feature = "server")]
+fn presence_update(device_id: &str, entry: &PresenceEntry) -> DevicePresence {
+    DevicePresence {
+        device_id: device_id.to_string(),
+        status: entry.status,
+        last_seen: entry.last_seen,
+    }
+}
+
 #[cfg(feature = "server")]
 pub async fn start_web_server(
     hub_state: Arc<Mutex<HubState>>,
@@ -117,8 +354,7 @@ async fn get_devices(
     hub_state: Arc<Mutex<HubState>>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let state = hub_state.lock().await;
-    let devices: Vec<&DeviceTelemetry> = state.devices.values().collect();
-    Ok(warp::reply::json(&devices))
+    Ok(warp::reply::json(&state.device_summaries()))
 }
 
 #[cfg(feature = "server")]
@@ -2,7 +2,7 @@
 pub mod lib;
 
 #[cfg(any(feature = "server", feature = "wasm"))]
-pub use lib::DeviceTelemetry;
+pub use lib::{DevicePresence, DeviceSummary, DeviceTelemetry};
 
 #[cfg(feature = "server")]
 pub mod yew_components;
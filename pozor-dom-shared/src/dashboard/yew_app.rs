@@ -11,12 +11,14 @@ use std::collections::HashMap;
 #[cfg(any(feature = "server", feature = "wasm"))]
 use crate::dashboard::yew_components::{Dashboard, DashboardContext, ServiceType, DashboardProps};
 #[cfg(any(feature = "server", feature = "wasm"))]
-use crate::dashboard::DeviceTelemetry;
+use crate::dashboard::DeviceSummary;
+#[cfg(any(feature = "server", feature = "wasm"))]
+use crate::envelope::{Envelope, Payload};
 
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ApiResponse {
-    pub devices: Vec<DeviceTelemetry>,
+    pub devices: Vec<DeviceSummary>,
     pub messages: Vec<String>,
     pub cloud_enabled: bool,
 }
@@ -24,8 +26,8 @@ pub struct ApiResponse {
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[function_component(YewDashboardApp)]
 pub fn yew_dashboard_app() -> Html {
-    let devices = use_state(|| HashMap::<String, DeviceTelemetry>::new());
-    let messages = use_state(|| Vec::<String>::new());
+    let devices = use_state(HashMap::<String, DeviceSummary>::new);
+    let messages = use_state(Vec::<String>::new);
     let cloud_enabled = use_state(|| false);
     let service_type = use_state(|| ServiceType::Hub);
     let version = use_state(|| 0);
@@ -65,7 +67,7 @@ pub fn yew_dashboard_app() -> Html {
 
             wasm_bindgen_futures::spawn_local(async move {
                 if let Ok(response) = fetch_data().await {
-                    devices.set(response.devices.into_iter().map(|d| (d.device_id.clone(), d)).collect());
+                    devices.set(response.devices.into_iter().map(|d| (d.telemetry.device_id.clone(), d)).collect());
                     messages.set(response.messages);
                     // Set cloud_enabled based on service type for initial state
                     let initial_cloud_enabled = matches!(*service_type, ServiceType::Cloud);
@@ -103,33 +105,18 @@ pub fn yew_dashboard_app() -> Html {
                     if let Ok(text) = e.data().dyn_into::<js_sys::JsString>() {
                         let message = text.as_string().unwrap_or_default();
 
-                        // Skip echo messages that start with component names
-                        if message.starts_with("Cloud received:") ||
-                           message.starts_with("Hub received:") ||
-                           message.starts_with("[CLOUD]") ||
-                           message.starts_with("[HUB]") ||
-                           message == "Welcome to Pozor-dom Hub!" ||
-                           message == "Welcome to Pozor-dom Cloud!" {
-                            // Still add to messages list for display
-                            messages.set({
-                                let mut msgs = (*messages).clone();
-                                msgs.push(message.clone());
-                                if msgs.len() > 100 {
-                                    msgs.remove(0);
-                                }
-                                msgs
-                            });
-                            return;
-                        }
+                        let envelope = match Envelope::from_json(&message) {
+                            Ok(envelope) => envelope,
+                            Err(_) => return,
+                        };
 
-                        // Try to parse as device telemetry
-                        if let Ok(_telemetry) = serde_json::from_str::<DeviceTelemetry>(&message) {
+                        if let Payload::Telemetry(_) | Payload::Presence(_) = &envelope.payload {
                             // Refetch all devices from API to ensure we have the latest state
                             let devices_clone = devices.clone();
                             let version_clone = version.clone();
                             wasm_bindgen_futures::spawn_local(async move {
                                 if let Ok(response) = fetch_devices().await {
-                                    devices_clone.set(response.into_iter().map(|d| (d.device_id.clone(), d)).collect());
+                                    devices_clone.set(response.into_iter().map(|d| (d.telemetry.device_id.clone(), d)).collect());
                                     version_clone.set(*version_clone + 1);
                                 }
                             });
@@ -138,7 +125,7 @@ pub fn yew_dashboard_app() -> Html {
                         // Add to messages list
                         messages.set({
                             let mut msgs = (*messages).clone();
-                            msgs.push(message.clone());
+                            msgs.push(envelope.to_string());
                             if msgs.len() > 100 {
                                 msgs.remove(0);
                             }
@@ -186,10 +173,8 @@ pub fn yew_dashboard_app() -> Html {
 
                 wasm_bindgen_futures::spawn_local(async move {
                     // Only update messages, don't touch devices (they're updated via WebSocket)
-                    if let Ok(response) = fetch_messages().await {
-                        if messages.is_empty() {
-                            messages.set(response);
-                        }
+                    if let Ok(response) = fetch_messages().await && messages.is_empty() {
+                        messages.set(response);
                     }
                 });
             });
@@ -254,7 +239,7 @@ async fn fetch_data() -> Result<ApiResponse, Box<dyn std::error::Error>> {
         .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
 
-    let devices: Vec<DeviceTelemetry> = devices_response.json().await
+    let devices: Vec<DeviceSummary> = devices_response.json().await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
     let messages: Vec<String> = messages_response.json().await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
@@ -270,14 +255,14 @@ async fn fetch_data() -> Result<ApiResponse, Box<dyn std::error::Error>> {
 }
 
 #[cfg(any(feature = "server", feature = "wasm"))]
-async fn fetch_devices() -> Result<Vec<DeviceTelemetry>, Box<dyn std::error::Error>> {
+async fn fetch_devices() -> Result<Vec<DeviceSummary>, Box<dyn std::error::Error>> {
     // Use relative URLs since API is served from the same server
     let devices_response = gloo_net::http::Request::get("/api/devices")
         .send()
         .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
 
-    let devices: Vec<DeviceTelemetry> = devices_response.json().await
+    let devices: Vec<DeviceSummary> = devices_response.json().await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
 
     Ok(devices)
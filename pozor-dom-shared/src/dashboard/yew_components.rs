@@ -7,12 +7,14 @@ use wasm_bindgen::JsCast;
 #[cfg(any(feature = "server", feature = "wasm"))]
 use std::collections::HashMap;
 #[cfg(any(feature = "server", feature = "wasm"))]
-use crate::dashboard::DeviceTelemetry;
+use crate::dashboard::DeviceSummary;
+#[cfg(any(feature = "server", feature = "wasm"))]
+use crate::envelope::{DeviceCommand, Envelope};
 
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[derive(Clone, PartialEq)]
 pub struct DashboardContext {
-    pub devices: HashMap<String, DeviceTelemetry>,
+    pub devices: HashMap<String, DeviceSummary>,
     pub messages: Vec<String>,
     pub cloud_enabled: bool,
     pub service_name: String,
@@ -138,7 +140,7 @@ struct CloudToggleProps {
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[function_component(DevicesGrid)]
 fn devices_grid(props: &DevicesGridProps) -> Html {
-    let devices: Vec<DeviceTelemetry> = props.devices.values().cloned().collect();
+    let devices: Vec<DeviceSummary> = props.devices.values().cloned().collect();
 
     if devices.is_empty() {
         return html! { <p>{"No devices connected"}</p> };
@@ -156,36 +158,42 @@ fn devices_grid(props: &DevicesGridProps) -> Html {
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[derive(Clone, PartialEq, Properties)]
 struct DevicesGridProps {
-    pub devices: HashMap<String, DeviceTelemetry>,
+    pub devices: HashMap<String, DeviceSummary>,
 }
 
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[function_component(DeviceCard)]
 fn device_card(props: &DeviceCardProps) -> Html {
-    let channel_class = format!("channel-{}", props.device.channel.to_lowercase());
+    let device = &props.device.telemetry;
+    let status = props.device.status;
+    let channel_class = format!("channel-{}", device.channel.to_lowercase());
 
     html! {
-        <div class="device-card">
-            <div class="device-id">{&props.device.device_id}</div>
-            <span class={classes!("device-channel", channel_class)}>{&props.device.channel}</span>
+        <div class={classes!("device-card", status.as_str())}>
+            <div class="device-id">{&device.device_id}</div>
+            <span class={classes!("device-channel", channel_class)}>{&device.channel}</span>
+            <span class={classes!("device-status", status.as_str())}>{status.as_str()}</span>
+
+            <div class="device-type">{device.device_type.as_str()}</div>
 
             <div class="device-metrics">
-                <div class="metric">
-                    <span class="metric-label">{"Temperature:"}</span>
-                    <span class={classes!("metric-value", "temperature")}>{props.device.temperature.clone() + "°C"}</span>
-                </div>
-                <div class="metric">
-                    <span class="metric-label">{"Humidity:"}</span>
-                    <span class={classes!("metric-value", "humidity")}>{props.device.humidity.clone() + "%"}</span>
-                </div>
+                {for device.capabilities.iter().map(|(capability, value)| html! {
+                    <div class="metric">
+                        <span class="metric-label">{format!("{}:", capability.label())}</span>
+                        <span class={classes!("metric-value", capability.as_str())}>{capability.format_value(value)}</span>
+                    </div>
+                })}
                 <div class="metric">
                     <span class="metric-label">{"Signal Strength:"}</span>
-                    <span class={classes!("metric-value", "signal")}>{props.device.signal_strength.to_string() + " dBm"}</span>
+                    <span class={classes!("metric-value", "signal")}>{device.signal_strength.to_string() + " dBm"}</span>
                 </div>
             </div>
 
             <div class="timestamp">
-                {"Last updated: "}{&props.device.timestamp}
+                {"Last updated: "}{device.timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string()}
+            </div>
+            <div class="timestamp">
+                {"Last seen: "}{props.device.last_seen.format("%Y-%m-%d %H:%M:%S UTC").to_string()}
             </div>
         </div>
     }
@@ -194,7 +202,7 @@ fn device_card(props: &DeviceCardProps) -> Html {
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[derive(Clone, PartialEq, Properties)]
 struct DeviceCardProps {
-    pub device: DeviceTelemetry,
+    pub device: DeviceSummary,
 }
 
 #[cfg(any(feature = "server", feature = "wasm"))]
@@ -227,9 +235,9 @@ struct MessagesListProps {
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[function_component(DeviceControls)]
 fn device_controls(props: &DeviceControlsProps) -> Html {
-    let devices: Vec<DeviceTelemetry> = props.devices.values().cloned().collect();
-    let selected_device = use_state(|| String::new());
-    let action_input = use_state(|| String::new());
+    let devices: Vec<DeviceSummary> = props.devices.values().cloned().collect();
+    let selected_device = use_state(String::new);
+    let action_input = use_state(String::new);
 
     let on_device_change = {
         let selected_device = selected_device.clone();
@@ -252,6 +260,7 @@ fn device_controls(props: &DeviceControlsProps) -> Html {
     };
 
     let on_submit = {
+        let devices_by_id = props.devices.clone();
         let selected_device = selected_device.clone();
         let action_input = action_input.clone();
         Callback::from(move |e: MouseEvent| {
@@ -260,30 +269,14 @@ fn device_controls(props: &DeviceControlsProps) -> Html {
             let action = (*action_input).clone();
 
             if !device_id.is_empty() && !action.is_empty() {
-                // Send command via WebSocket
-                if let Some(ws) = web_sys::window().and_then(|w| w.document()).and_then(|d| {
-                    d.get_element_by_id("websocket").and_then(|e| e.dyn_into::<WebSocket>().ok())
-                }) {
-                    let command = serde_json::json!({
-                        "type": "command",
-                        "device_id": device_id,
-                        "action": action,
-                        "timestamp": js_sys::Date::now().to_string()
-                    });
-
-                    if let Ok(msg) = serde_json::to_string(&command) {
-                        if let Err(_err) = ws.send_with_str(&msg) {
-                            // Log error if needed
-                        }
-                    }
-                }
-
+                send_device_command(&devices_by_id, &device_id, &action);
                 action_input.set(String::new());
             }
         })
     };
 
     let on_key_press = {
+        let devices_by_id = props.devices.clone();
         let selected_device = selected_device.clone();
         let action_input = action_input.clone();
         Callback::from(move |e: KeyboardEvent| {
@@ -293,24 +286,7 @@ fn device_controls(props: &DeviceControlsProps) -> Html {
                 let action = (*action_input).clone();
 
                 if !device_id.is_empty() && !action.is_empty() {
-                    // Send command via WebSocket
-                    if let Some(ws) = web_sys::window().and_then(|w| w.document()).and_then(|d| {
-                        d.get_element_by_id("websocket").and_then(|e| e.dyn_into::<WebSocket>().ok())
-                    }) {
-                        let command = serde_json::json!({
-                            "type": "command",
-                            "device_id": device_id,
-                            "action": action,
-                            "timestamp": js_sys::Date::now().to_string()
-                        });
-
-                        if let Ok(msg) = serde_json::to_string(&command) {
-                        if let Err(_err) = ws.send_with_str(&msg) {
-                            // Log error if needed
-                        }
-                        }
-                    }
-
+                    send_device_command(&devices_by_id, &device_id, &action);
                     action_input.set(String::new());
                 }
             }
@@ -324,8 +300,8 @@ fn device_controls(props: &DeviceControlsProps) -> Html {
                 <select id="device-select" onchange={on_device_change}>
                     <option value="">{"Choose a device..."}</option>
                     {for devices.iter().map(|device| html! {
-                        <option value={device.device_id.clone()}>
-                            {format!("{} ({})", device.device_id, device.channel)}
+                        <option value={device.telemetry.device_id.clone()}>
+                            {format!("{} ({}, {})", device.telemetry.device_id, device.telemetry.channel, device.status.as_str())}
                         </option>
                     })}
                 </select>
@@ -349,5 +325,27 @@ fn device_controls(props: &DeviceControlsProps) -> Html {
 #[cfg(any(feature = "server", feature = "wasm"))]
 #[derive(Clone, PartialEq, Properties)]
 struct DeviceControlsProps {
-    pub devices: HashMap<String, DeviceTelemetry>,
+    pub devices: HashMap<String, DeviceSummary>,
+}
+
+#[cfg(any(feature = "server", feature = "wasm"))]
+fn send_device_command(devices: &HashMap<String, DeviceSummary>, device_id: &str, action: &str) {
+    let channel = devices.get(device_id).map(|d| d.telemetry.channel.clone()).unwrap_or_default();
+
+    // Send command via WebSocket
+    if let Some(ws) = web_sys::window().and_then(|w| w.document()).and_then(|d| {
+        d.get_element_by_id("websocket").and_then(|e| e.dyn_into::<WebSocket>().ok())
+    }) {
+        let command = Envelope::command(DeviceCommand {
+            command_id: String::new(),
+            device_id: device_id.to_string(),
+            channel,
+            action: action.to_string(),
+            timestamp: js_sys::Date::new_0().to_iso_string().into(),
+        });
+
+        if let Err(_err) = ws.send_with_str(&command.to_json()) {
+            // Log error if needed
+        }
+    }
 }
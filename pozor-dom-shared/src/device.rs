@@ -0,0 +1,183 @@
+// Capability-based device model shared by the emulator, hub, cloud and dashboard
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceType {
+    #[default]
+    Sensor,
+    Light,
+    SmartPlug,
+    Thermostat,
+    ContactSensor,
+    MotionSensor,
+    Lock,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Temperature,
+    Humidity,
+    Switch,
+    Dimmer,
+    Contact,
+    Motion,
+    PowerMeter,
+    ThermostatSetpoint,
+    Lock,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum CapabilityValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+// Whether the hub currently hears from a device (MQTT last will / birth message or telemetry heartbeat)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    #[default]
+    Offline,
+}
+
+// Static description of a device: what it is and which capabilities it reports
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceDescriptor {
+    pub device_id: String,
+    pub channel: String,
+    pub device_type: DeviceType,
+    pub capabilities: Vec<Capability>,
+}
+
+impl DeviceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceType::Sensor => "sensor",
+            DeviceType::Light => "light",
+            DeviceType::SmartPlug => "smart_plug",
+            DeviceType::Thermostat => "thermostat",
+            DeviceType::ContactSensor => "contact_sensor",
+            DeviceType::MotionSensor => "motion_sensor",
+            DeviceType::Lock => "lock",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sensor" => Some(DeviceType::Sensor),
+            "light" => Some(DeviceType::Light),
+            "smart_plug" => Some(DeviceType::SmartPlug),
+            "thermostat" => Some(DeviceType::Thermostat),
+            "contact_sensor" => Some(DeviceType::ContactSensor),
+            "motion_sensor" => Some(DeviceType::MotionSensor),
+            "lock" => Some(DeviceType::Lock),
+            _ => None,
+        }
+    }
+}
+
+impl PresenceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresenceStatus::Online => "online",
+            PresenceStatus::Offline => "offline",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "online" => Some(PresenceStatus::Online),
+            "offline" => Some(PresenceStatus::Offline),
+            _ => None,
+        }
+    }
+}
+
+impl Capability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Temperature => "temperature",
+            Capability::Humidity => "humidity",
+            Capability::Switch => "switch",
+            Capability::Dimmer => "dimmer",
+            Capability::Contact => "contact",
+            Capability::Motion => "motion",
+            Capability::PowerMeter => "power_meter",
+            Capability::ThermostatSetpoint => "thermostat_setpoint",
+            Capability::Lock => "lock",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Capability::Temperature => "Temperature",
+            Capability::Humidity => "Humidity",
+            Capability::Switch => "Power",
+            Capability::Dimmer => "Brightness",
+            Capability::Contact => "Contact",
+            Capability::Motion => "Motion",
+            Capability::PowerMeter => "Power Usage",
+            Capability::ThermostatSetpoint => "Target",
+            Capability::Lock => "Lock",
+        }
+    }
+
+    // Human-readable state, e.g. "21.30°C", "On", "Open"
+    pub fn format_value(&self, value: &CapabilityValue) -> String {
+        match (self, value) {
+            (Capability::Temperature | Capability::ThermostatSetpoint, CapabilityValue::Number(v)) => format!("{:.2}°C", v),
+            (Capability::Humidity | Capability::Dimmer, CapabilityValue::Number(v)) => format!("{:.0}%", v),
+            (Capability::PowerMeter, CapabilityValue::Number(v)) => format!("{:.1} W", v),
+            (Capability::Switch, CapabilityValue::Bool(on)) => if *on { "On" } else { "Off" }.to_string(),
+            (Capability::Contact, CapabilityValue::Bool(open)) => if *open { "Open" } else { "Closed" }.to_string(),
+            (Capability::Motion, CapabilityValue::Bool(detected)) => if *detected { "Detected" } else { "Clear" }.to_string(),
+            (Capability::Lock, CapabilityValue::Bool(locked)) => if *locked { "Locked" } else { "Unlocked" }.to_string(),
+            (_, other) => other.to_string(),
+        }
+    }
+}
+
+impl CapabilityValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            CapabilityValue::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            CapabilityValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CapabilityValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CapabilityValue::Bool(value) => write!(f, "{}", value),
+            CapabilityValue::Number(value) => write!(f, "{:.2}", value),
+            CapabilityValue::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<bool> for CapabilityValue {
+    fn from(value: bool) -> Self {
+        CapabilityValue::Bool(value)
+    }
+}
+
+impl From<f64> for CapabilityValue {
+    fn from(value: f64) -> Self {
+        CapabilityValue::Number(value)
+    }
+}
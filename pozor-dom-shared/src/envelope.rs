@@ -0,0 +1,388 @@
+// Typed message envelope exchanged over every WebSocket link (cloud, hub, client, dashboard)
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::dashboard::{DevicePresence, DeviceTelemetry};
+
+// Bump when a change to the payload shapes is not backwards compatible
+pub const PROTOCOL_VERSION: u32 = 1;
+
+fn default_version() -> u32 {
+    PROTOCOL_VERSION
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Envelope {
+    // Frames sent before the envelope existed carry no version; treat them as current
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(flatten)]
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Payload {
+    Telemetry(DeviceTelemetry),
+    Command(DeviceCommand),
+    CommandAck(CommandAck),
+    Presence(DevicePresence),
+    // Client -> hub: run a scene stored on the hub
+    ActivateScene { name: String },
+    SceneResult(SceneResult),
+    Alert(AlertEvent),
+    // Hub -> clients: something a user should see, e.g. from an automation rule
+    Notification { source: String, message: String },
+    Chat { sender: String, text: String },
+    Echo { component: String, text: String },
+    Welcome { component: String },
+    Error { message: String },
+    PermissionDenied(PermissionDenied),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceCommand {
+    // Correlation id; the hub assigns one when the sender leaves it empty
+    #[serde(default)]
+    pub command_id: String,
+    pub device_id: String,
+    pub channel: String,
+    pub action: String,
+    #[serde(default)]
+    pub timestamp: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    Pending,
+    Succeeded,
+    Failed,
+    TimedOut,
+}
+
+// Hub -> WebSocket clients: progress of a command they (or someone else) issued
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CommandAck {
+    pub command_id: String,
+    pub device_id: String,
+    pub action: String,
+    pub status: CommandStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+// Hub -> the requesting client: the user's device access list does not allow this command
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PermissionDenied {
+    #[serde(default)]
+    pub command_id: String,
+    pub username: String,
+    pub device_id: String,
+    pub action: String,
+}
+
+// Hub -> clients: final status of every command of an activated scene
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SceneResult {
+    pub name: String,
+    pub success: bool,
+    pub outcomes: Vec<CommandAck>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    Temperature,
+    Humidity,
+    SignalStrength,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertCondition {
+    Above,
+    Below,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Raised,
+    Cleared,
+}
+
+// Hub -> clients: a telemetry value left (raised) or returned to (cleared) its configured band
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AlertEvent {
+    // Row id in the hub's alert history; 0 until stored
+    #[serde(default)]
+    pub id: i64,
+    pub device_id: String,
+    pub metric: AlertMetric,
+    pub condition: AlertCondition,
+    pub threshold: f64,
+    pub value: f64,
+    pub state: AlertState,
+    pub timestamp: DateTime<Utc>,
+}
+
+// Device -> hub over MQTT (`pozor-dom/device/{id}/command_result`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CommandResult {
+    pub command_id: String,
+    pub device_id: String,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl AlertMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertMetric::Temperature => "temperature",
+            AlertMetric::Humidity => "humidity",
+            AlertMetric::SignalStrength => "signal_strength",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "temperature" => Some(AlertMetric::Temperature),
+            "humidity" => Some(AlertMetric::Humidity),
+            "signal_strength" => Some(AlertMetric::SignalStrength),
+            _ => None,
+        }
+    }
+}
+
+impl AlertCondition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertCondition::Above => "above",
+            AlertCondition::Below => "below",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "above" => Some(AlertCondition::Above),
+            "below" => Some(AlertCondition::Below),
+            _ => None,
+        }
+    }
+}
+
+impl AlertState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertState::Raised => "raised",
+            AlertState::Cleared => "cleared",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "raised" => Some(AlertState::Raised),
+            "cleared" => Some(AlertState::Cleared),
+            _ => None,
+        }
+    }
+}
+
+impl CommandStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandStatus::Pending => "pending",
+            CommandStatus::Succeeded => "succeeded",
+            CommandStatus::Failed => "failed",
+            CommandStatus::TimedOut => "timed out",
+        }
+    }
+}
+
+impl CommandAck {
+    pub fn for_command(command: &DeviceCommand, status: CommandStatus, detail: Option<String>) -> Self {
+        Self {
+            command_id: command.command_id.clone(),
+            device_id: command.device_id.clone(),
+            action: command.action.clone(),
+            status,
+            detail,
+        }
+    }
+}
+
+impl Envelope {
+    pub fn new(payload: Payload) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            payload,
+        }
+    }
+
+    pub fn telemetry(telemetry: DeviceTelemetry) -> Self {
+        Self::new(Payload::Telemetry(telemetry))
+    }
+
+    pub fn command(command: DeviceCommand) -> Self {
+        Self::new(Payload::Command(command))
+    }
+
+    pub fn command_ack(command: &DeviceCommand, status: CommandStatus, detail: Option<String>) -> Self {
+        Self::new(Payload::CommandAck(CommandAck::for_command(command, status, detail)))
+    }
+
+    pub fn presence(presence: DevicePresence) -> Self {
+        Self::new(Payload::Presence(presence))
+    }
+
+    pub fn activate_scene(name: &str) -> Self {
+        Self::new(Payload::ActivateScene { name: name.to_string() })
+    }
+
+    pub fn scene_result(result: SceneResult) -> Self {
+        Self::new(Payload::SceneResult(result))
+    }
+
+    pub fn alert(alert: AlertEvent) -> Self {
+        Self::new(Payload::Alert(alert))
+    }
+
+    pub fn notification(source: &str, message: &str) -> Self {
+        Self::new(Payload::Notification {
+            source: source.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    pub fn chat(sender: &str, text: &str) -> Self {
+        Self::new(Payload::Chat {
+            sender: sender.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    pub fn echo(component: &str, text: &str) -> Self {
+        Self::new(Payload::Echo {
+            component: component.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    pub fn welcome(component: &str) -> Self {
+        Self::new(Payload::Welcome {
+            component: component.to_string(),
+        })
+    }
+
+    pub fn error(message: &str) -> Self {
+        Self::new(Payload::Error {
+            message: message.to_string(),
+        })
+    }
+
+    pub fn permission_denied(username: &str, command: &DeviceCommand) -> Self {
+        Self::new(Payload::PermissionDenied(PermissionDenied {
+            command_id: command.command_id.clone(),
+            username: username.to_string(),
+            device_id: command.device_id.clone(),
+            action: command.action.clone(),
+        }))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+
+    pub fn to_json(&self) -> String {
+        // Serializing plain data with string keys cannot fail
+        serde_json::to_string(self).expect("envelope serialization")
+    }
+
+    // Replies generated by a component; these must never be echoed again
+    pub fn is_response(&self) -> bool {
+        matches!(
+            self.payload,
+            Payload::Echo { .. } | Payload::CommandAck(_) | Payload::SceneResult(_) | Payload::Error { .. } | Payload::PermissionDenied(_)
+        )
+    }
+
+    pub fn is_supported_version(&self) -> bool {
+        self.version <= PROTOCOL_VERSION
+    }
+}
+
+impl From<Payload> for Envelope {
+    fn from(payload: Payload) -> Self {
+        Self::new(payload)
+    }
+}
+
+// Human-readable form used by the terminal client and the dashboard message log
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.payload {
+            Payload::Telemetry(t) => {
+                let states: Vec<String> = t
+                    .capabilities
+                    .iter()
+                    .map(|(capability, value)| format!("{}={}", capability.as_str(), capability.format_value(value)))
+                    .collect();
+                write!(
+                    f,
+                    "Telemetry {} [{}] ({}): {}, signal={} dBm",
+                    t.device_id,
+                    t.device_type.as_str(),
+                    t.channel,
+                    states.join(", "),
+                    t.signal_strength
+                )
+            }
+            Payload::Command(c) => write!(f, "Command {} -> {} ({})", c.action, c.device_id, c.channel),
+            Payload::CommandAck(ack) => {
+                write!(f, "Command {} -> {} [{}]: {}", ack.action, ack.device_id, ack.command_id, ack.status.as_str())?;
+                match &ack.detail {
+                    Some(detail) => write!(f, " ({})", detail),
+                    None => Ok(()),
+                }
+            }
+            Payload::Presence(p) => write!(
+                f,
+                "Device {} is {} (last seen {})",
+                p.device_id,
+                p.status.as_str(),
+                p.last_seen.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            Payload::ActivateScene { name } => write!(f, "Activate scene {}", name),
+            Payload::SceneResult(result) => {
+                let succeeded = result.outcomes.iter().filter(|ack| ack.status == CommandStatus::Succeeded).count();
+                write!(f, "Scene {}: {}/{} commands succeeded", result.name, succeeded, result.outcomes.len())?;
+                for ack in result.outcomes.iter().filter(|ack| ack.status != CommandStatus::Succeeded) {
+                    write!(f, "; {} {}", ack.device_id, ack.status.as_str())?;
+                }
+                Ok(())
+            }
+            Payload::Alert(alert) => write!(
+                f,
+                "Alert {}: {} {} {} {} (value {})",
+                alert.state.as_str(),
+                alert.device_id,
+                alert.metric.as_str(),
+                alert.condition.as_str(),
+                alert.threshold,
+                alert.value
+            ),
+            Payload::Notification { source, message } => write!(f, "Notification from {}: {}", source, message),
+            Payload::Chat { sender, text } => write!(f, "[{}] {}", sender, text),
+            Payload::Echo { component, text } => write!(f, "{} received: {}", component, text),
+            Payload::Welcome { component } => write!(f, "Welcome to Pozor-dom {}!", component),
+            Payload::Error { message } => write!(f, "Error: {}", message),
+            Payload::PermissionDenied(denied) => {
+                write!(f, "Permission denied: {} may not send {} to {}", denied.username, denied.action, denied.device_id)
+            }
+        }
+    }
+}
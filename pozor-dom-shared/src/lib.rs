@@ -1,12 +1,17 @@
 // Shared constants and utilities for Pozor-dom
 
+#[cfg(feature = "server")]
+pub mod config;
 pub mod dashboard;
+pub mod device;
+#[cfg(any(feature = "server", feature = "wasm"))]
+pub mod envelope;
+#[cfg(feature = "server")]
+pub mod tls;
 
 #[cfg(feature = "server")]
 use std::collections::HashMap;
 #[cfg(feature = "server")]
-use std::net::SocketAddr;
-#[cfg(feature = "server")]
 use std::sync::{Arc, Mutex};
 #[cfg(feature = "server")]
 use tokio::io::AsyncBufReadExt;
@@ -21,6 +26,15 @@ pub const HUB_PORT: u16 = 8082;
 pub const DEFAULT_CLOUD_HOST: &str = "127.0.0.1"; // Change to public IP for production
 pub const DEFAULT_HUB_HOST: &str = "127.0.0.1";
 
+// Telemetry history
+pub const DEFAULT_RAW_RETENTION_HOURS: u64 = 24;
+
+// A device is marked offline after this many publish intervals without telemetry
+pub const DEFAULT_PRESENCE_MISSED_INTERVALS: u32 = 3;
+
+// How long the hub waits for its tasks to wind down after Ctrl+C or SIGTERM
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+
 // URL builders
 pub fn cloud_url() -> String {
     format!("ws://{}:{}", DEFAULT_CLOUD_HOST, CLOUD_PORT)
@@ -38,48 +52,50 @@ pub fn hub_url() -> String {
 #[cfg(feature = "server")]
 pub type Tx = mpsc::UnboundedSender<tokio_tungstenite::tungstenite::protocol::Message>;
 #[cfg(feature = "server")]
-pub type PeerMap = Arc<Mutex<HashMap<SocketAddr, Tx>>>;
+pub type PeerMap<K> = Arc<Mutex<HashMap<K, Tx>>>;
 
 // Message handling utilities
 #[cfg(feature = "server")]
 pub mod messages {
     use tokio_tungstenite::tungstenite::protocol::Message;
+    use crate::envelope::{Envelope, PROTOCOL_VERSION};
 
-    pub const HUB_BROADCAST_PREFIX: &str = "HUB_BROADCAST:";
-    pub const CLOUD_PREFIX: &str = "[CLOUD]";
-    pub const HUB_PREFIX: &str = "[HUB]";
-    pub const RECEIVED_SUFFIX: &str = "received:";
+    pub const CLOUD_COMPONENT: &str = "Cloud";
+    pub const HUB_COMPONENT: &str = "Hub";
 
-    pub fn is_hub_broadcast(msg: &str) -> bool {
-        msg.starts_with(HUB_BROADCAST_PREFIX)
+    pub fn to_ws_message(envelope: &Envelope) -> Message {
+        Message::Text(envelope.to_json().into())
     }
 
-    pub fn extract_hub_broadcast(msg: &str) -> &str {
-        msg.strip_prefix(HUB_BROADCAST_PREFIX).unwrap_or(msg).trim()
+    pub fn parse_envelope(msg: &str) -> Result<Envelope, serde_json::Error> {
+        Envelope::from_json(msg)
     }
 
-    pub fn create_hub_broadcast(content: &str) -> String {
-        format!("{} {}", HUB_BROADCAST_PREFIX, content)
-    }
-
-    pub fn is_response_message(msg: &str) -> bool {
-        msg.contains(RECEIVED_SUFFIX)
+    // Error reply for a frame from a newer protocol version than this component speaks
+    pub fn version_error(envelope: &Envelope) -> Option<Message> {
+        (!envelope.is_supported_version()).then(|| {
+            create_error_message(&format!("Unsupported protocol version {} (up to {} is supported)", envelope.version, PROTOCOL_VERSION))
+        })
     }
 
     pub fn create_cloud_message(content: &str) -> Message {
-        Message::Text(format!("{} {}", CLOUD_PREFIX, content).into())
+        to_ws_message(&Envelope::chat(CLOUD_COMPONENT, content))
     }
 
     pub fn create_hub_message(content: &str) -> Message {
-        Message::Text(format!("{} {}", HUB_PREFIX, content).into())
+        to_ws_message(&Envelope::chat(HUB_COMPONENT, content))
     }
 
     pub fn create_welcome_message(component: &str) -> Message {
-        Message::Text(format!("Welcome to Pozor-dom {}!", component).into())
+        to_ws_message(&Envelope::welcome(component))
     }
 
     pub fn create_echo_message(component: &str, content: &str) -> Message {
-        Message::Text(format!("{} received: {}", component, content).into())
+        to_ws_message(&Envelope::echo(component, content))
+    }
+
+    pub fn create_error_message(message: &str) -> Message {
+        to_ws_message(&Envelope::error(message))
     }
 }
 
@@ -89,10 +105,25 @@ pub mod connection {
     use super::*;
     use std::io;
 
-    pub fn create_peer_map() -> PeerMap {
+    pub fn create_peer_map<K>() -> PeerMap<K> {
         Arc::new(Mutex::new(HashMap::new()))
     }
 
+    // WebSocket request carrying `token` as a bearer token for the cloud relay
+    pub fn authorized_request(
+        url: &str,
+        token: Option<&str>,
+    ) -> Result<tokio_tungstenite::tungstenite::handshake::client::Request, Box<dyn std::error::Error + Send + Sync>> {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+        use tokio_tungstenite::tungstenite::http::header::AUTHORIZATION;
+
+        let mut request = url.into_client_request()?;
+        if let Some(token) = token {
+            request.headers_mut().insert(AUTHORIZATION, format!("Bearer {}", token).parse()?);
+        }
+        Ok(request)
+    }
+
     pub async fn setup_stdin_channel() -> Result<mpsc::UnboundedReceiver<String>, io::Error> {
         let (tx, rx) = mpsc::unbounded_channel();
 
@@ -103,10 +134,8 @@ pub mod connection {
 
             while let Ok(Some(line)) = lines.next_line().await {
                 let trimmed = line.trim();
-                if !trimmed.is_empty() {
-                    if tx.send(trimmed.to_string()).is_err() {
-                        break;
-                    }
+                if !trimmed.is_empty() && tx.send(trimmed.to_string()).is_err() {
+                    break;
                 }
             }
         });
@@ -115,45 +144,20 @@ pub mod connection {
     }
 }
 
-// Configuration
-pub mod config {
-    use std::env;
-
-    pub fn get_cloud_host() -> String {
-        env::var("POZOR_DOM_CLOUD_HOST").unwrap_or(super::DEFAULT_CLOUD_HOST.to_string())
-    }
-
-    pub fn get_hub_host() -> String {
-        env::var("POZOR_DOM_HUB_HOST").unwrap_or(super::DEFAULT_HUB_HOST.to_string())
-    }
-
-    pub fn get_cloud_port() -> u16 {
-        env::var("POZOR_DOM_CLOUD_PORT")
-            .ok()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(super::CLOUD_PORT)
-    }
-
-    pub fn get_hub_port() -> u16 {
-        env::var("POZOR_DOM_HUB_PORT")
-            .ok()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(super::HUB_PORT)
-    }
-}
-
 // Logging utilities
 pub mod logging {
-    pub fn log_connection(addr: &std::net::SocketAddr, component: &str) {
-        println!("New {} connection from: {}", component, addr);
+    use std::fmt::Display;
+
+    pub fn log_connection(peer: &dyn Display, component: &str) {
+        println!("New {} connection from: {}", component, peer);
     }
 
-    pub fn log_disconnection(addr: &std::net::SocketAddr, component: &str) {
-        println!("{} connection closed: {}", component, addr);
+    pub fn log_disconnection(peer: &dyn Display, component: &str) {
+        println!("{} connection closed: {}", component, peer);
     }
 
-    pub fn log_message_received(addr: &std::net::SocketAddr, msg: &str) {
-        println!("Received from {}: {}", addr, msg);
+    pub fn log_message_received(peer: &dyn Display, msg: &str) {
+        println!("Received from {}: {}", peer, msg);
     }
 
     pub fn log_broadcast(content: &str, source: &str) {
@@ -186,9 +190,132 @@ mod tests {
     }
 
     #[test]
-    fn test_hub_broadcast() {
-        let msg = messages::create_hub_broadcast("test message");
-        assert!(messages::is_hub_broadcast(&msg));
-        assert_eq!(messages::extract_hub_broadcast(&msg), "test message");
+    fn test_envelope_roundtrip() {
+        let envelope = envelope::Envelope::chat(messages::HUB_COMPONENT, "test message");
+        let parsed = messages::parse_envelope(&envelope.to_json()).unwrap();
+        assert_eq!(parsed, envelope);
+        assert_eq!(parsed.version, envelope::PROTOCOL_VERSION);
+        assert_eq!(parsed.to_string(), "[Hub] test message");
+    }
+
+    #[test]
+    fn test_legacy_command_parses_as_envelope() {
+        let raw = r#"{"type":"command","device_id":"device-wifi-001","channel":"WiFi","action":"get_temperature"}"#;
+        let parsed = messages::parse_envelope(raw).unwrap();
+        assert_eq!(parsed.version, envelope::PROTOCOL_VERSION);
+        match parsed.payload {
+            envelope::Payload::Command(command) => {
+                assert_eq!(command.device_id, "device-wifi-001");
+                assert_eq!(command.action, "get_temperature");
+            }
+            other => panic!("expected command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_command_ack_from_result() {
+        let command = envelope::DeviceCommand {
+            command_id: "cmd-1".to_string(),
+            device_id: "device-wifi-001".to_string(),
+            channel: "WiFi".to_string(),
+            action: "turn_on".to_string(),
+            timestamp: String::new(),
+        };
+        let ack = envelope::Envelope::command_ack(&command, envelope::CommandStatus::TimedOut, None);
+        let json = ack.to_json();
+        assert!(json.contains(r#""type":"command_ack""#));
+        assert!(json.contains(r#""status":"timed_out""#));
+        assert!(ack.is_response());
+
+        let result: envelope::CommandResult =
+            serde_json::from_str(r#"{"command_id":"cmd-1","device_id":"device-wifi-001","success":false}"#).unwrap();
+        assert!(!result.success);
+        assert_eq!(result.detail, None);
+    }
+
+    #[test]
+    fn test_scene_envelopes() {
+        let activate = messages::parse_envelope(r#"{"type":"activate_scene","name":"night-mode"}"#).unwrap();
+        assert_eq!(activate, envelope::Envelope::activate_scene("night-mode"));
+
+        let command = envelope::DeviceCommand {
+            command_id: "cmd-2".to_string(),
+            device_id: "lock-front-001".to_string(),
+            channel: "ZigBee".to_string(),
+            action: "lock".to_string(),
+            timestamp: String::new(),
+        };
+        let result = envelope::Envelope::scene_result(envelope::SceneResult {
+            name: "night-mode".to_string(),
+            success: false,
+            outcomes: vec![envelope::CommandAck::for_command(&command, envelope::CommandStatus::TimedOut, None)],
+        });
+        assert!(result.is_response());
+        assert_eq!(result.to_string(), "Scene night-mode: 0/1 commands succeeded; lock-front-001 timed out");
+        assert_eq!(messages::parse_envelope(&result.to_json()).unwrap(), result);
+    }
+
+    #[test]
+    fn test_permission_denied_envelope() {
+        let command = envelope::DeviceCommand {
+            command_id: "cmd-3".to_string(),
+            device_id: "lock-front-001".to_string(),
+            channel: "ZigBee".to_string(),
+            action: "unlock".to_string(),
+            timestamp: String::new(),
+        };
+        let denied = envelope::Envelope::permission_denied("kid", &command);
+        assert!(denied.is_response());
+        assert_eq!(denied.to_string(), "Permission denied: kid may not send unlock to lock-front-001");
+        assert!(denied.to_json().contains(r#""type":"permission_denied""#));
+        assert_eq!(messages::parse_envelope(&denied.to_json()).unwrap(), denied);
+    }
+
+    #[test]
+    fn test_parse_token_list() {
+        assert_eq!(
+            config::parse_token_list("home:s3cret, cottage : other:part ,broken,:empty-name,"),
+            vec![
+                ("home".to_string(), "s3cret".to_string()),
+                ("cottage".to_string(), "other:part".to_string()),
+            ]
+        );
+        assert!(config::parse_token_list("").is_empty());
+    }
+
+    #[test]
+    fn test_alert_envelope() {
+        let alert = envelope::Envelope::alert(envelope::AlertEvent {
+            id: 7,
+            device_id: "device-wifi-001".to_string(),
+            metric: envelope::AlertMetric::Temperature,
+            condition: envelope::AlertCondition::Above,
+            threshold: 28.0,
+            value: 29.5,
+            state: envelope::AlertState::Raised,
+            timestamp: chrono::Utc::now(),
+        });
+        assert_eq!(alert.to_string(), "Alert raised: device-wifi-001 temperature above 28 (value 29.5)");
+        assert_eq!(messages::parse_envelope(&alert.to_json()).unwrap(), alert);
+        assert!(alert.to_json().contains(r#""type":"alert""#));
+    }
+
+    #[test]
+    fn test_newer_versions_get_an_error() {
+        let current = envelope::Envelope::chat("Client", "hello");
+        assert!(messages::version_error(&current).is_none());
+        let newer = messages::parse_envelope(&format!(r#"{{"version":{},"type":"chat","sender":"Client","text":"hello"}}"#, envelope::PROTOCOL_VERSION + 1)).unwrap();
+        let reply = messages::version_error(&newer).expect("newer frames are refused");
+        match messages::parse_envelope(reply.to_text().unwrap()).unwrap().payload {
+            envelope::Payload::Error { message } => assert!(message.contains("Unsupported protocol version")),
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_echo_is_response() {
+        let echo = envelope::Envelope::echo(messages::CLOUD_COMPONENT, "hello");
+        assert!(echo.is_response());
+        assert!(!envelope::Envelope::chat("Client", "hello").is_response());
     }
 }
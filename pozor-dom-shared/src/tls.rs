@@ -0,0 +1,131 @@
+// rustls-based TLS for the WebSocket and dashboard servers, and for wss:// connections to them
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_tungstenite::tungstenite::handshake::client::{Request, Response};
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+use crate::config::TlsConfig;
+
+pub use tokio_rustls::TlsAcceptor;
+
+pub type ClientStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Certificate chain and private key a server presents, as PEM files
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsFiles {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+impl TlsFiles {
+    // None means plain ws:// and http://; the config is validated to have both files or neither
+    pub fn from_config(tls: &TlsConfig) -> Option<Self> {
+        Some(Self { cert_path: tls.cert.clone()?, key_path: tls.key.clone()? })
+    }
+
+    pub fn acceptor(&self) -> Result<TlsAcceptor, Box<dyn Error + Send + Sync>> {
+        let certs = load_certs(&self.cert_path)?;
+        let key_file = File::open(&self.key_path).map_err(|e| format!("{}: {}", self.key_path, e))?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))?
+            .ok_or_else(|| format!("{}: no private key found", self.key_path))?;
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error + Send + Sync>> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file)).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path).into());
+    }
+    Ok(certs)
+}
+
+// Trusts the public web roots, plus the CA in `ca_path` for servers with self-signed certificates
+pub fn client_config(ca_path: Option<&str>) -> Result<Arc<ClientConfig>, Box<dyn Error + Send + Sync>> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(path) = ca_path {
+        for cert in load_certs(path)? {
+            roots.add(cert)?;
+        }
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+// Opens a ws:// or wss:// connection; wss:// also trusts the CA in `ca_path` (tls.ca)
+pub async fn connect(request: Request, ca_path: Option<&str>) -> Result<(ClientStream, Response), Box<dyn Error + Send + Sync>> {
+    let connector = Connector::Rustls(client_config(ca_path)?);
+    Ok(tokio_tungstenite::connect_async_tls_with_config(request, None, false, Some(connector)).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    // Self-signed test CA and a localhost certificate issued by it
+    fn fixture(name: &str) -> String {
+        format!("{}/../tests/certs/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    async fn echo_server() -> u16 {
+        let files = TlsFiles { cert_path: fixture("localhost.pem"), key_path: fixture("localhost-key.pem") };
+        let acceptor = files.acceptor().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    continue;
+                };
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                if let Some(Ok(message)) = ws.next().await {
+                    ws.send(message).await.unwrap();
+                }
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_wss_round_trip_with_test_ca() {
+        let port = echo_server().await;
+        let request = format!("wss://localhost:{}/", port).into_client_request().unwrap();
+        let connector = Connector::Rustls(client_config(Some(&fixture("ca.pem"))).unwrap());
+        let (mut ws, _) = tokio_tungstenite::connect_async_tls_with_config(request, None, false, Some(connector)).await.unwrap();
+
+        ws.send(Message::Text("ping".into())).await.unwrap();
+        assert_eq!(ws.next().await.unwrap().unwrap(), Message::Text("ping".into()));
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_certificate_is_rejected() {
+        let port = echo_server().await;
+        let request = format!("wss://localhost:{}/", port).into_client_request().unwrap();
+        let connector = Connector::Rustls(client_config(None).unwrap());
+        assert!(tokio_tungstenite::connect_async_tls_with_config(request, None, false, Some(connector)).await.is_err());
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let files = TlsFiles { cert_path: fixture("missing.pem"), key_path: fixture("localhost-key.pem") };
+        assert!(files.acceptor().err().is_some_and(|e| e.to_string().contains("missing.pem")));
+        assert!(client_config(Some(&fixture("missing.pem"))).is_err());
+    }
+}
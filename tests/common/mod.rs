@@ -1,91 +1,148 @@
-use rumqttc::{AsyncClient, MqttOptions, QoS};
-use std::collections::HashMap;
-use std::sync::Arc;
+use pozor_dom_shared::device::{Capability, CapabilityValue};
+use std::collections::BTreeMap;
 use std::time::Duration;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
+use tokio::sync::OnceCell;
 use tokio_tungstenite::connect_async;
 
-pub struct TestMqttClient {
-    pub client: AsyncClient,
-    pub received_messages: Arc<Mutex<HashMap<String, Vec<String>>>>,
-}
-
-impl TestMqttClient {
-    pub async fn new(client_id: &str) -> Self {
-        let mut options = MqttOptions::new(client_id, "127.0.0.1", 1883);
-        options.set_keep_alive(Duration::from_secs(5));
-
-        let (client, _) = AsyncClient::new(options, 10);
-        let received_messages = Arc::new(Mutex::new(HashMap::new()));
-
-        Self {
-            client,
-            received_messages,
-        }
-    }
-
-    pub async fn subscribe(&self, topic: &str) {
-        self.client.subscribe(topic, QoS::AtLeastOnce).await.unwrap();
-    }
-
-    pub async fn get_messages(&self, topic: &str) -> Vec<String> {
-        let messages = self.received_messages.lock().await;
-        messages.get(topic).cloned().unwrap_or_default()
-    }
+pub async fn setup_ws_client(url: &str) -> tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>> {
+    let (ws_stream, _) = connect_async(url)
+        .await
+        .expect("Failed to connect to WebSocket");
 
-    pub async fn publish(&self, topic: &str, payload: &str) {
-        self.client
-            .publish(topic, QoS::AtLeastOnce, false, payload.as_bytes())
-            .await
-            .unwrap();
-    }
+    ws_stream
 }
 
-pub async fn setup_mqtt_client() -> TestMqttClient {
-    TestMqttClient::new("test-client").await
+// The hub must have been started with the same POZOR_DOM_ADMIN_PASSWORD on an empty database
+pub fn admin_password() -> String {
+    std::env::var("POZOR_DOM_ADMIN_PASSWORD").unwrap_or_else(|_| "pozor-dom-test".to_string())
 }
 
-pub async fn setup_ws_client(url: &str) -> tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>> {
-    let (ws_stream, _) = connect_async(url)
+pub async fn login(username: &str, password: &str) -> Result<reqwest::Response, reqwest::Error> {
+    reqwest::Client::new()
+        .post("http://localhost:3000/api/login")
+        .json(&serde_json::json!({ "username": username, "password": password }))
+        .timeout(Duration::from_secs(5))
+        .send()
         .await
-        .expect("Failed to connect to WebSocket");
+}
 
-    ws_stream
+pub async fn login_token(username: &str, password: &str) -> String {
+    let response = login(username, password).await.expect("Failed to log in");
+    assert_eq!(response.status(), 200, "Login as {} should succeed", username);
+    let session: serde_json::Value = response.json().await.expect("Login should return JSON");
+    session["token"].as_str().expect("Login should return a token").to_string()
 }
 
-pub async fn wait_for_mqtt_message(
-    mqtt_client: &TestMqttClient,
-    topic: &str,
-    timeout_duration: Duration,
-) -> Option<String> {
-    let start_time = std::time::Instant::now();
+// Admin session shared by every test
+pub async fn admin_token() -> &'static str {
+    static TOKEN: OnceCell<String> = OnceCell::const_new();
+    TOKEN.get_or_init(|| async { login_token("admin", &admin_password()).await }).await
+}
 
-    while start_time.elapsed() < timeout_duration {
-        let messages = mqtt_client.get_messages(topic).await;
-        if !messages.is_empty() {
-            return Some(messages.last().unwrap().clone());
-        }
-        sleep(Duration::from_millis(100)).await;
-    }
+// The cloud must have been started with POZOR_DOM_USER_TOKENS="alice:alice-secret", POZOR_DOM_USER_HUBS="alice:home"
+// and POZOR_DOM_HUB_API_URLS="home:http://localhost:3000"
+pub fn cloud_user_token() -> String {
+    std::env::var("POZOR_DOM_TEST_USER_TOKEN").unwrap_or_else(|_| "alice-secret".to_string())
+}
 
-    None
+// A hub API request through the cloud: the relay token for the cloud, the admin session cookie for the hub
+pub async fn make_cloud_proxy_request(path: &str) -> Result<reqwest::Response, reqwest::Error> {
+    reqwest::Client::new()
+        .get(format!("http://localhost:8080/api/hubs/home/proxy/{}", path))
+        .bearer_auth(cloud_user_token())
+        .header("cookie", format!("pozor_session={}", admin_token().await))
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
 }
 
 pub async fn make_http_request(url: &str) -> Result<reqwest::Response, reqwest::Error> {
+    make_http_request_as(url, admin_token().await).await
+}
+
+pub async fn make_http_request_as(url: &str, token: &str) -> Result<reqwest::Response, reqwest::Error> {
     reqwest::Client::new()
         .get(url)
+        .bearer_auth(token)
         .timeout(Duration::from_secs(5))
         .send()
         .await
 }
 
 pub async fn make_http_post(url: &str, body: &str) -> Result<reqwest::Response, reqwest::Error> {
+    make_http_post_as(url, body, admin_token().await).await
+}
+
+pub async fn make_http_post_as(url: &str, body: &str, token: &str) -> Result<reqwest::Response, reqwest::Error> {
     reqwest::Client::new()
         .post(url)
+        .bearer_auth(token)
+        .header("content-type", "application/json")
+        .body(body.to_string())
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+}
+
+pub async fn make_http_put(url: &str, body: &str) -> Result<reqwest::Response, reqwest::Error> {
+    reqwest::Client::new()
+        .put(url)
+        .bearer_auth(admin_token().await)
         .header("content-type", "application/json")
         .body(body.to_string())
         .timeout(Duration::from_secs(5))
         .send()
         .await
 }
+
+pub async fn make_http_delete(url: &str) -> Result<reqwest::Response, reqwest::Error> {
+    reqwest::Client::new()
+        .delete(url)
+        .bearer_auth(admin_token().await)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+}
+
+// Steps shared by the CRUD endpoint tests (rules, scenes, schedules, alert thresholds)
+
+// POST to a collection; returns the created item and its URL
+pub async fn create_item(collection: &str, item: &serde_json::Value) -> (serde_json::Value, String) {
+    let response = make_http_post(collection, &item.to_string()).await.expect("Failed to make HTTP request");
+    assert_eq!(response.status(), 201, "Creating in {} should return 201 Created", collection);
+    let created: serde_json::Value = response.json().await.expect("Should return the created item");
+    let id = created["id"].as_i64().expect("Created item should have an id");
+    (created, format!("{}/{}", collection, id))
+}
+
+// PUT to an item URL; returns the item as stored
+pub async fn put_item(url: &str, item: &serde_json::Value) -> serde_json::Value {
+    let response = make_http_put(url, &item.to_string()).await.expect("Failed to make HTTP request");
+    assert_eq!(response.status(), 200, "Saving {} should return 200 OK", url);
+    response.json().await.expect("Should return the saved item")
+}
+
+pub async fn assert_listed(collection: &str, key: &str, value: &serde_json::Value) {
+    let response = make_http_request(collection).await.expect("Failed to make HTTP request");
+    let items: Vec<serde_json::Value> = response.json().await.expect("Should return a JSON array");
+    assert!(items.iter().any(|item| item[key] == *value), "{} should list {} {}", collection, key, value);
+}
+
+// DELETE an item; a second DELETE must find nothing
+pub async fn delete_item(url: &str) {
+    let response = make_http_delete(url).await.expect("Failed to make HTTP request");
+    assert_eq!(response.status(), 200, "Deleting {} should return 200 OK", url);
+    let response = make_http_delete(url).await.expect("Failed to make HTTP request");
+    assert_eq!(response.status(), 404, "{} should be gone", url);
+}
+
+pub fn assert_rejected(response: Result<reqwest::Response, reqwest::Error>, what: &str) {
+    assert_eq!(response.expect("Failed to make HTTP request").status(), 400, "{} should be rejected", what);
+}
+
+pub fn climate_capabilities(temperature: f64, humidity: f64) -> BTreeMap<Capability, CapabilityValue> {
+    BTreeMap::from([
+        (Capability::Temperature, CapabilityValue::Number(temperature)),
+        (Capability::Humidity, CapabilityValue::Number(humidity)),
+    ])
+}